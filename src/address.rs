@@ -0,0 +1,352 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use crate::error::AddressParseError;
+
+/// IPv4 address owned by value, unlike the `protocols::ipv4::Ipv4Address` view into a packet
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Default, Copy, Clone)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    /// The limited broadcast address
+    pub const BROADCAST: Ipv4Address = Ipv4Address([0xFF; 4]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Ipv4Address {
+        Ipv4Address([a, b, c, d])
+    }
+
+    pub fn octets(&self) -> [u8; 4] {
+        self.0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Whether this is the limited broadcast address. Directed broadcasts depend on the subnet,
+    /// see `Ipv4Cidr::broadcast`.
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [0xFF; 4]
+    }
+
+    /// 224.0.0.0/4
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+
+    /// 169.254.0.0/16 (RFC 3927)
+    pub fn is_link_local(&self) -> bool {
+        self.0[0] == 169 && self.0[1] == 254
+    }
+
+    /// 127.0.0.0/8
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    /// Whether the address can name a single host: not unspecified, broadcast, loopback or multicast
+    pub fn is_unicast(&self) -> bool {
+        !(self.is_unspecified() || self.is_broadcast() || self.is_loopback() || self.is_multicast())
+    }
+}
+
+impl From<[u8; 4]> for Ipv4Address {
+    fn from(octets: [u8; 4]) -> Ipv4Address {
+        Ipv4Address(octets)
+    }
+}
+
+impl From<Ipv4Address> for [u8; 4] {
+    fn from(address: Ipv4Address) -> [u8; 4] {
+        address.0
+    }
+}
+
+impl From<Ipv4Addr> for Ipv4Address {
+    fn from(address: Ipv4Addr) -> Ipv4Address {
+        Ipv4Address(address.octets())
+    }
+}
+
+impl From<Ipv4Address> for Ipv4Addr {
+    fn from(address: Ipv4Address) -> Ipv4Addr {
+        Ipv4Addr::from(address.0)
+    }
+}
+
+impl FromStr for Ipv4Address {
+    type Err = AddressParseError;
+
+    /// Dotted decimal, as accepted by `std::net::Ipv4Addr`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Ipv4Addr>()
+            .map(Ipv4Address::from)
+            .map_err(|_| AddressParseError::BadIpv4Address)
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Ethernet hardware address owned by value, unlike the `protocols::ethernet::MacAddress` view into a frame
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Default, Copy, Clone)]
+pub struct EthernetAddress(pub [u8; 6]);
+
+impl EthernetAddress {
+    pub const BROADCAST: EthernetAddress = EthernetAddress([0xFF; 6]);
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [0xFF; 6]
+    }
+
+    /// Group addresses have the lowest bit of the first octet set, broadcast included
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    /// Locally administered addresses have the second lowest bit of the first octet set,
+    /// the ones assigned by the manufacturer don't
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+}
+
+impl From<[u8; 6]> for EthernetAddress {
+    fn from(octets: [u8; 6]) -> EthernetAddress {
+        EthernetAddress(octets)
+    }
+}
+
+impl From<EthernetAddress> for [u8; 6] {
+    fn from(address: EthernetAddress) -> [u8; 6] {
+        address.0
+    }
+}
+
+impl FromStr for EthernetAddress {
+    type Err = AddressParseError;
+
+    /// Six hexadecimal octets separated by colons or hyphens
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let separator = if s.contains('-') { '-' } else { ':' };
+        let mut octets = [0_u8; 6];
+        let mut parts = s.split(separator);
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or(AddressParseError::BadEthernetAddress)?;
+            if part.is_empty() || part.len() > 2 {
+                return Err(AddressParseError::BadEthernetAddress);
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| AddressParseError::BadEthernetAddress)?;
+        }
+        if parts.next().is_some() {
+            return Err(AddressParseError::BadEthernetAddress);
+        }
+        Ok(EthernetAddress(octets))
+    }
+}
+
+impl fmt::Display for EthernetAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        )
+    }
+}
+
+impl fmt::Debug for EthernetAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// IPv4 address with the prefix length of its subnet, in CIDR notation `a.b.c.d/len`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Default, Copy, Clone)]
+pub struct Ipv4Cidr {
+    address: Ipv4Address,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Panics if `prefix_len` is over 32
+    pub fn new(address: Ipv4Address, prefix_len: u8) -> Ipv4Cidr {
+        assert!(prefix_len <= 32);
+        Ipv4Cidr { address, prefix_len }
+    }
+
+    pub fn address(&self) -> Ipv4Address {
+        self.address
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn netmask(&self) -> Ipv4Address {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+        Ipv4Address(mask.to_be_bytes())
+    }
+
+    /// The subnet itself, with the host bits cleared
+    pub fn network(&self) -> Ipv4Cidr {
+        let mask = u32::from_be_bytes(self.netmask().0);
+        Ipv4Cidr::new(Ipv4Address((u32::from_be_bytes(self.address.0) & mask).to_be_bytes()), self.prefix_len)
+    }
+
+    /// Directed broadcast address of the subnet, `None` for /31 and /32 which have none
+    pub fn broadcast(&self) -> Option<Ipv4Address> {
+        if self.prefix_len >= 31 {
+            return None;
+        }
+        let mask = u32::from_be_bytes(self.netmask().0);
+        Some(Ipv4Address((u32::from_be_bytes(self.address.0) | !mask).to_be_bytes()))
+    }
+
+    /// Whether `address` is inside the subnet
+    pub fn contains(&self, address: &Ipv4Address) -> bool {
+        let mask = u32::from_be_bytes(self.netmask().0);
+        u32::from_be_bytes(self.address.0) & mask == u32::from_be_bytes(address.0) & mask
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = s.split_once('/').ok_or(AddressParseError::BadPrefixLength)?;
+        let address = address.parse()?;
+        let prefix_len = prefix_len.parse().ok()
+            .filter(|len| *len <= 32)
+            .ok_or(AddressParseError::BadPrefixLength)?;
+        Ok(Ipv4Cidr::new(address, prefix_len))
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl fmt::Debug for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Ipv4Cidr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_addresses_parse_and_display_dotted_decimal() {
+        assert_eq!("192.168.1.10".parse(), Ok(Ipv4Address::new(192, 168, 1, 10)));
+        assert_eq!(Ipv4Address::new(10, 0, 0, 255).to_string(), "10.0.0.255");
+        assert_eq!(format!("{:?}", Ipv4Address::BROADCAST), "255.255.255.255");
+        for bad in &["", "1.2.3", "1.2.3.4.5", "256.0.0.1", "1.2.3.4/8", " 1.2.3.4"] {
+            assert_eq!(bad.parse::<Ipv4Address>(), Err(AddressParseError::BadIpv4Address), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn ipv4_address_classes() {
+        assert!(Ipv4Address::UNSPECIFIED.is_unspecified());
+        assert!(Ipv4Address::new(224, 0, 0, 1).is_multicast());
+        assert!(Ipv4Address::new(239, 255, 255, 255).is_multicast());
+        assert!(!Ipv4Address::new(240, 0, 0, 1).is_multicast());
+        assert!(Ipv4Address::new(169, 254, 3, 4).is_link_local());
+        assert!(Ipv4Address::new(127, 0, 0, 1).is_loopback());
+        assert!(Ipv4Address::new(10, 0, 0, 1).is_unicast());
+        for address in &[Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, Ipv4Address::new(127, 0, 0, 1), Ipv4Address::new(224, 0, 0, 1)] {
+            assert!(!address.is_unicast(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn ethernet_addresses_parse_with_either_separator() {
+        let address = EthernetAddress([0x02, 0xDE, 0xAD, 0x00, 0xBE, 0xEF]);
+        assert_eq!("02:de:ad:00:be:ef".parse(), Ok(address));
+        assert_eq!("02-DE-AD-0-BE-EF".parse(), Ok(address));
+        assert_eq!(address.to_string(), "02:de:ad:00:be:ef");
+        assert!(address.is_local() && address.is_unicast());
+        assert!(EthernetAddress::BROADCAST.is_broadcast() && EthernetAddress::BROADCAST.is_multicast());
+        for bad in &["", "02:de:ad:00:be", "02:de:ad:00:be:ef:01", "02:de:ad:00:be:fg", "02:de:ad:000:be:ef", "02:de:ad::be:ef", "02:de-ad:00:be:ef"] {
+            assert_eq!(bad.parse::<EthernetAddress>(), Err(AddressParseError::BadEthernetAddress), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn cidrs_parse_and_display() {
+        let parsed = cidr("192.168.1.10/24");
+        assert_eq!(parsed.address(), Ipv4Address::new(192, 168, 1, 10));
+        assert_eq!(parsed.prefix_len(), 24);
+        assert_eq!(parsed.to_string(), "192.168.1.10/24");
+        assert_eq!("192.168.1.10".parse::<Ipv4Cidr>(), Err(AddressParseError::BadPrefixLength));
+        assert_eq!("192.168.1.10/33".parse::<Ipv4Cidr>(), Err(AddressParseError::BadPrefixLength));
+        assert_eq!("192.168.1.10/x".parse::<Ipv4Cidr>(), Err(AddressParseError::BadPrefixLength));
+        assert_eq!("192.168.1/24".parse::<Ipv4Cidr>(), Err(AddressParseError::BadIpv4Address));
+    }
+
+    #[test]
+    fn netmasks_networks_and_broadcasts() {
+        let subnet = cidr("192.168.1.10/24");
+        assert_eq!(subnet.netmask(), Ipv4Address::new(255, 255, 255, 0));
+        assert_eq!(subnet.network(), cidr("192.168.1.0/24"));
+        assert_eq!(subnet.broadcast(), Some(Ipv4Address::new(192, 168, 1, 255)));
+        assert!(subnet.contains(&Ipv4Address::new(192, 168, 1, 200)));
+        assert!(!subnet.contains(&Ipv4Address::new(192, 168, 2, 10)));
+
+        assert_eq!(cidr("10.1.2.3/20").netmask(), Ipv4Address::new(255, 255, 240, 0));
+        assert_eq!(cidr("10.1.2.3/20").broadcast(), Some(Ipv4Address::new(10, 1, 15, 255)));
+    }
+
+    #[test]
+    fn the_whole_internet_and_single_hosts() {
+        let everything = cidr("10.1.2.3/0");
+        assert_eq!(everything.netmask(), Ipv4Address::UNSPECIFIED);
+        assert_eq!(everything.network(), cidr("0.0.0.0/0"));
+        assert_eq!(everything.broadcast(), Some(Ipv4Address::BROADCAST));
+        assert!(everything.contains(&Ipv4Address::new(203, 0, 113, 1)));
+
+        let host = cidr("10.1.2.3/32");
+        assert_eq!(host.netmask(), Ipv4Address::BROADCAST);
+        assert_eq!(host.network(), host);
+        assert_eq!(host.broadcast(), None);
+        assert!(host.contains(&Ipv4Address::new(10, 1, 2, 3)));
+        assert!(!host.contains(&Ipv4Address::new(10, 1, 2, 4)));
+
+        assert_eq!(cidr("10.1.2.3/31").broadcast(), None);
+    }
+}
@@ -0,0 +1,66 @@
+pub mod loopback;
+pub mod pipe;
+pub mod tap;
+
+use std::time::Duration;
+
+use crate::error::Error;
+
+pub use loopback::Loopback;
+pub use pipe::Pipe;
+pub use tap::TapDevice;
+
+/// Link layer framing used by a device
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Medium {
+    /// Frames carry an Ethernet header
+    Ethernet,
+    /// Frames are bare IP packets
+    Ip,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceCapabilities {
+    /// Largest IP packet the device can carry, excluding any link layer header
+    pub mtu: usize,
+    pub medium: Medium,
+    /// The device has already verified the checksums of received packets
+    pub rx_checksum_offload: bool,
+}
+
+impl DeviceCapabilities {
+    /// Largest frame that is passed through `receive`/`transmit`
+    pub fn max_frame_size(&self) -> usize {
+        match self.medium {
+            Medium::Ethernet => self.mtu + 14,
+            Medium::Ip => self.mtu,
+        }
+    }
+}
+
+impl Default for DeviceCapabilities {
+    fn default() -> Self {
+        DeviceCapabilities {
+            mtu: 1500,
+            medium: Medium::Ethernet,
+            rx_checksum_offload: false,
+        }
+    }
+}
+
+pub trait Device {
+    /// Receive a single frame into `buffer` and return its length.
+    /// A length of zero means that no frame was available.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Error>;
+
+    /// Transmit a single frame, returning the number of bytes sent
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize, Error>;
+
+    fn capabilities(&self) -> DeviceCapabilities;
+
+    /// Block until a frame may be available or `timeout` has passed.
+    /// Devices that never block return immediately.
+    fn wait(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+}
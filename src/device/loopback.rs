@@ -0,0 +1,59 @@
+use std::collections::VecDeque;
+
+use crate::device::{Device, DeviceCapabilities};
+use crate::error::Error;
+
+/// In-memory device that receives every frame it transmits
+#[derive(Debug, Default)]
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+    capabilities: DeviceCapabilities,
+}
+
+impl Loopback {
+    pub fn new(capabilities: DeviceCapabilities) -> Loopback {
+        Loopback {
+            queue: VecDeque::new(),
+            capabilities,
+        }
+    }
+
+    /// Number of frames waiting to be received
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl Device for Loopback {
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self.queue.pop_front() {
+            Some(frame) => copy_frame(&frame, buffer),
+            None => Ok(0),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        check_frame_size(frame, &self.capabilities)?;
+        self.queue.push_back(frame.to_vec());
+        Ok(frame.len())
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.capabilities
+    }
+}
+
+pub(crate) fn copy_frame(frame: &[u8], buffer: &mut [u8]) -> Result<usize, Error> {
+    if frame.len() > buffer.len() {
+        return Err(Error::BufferTooSmall);
+    }
+    buffer[..frame.len()].copy_from_slice(frame);
+    Ok(frame.len())
+}
+
+pub(crate) fn check_frame_size(frame: &[u8], capabilities: &DeviceCapabilities) -> Result<(), Error> {
+    if frame.len() > capabilities.max_frame_size() {
+        return Err(Error::FrameTooLarge);
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::device::{Device, DeviceCapabilities};
+use crate::device::loopback::{check_frame_size, copy_frame};
+use crate::error::Error;
+
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// One end of an in-memory point-to-point link, see [`pair`]
+#[derive(Debug)]
+pub struct Pipe {
+    rx: Queue,
+    tx: Queue,
+    capabilities: DeviceCapabilities,
+}
+
+/// Create two connected devices. Frames transmitted on one end are received on the other.
+pub fn pair(capabilities: DeviceCapabilities) -> (Pipe, Pipe) {
+    let a_to_b: Queue = Default::default();
+    let b_to_a: Queue = Default::default();
+
+    (
+        Pipe { rx: b_to_a.clone(), tx: a_to_b.clone(), capabilities },
+        Pipe { rx: a_to_b, tx: b_to_a, capabilities },
+    )
+}
+
+impl Pipe {
+    /// Number of frames waiting to be received on this end
+    pub fn pending(&self) -> usize {
+        self.rx.lock().unwrap().len()
+    }
+}
+
+impl Device for Pipe {
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self.rx.lock().unwrap().pop_front() {
+            Some(frame) => copy_frame(&frame, buffer),
+            None => Ok(0),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        check_frame_size(frame, &self.capabilities)?;
+        self.tx.lock().unwrap().push_back(frame.to_vec());
+        Ok(frame.len())
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.capabilities
+    }
+}
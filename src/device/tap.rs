@@ -0,0 +1,103 @@
+use std::io::ErrorKind;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use tun_tap::Iface;
+use network_bridge::{interface_id,add_interface_to_bridge};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{socket, AddressFamily, SockType, SockFlag};
+use netdevice::{set_flags, get_flags, IFF_UP};
+
+use crate::device::{Device, DeviceCapabilities};
+use crate::error::Error;
+
+fn set_if_up(interface: &Iface) -> Result<(), Error> {
+    let socket = socket(AddressFamily::Unix, SockType::Stream, SockFlag::empty(), None)?;
+
+    let mut flags = get_flags(socket, interface.name())?;
+    flags |= IFF_UP;
+    set_flags(socket, interface.name(), &flags)?;
+
+    Ok(())
+
+}
+
+fn set_non_blocking(interface: &Iface) -> Result<(), Error> {
+    fcntl(interface.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok(())
+}
+
+/// Name of the TAP interface created by `setup`
+pub const DEFAULT_INTERFACE_NAME: &str = "rs-test-if";
+
+fn new_interface(name: &str) -> Result<Iface, std::io::Error> {
+    Iface::without_packet_info(name, tun_tap::Mode::Tap)
+}
+
+pub fn setup(bridge_name: &str) -> TapDevice {
+    setup_named(bridge_name, DEFAULT_INTERFACE_NAME)
+}
+
+/// Create a TAP interface called `interface_name` and attach it to the bridge
+pub fn setup_named(bridge_name: &str, interface_name: &str) -> TapDevice {
+    let iface = new_interface(interface_name).unwrap();
+    let iface_id = interface_id(iface.name()).unwrap();
+    add_interface_to_bridge(iface_id, bridge_name).unwrap();
+    set_if_up(&iface).unwrap();
+    set_non_blocking(&iface).unwrap();
+
+    TapDevice {
+        iface,
+        capabilities: DeviceCapabilities::default(),
+    }
+}
+
+pub struct TapDevice {
+    iface: Iface,
+    capabilities: DeviceCapabilities,
+}
+
+impl TapDevice {
+    pub fn name(&self) -> &str {
+        self.iface.name()
+    }
+}
+
+/// Block until any of the devices has a frame to receive or the timeout passes
+pub fn wait_any(devices: &[&TapDevice], timeout: Option<Duration>) -> Result<(), Error> {
+    let mut fds = devices.iter()
+        .map(|device| PollFd::new(device.iface.as_raw_fd(), PollFlags::POLLIN))
+        .collect::<Vec<_>>();
+    poll(&mut fds, poll_timeout(timeout))?;
+    Ok(())
+}
+
+fn poll_timeout(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    }
+}
+
+impl Device for TapDevice {
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self.iface.recv(buffer) {
+            Ok(size) => Ok(size),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        Ok(self.iface.send(frame)?)
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.capabilities
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        wait_any(&[self], timeout)
+    }
+}
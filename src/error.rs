@@ -2,6 +2,9 @@
 pub enum Error {
     IoError(std::io::Error),
     NixError(nix::Error),
+    ParseError(ParseError),
+    BufferTooSmall,
+    FrameTooLarge,
 }
 
 impl From<std::io::Error> for Error {
@@ -14,4 +17,42 @@ impl From<nix::Error> for Error {
     fn from(err: nix::Error) -> Error {
         Error::NixError(err)
     }
-}
\ No newline at end of file
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::ParseError(err)
+    }
+}
+
+/// Reasons for rejecting a received packet
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ParseError {
+    /// The buffer ended before the packet did
+    Truncated,
+    /// An address field had an unexpected length
+    BadAddressLength,
+    /// IP version field was not 4, or GRE version field not 0
+    BadVersion(u8),
+    /// GRE routing, strict source route or recursion control bits were set (RFC 2784 2.3)
+    BadFlags(u8),
+    /// IPv4 header length was below the minimum or didn't match the buffer
+    BadIhl(u8),
+    /// IPv4 total length was shorter than the header
+    BadLength(u16),
+    /// An IPv4 option of this kind had an invalid length or pointer
+    BadOption(u8),
+    /// TCP data offset was below the minimum
+    BadDataOffset(u8),
+}
+
+/// Reasons for rejecting an address written out as text
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AddressParseError {
+    /// Not four decimal octets separated by dots
+    BadIpv4Address,
+    /// Not six hexadecimal octets separated by colons or hyphens
+    BadEthernetAddress,
+    /// The prefix length after the slash was missing or over 32
+    BadPrefixLength,
+}
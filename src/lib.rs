@@ -0,0 +1,5 @@
+pub mod address;
+pub mod device;
+pub mod error;
+pub mod net;
+pub mod protocols;
@@ -1,50 +1,245 @@
-mod error;
-mod tap;
-mod net;
-pub mod protocols;
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
 
-use std::{env};
+use rs_network_stack::address::{EthernetAddress, Ipv4Address};
+use rs_network_stack::device::{tap, Device, DeviceCapabilities, Pipe, TapDevice};
+use rs_network_stack::error::Error;
+use rs_network_stack::net::{config, tunnel, Interface, InterfaceAddress, InterfaceConfig, Router, TunnelConfig, TunnelKind};
+
+/// What an interface is attached to
+enum Link {
+    /// A new TAP device in the bridge
+    Bridge(String),
+    /// A tunnel carried through the other interfaces
+    Tunnel(TunnelConfig),
+}
+
+/// Device of an interface, TAP devices and tunnels being polled by the same router
+enum LinkDevice {
+    Tap(TapDevice),
+    Tunnel(Pipe),
+}
+
+impl Device for LinkDevice {
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            LinkDevice::Tap(device) => device.receive(buffer),
+            LinkDevice::Tunnel(device) => device.receive(buffer),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        match self {
+            LinkDevice::Tap(device) => device.transmit(frame),
+            LinkDevice::Tunnel(device) => device.transmit(frame),
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self {
+            LinkDevice::Tap(device) => device.capabilities(),
+            LinkDevice::Tunnel(device) => device.capabilities(),
+        }
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        match self {
+            LinkDevice::Tap(device) => device.wait(timeout),
+            LinkDevice::Tunnel(device) => device.wait(timeout),
+        }
+    }
+}
+
+/// One interface with the options given after its bridge name or tunnel
+struct InterfaceArgs {
+    link: Link,
+    config: InterfaceConfig,
+    /// Static routes as (prefix, gateway), the gateway being reachable through this interface
+    routes: Vec<(InterfaceAddress, [u8; 4])>,
+    /// Multicast groups to join
+    groups: Vec<[u8; 4]>,
+}
 
 struct Args {
-    bridge_name: String,
+    interfaces: Vec<InterfaceArgs>,
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--forward] <interface> [options] [<interface> [options]]...\n\
+         Interfaces are a bridge name or --tunnel <gre|gretap|ipip> <local ip> <remote ip>.\n\
+         Options apply to the interface before them:\n\
+         [--mac <mac>] [--address <ip>/<prefix length>]... [--mtu <mtu>] [--gateway <ip>] \
+         [--route <ip>/<prefix length> <gateway>]... [--no-dad] [--proxy-arp <ip>/<prefix length>]... \
+         [--masquerade] [--broadcast-echo] [--join <group>]... [--key <GRE key>]",
+        program
+    );
+    process::exit(1);
+}
+
+fn parse_mac(value: &str) -> Option<[u8; 6]> {
+    value.parse::<EthernetAddress>().ok().map(|mac| mac.octets())
+}
+
+fn parse_ipv4(value: &str) -> Option<[u8; 4]> {
+    value.parse::<Ipv4Address>().ok().map(|ip| ip.octets())
+}
+
+fn parse_address(value: &str) -> Option<InterfaceAddress> {
+    value.parse().ok()
+}
+
+/// MTUs below what every IPv4 link has to carry are refused
+fn parse_mtu(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&mtu| mtu >= config::MIN_MTU)
 }
 
 fn parse_args() -> Args {
-    let mut args = env::args().collect::<Vec<String>>();
+    let mut args = env::args();
+    let program = args.next().unwrap();
+
+    let mut interfaces: Vec<InterfaceArgs> = Vec::new();
+    let mut addresses: Vec<Vec<InterfaceAddress>> = Vec::new();
+    let mut forward = false;
+
+    while let Some(arg) = args.next() {
+        if arg == "--forward" {
+            forward = true;
+            continue;
+        }
+        let link = if arg == "--tunnel" {
+            let kind = match args.next().as_deref() {
+                Some("gre") => TunnelKind::Gre,
+                Some("gretap") => TunnelKind::GreTap,
+                Some("ipip") => TunnelKind::IpIp,
+                _ => usage(&program),
+            };
+            let local = args.next().and_then(|value| parse_ipv4(&value)).unwrap_or_else(|| usage(&program));
+            let remote = args.next().and_then(|value| parse_ipv4(&value)).unwrap_or_else(|| usage(&program));
+            Some(Link::Tunnel(TunnelConfig::new(kind, local, remote)))
+        } else if !arg.starts_with("--") {
+            Some(Link::Bridge(arg.clone()))
+        } else {
+            None
+        };
+        if let Some(link) = link {
+            let mut config = InterfaceConfig::default();
+            // Keep the hardware addresses apart when nothing else is given
+            config.mac[5] = config.mac[5].wrapping_add(interfaces.len() as u8);
+            interfaces.push(InterfaceArgs { link, config, routes: Vec::new(), groups: Vec::new() });
+            addresses.push(Vec::new());
+            continue;
+        }
 
-    if args.len() != 2 {
-        panic!(format!("Usage: {} <bridge name>", args.get(0).unwrap()));
+        let interface = interfaces.last_mut().unwrap_or_else(|| usage(&program));
+        let addresses = addresses.last_mut().unwrap();
+        let config = &mut interface.config;
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "--mac" => config.mac = parse_mac(&value()).unwrap_or_else(|| usage(&program)),
+            "--address" => addresses.push(parse_address(&value()).unwrap_or_else(|| usage(&program))),
+            "--mtu" => config.mtu = parse_mtu(&value()).unwrap_or_else(|| usage(&program)),
+            "--gateway" => config.gateway = Some(parse_ipv4(&value()).unwrap_or_else(|| usage(&program))),
+            "--route" => {
+                let prefix = parse_address(&value()).unwrap_or_else(|| usage(&program));
+                let gateway = parse_ipv4(&value()).unwrap_or_else(|| usage(&program));
+                interface.routes.push((prefix, gateway));
+            },
+            "--no-dad" => config.duplicate_address_detection = false,
+            "--proxy-arp" => config.proxy_arp.push(parse_address(&value()).unwrap_or_else(|| usage(&program))),
+            "--masquerade" => config.masquerade = true,
+            "--broadcast-echo" => config.answer_broadcast_echo = true,
+            "--join" => interface.groups.push(parse_ipv4(&value()).unwrap_or_else(|| usage(&program))),
+            "--key" => match &mut interface.link {
+                Link::Tunnel(tunnel) if tunnel.kind != TunnelKind::IpIp => {
+                    tunnel.key = Some(value().parse().unwrap_or_else(|_| usage(&program)))
+                },
+                _ => usage(&program),
+            },
+            _ => usage(&program),
+        }
     }
 
-    let bridge_name = args.remove(1);
+    if interfaces.is_empty() {
+        usage(&program);
+    }
 
-    Args {
-        bridge_name
+    for (interface, addresses) in interfaces.iter_mut().zip(addresses) {
+        if !addresses.is_empty() {
+            interface.config.addresses = addresses;
+        }
+        interface.config.forwarding = forward;
     }
+
+    Args { interfaces }
 }
 
 fn main() {
 
     let args = parse_args();
 
-    println!("Using bridge {}", &args.bridge_name);
+    let mut router = Router::new();
+    let mut frame_size = 0;
+    let mut taps = 0;
 
-    let iface = tap::setup(&args.bridge_name);
+    // Tunnels leave room for their encapsulation on the smallest of the links they may go through
+    let underlay_mtu = args.interfaces.iter()
+        .filter(|interface| matches!(interface.link, Link::Bridge(_)))
+        .map(|interface| interface.config.mtu)
+        .min()
+        .unwrap_or(InterfaceConfig::default().mtu);
 
-    const MTU: usize = 1500;
+    for interface in args.interfaces {
+        let (device, tunnel) = match interface.link {
+            Link::Bridge(bridge_name) => {
+                let name = match taps {
+                    0 => String::from(tap::DEFAULT_INTERFACE_NAME),
+                    _ => format!("{}{}", tap::DEFAULT_INTERFACE_NAME, taps),
+                };
+                taps += 1;
+                println!("Using bridge {} for {}", &bridge_name, name);
+                (LinkDevice::Tap(tap::setup_named(&bridge_name, &name)), None)
+            },
+            Link::Tunnel(config) => {
+                println!("Using {:?} tunnel from {:?} to {:?}", config.kind, config.local, config.remote);
+                let (device, tunnel) = tunnel::pair(config, underlay_mtu);
+                (LinkDevice::Tunnel(device), Some(tunnel))
+            },
+        };
+        println!("{:x?}", &interface.config);
+        frame_size = frame_size.max(device.capabilities().max_frame_size());
 
-    let mut rx_buffer = [0 as u8; MTU];
-    let mut tx_buffer = [0 as u8; MTU];
+        let mut net_interface = Interface::new(device, interface.config);
+        for group in interface.groups {
+            if !net_interface.join_multicast_group(group, Instant::now()) {
+                println!("Not joining {:?}, it isn't a multicast group", group);
+            }
+        }
 
+        let index = match tunnel {
+            Some(tunnel) => router.add_tunnel(net_interface, tunnel),
+            None => router.add_interface(net_interface),
+        };
+        for (prefix, gateway) in interface.routes {
+            router.routes_mut().add(prefix, Some(gateway), index, 0);
+        }
+    }
 
-    let send = |tx_buffer: &[u8], len: usize| {
-        println!("Sending {} bytes", len);
-        let sent = iface.send(&tx_buffer[..len]);
-        println!("Sent: {}", sent.unwrap());
-    };
+    let mut rx_buffer = vec![0_u8; frame_size];
+    let mut tx_buffer = vec![0_u8; frame_size];
 
     loop {
-        let size = iface.recv(&mut rx_buffer).unwrap();
-        net::update(&mut rx_buffer[..size], &mut tx_buffer, send);
+        let received = router.poll(Instant::now(), &mut rx_buffer, &mut tx_buffer).unwrap();
+        if !received {
+            let delay = router.poll_delay(Instant::now());
+            let devices = router.interfaces().iter()
+                .filter_map(|interface| match interface.device() {
+                    LinkDevice::Tap(device) => Some(device),
+                    LinkDevice::Tunnel(_) => None,
+                })
+                .collect::<Vec<_>>();
+            tap::wait_any(&devices, delay).unwrap();
+        }
     }
 }
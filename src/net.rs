@@ -1,48 +1,616 @@
-//use heapless::FnvIndexMap;
-//use heapless::consts::*;
+pub mod aging;
+pub mod config;
+pub mod dad;
+pub mod filter;
+pub mod fragmentation;
+pub mod multicast;
+pub mod nat;
+pub mod neighbor;
+pub mod pending;
+pub mod pmtu;
+pub mod reassembly;
+pub mod route;
+pub mod router;
+pub mod stats;
+pub mod tunnel;
 
+#[cfg(test)]
+mod tests;
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use config::{InterfaceAddress, InterfaceConfig};
+pub use dad::AddressClaims;
+pub use filter::Filter;
+pub use multicast::MulticastGroups;
+pub use nat::Nat;
+pub use neighbor::NeighborCache;
+pub use pending::PendingQueue;
+pub use pmtu::PathMtuCache;
+pub use reassembly::Reassembler;
+pub use route::{Route, RoutingTable};
+pub use router::Router;
+pub use stats::{ForwardingStats, Stats};
+pub use tunnel::{Tunnel, TunnelConfig, TunnelKind};
+
+use dad::{ClaimAction, ConflictAction};
+use filter::{Chain, PacketInfo, Rejection, Verdict};
+use multicast::MembershipAction;
+use pending::RetryAction;
+use reassembly::FragmentKey;
+
+use crate::address;
+use crate::device::{Device, Medium};
+use crate::error::Error;
 use crate::protocols::*;
 use crate::protocols::arp::*;
-use crate::protocols::ethernet::{EthernetFrame, Payload};
-use crate::protocols::ipv4::{IpPayload, Ipv4Packet};
-use crate::protocols::icmp::{IcmpType, IcmpPacket};
-
-//const MY_MAC_BYTES: &[u8] = &[];
-//const MY_IP_BYTES: &[u8] = &[169, 254, 0, 2];
-
-fn reply_arp<'a, F> (
-    my_hardware_address: &HardwareAddress<'a>,
-    my_protocol_address: &ProtocolAddress<'a>,
-    arp_request: &mut ArpPacket<'a>,
-    tx_buffer: &mut [u8],
-    mut send: F
-) -> ()
-where
-    F: FnMut(&[u8], usize) -> (),
-{
-    if &my_protocol_address == &arp_request.tpa() {
-        println!("Hey, it's us!");
-
-        let request_sha_bytes = &mut [0 as u8; 6];
-        let request_spa_bytes = &mut [0 as u8; 4];
-
-        {
-            let HardwareAddress::MAC(sha) = arp_request.sha();
-            request_sha_bytes.copy_from_slice(&sha.get_address());
-        }
-        {
-            let ProtocolAddress::IPv4(spa) = arp_request.spa();
-            request_spa_bytes.copy_from_slice(&spa.get_address());
+use crate::protocols::ethernet::{self, EthernetFrame, Payload};
+use crate::protocols::ipv4::{options, IpPayload, IpProtocol, Ipv4Packet};
+use crate::protocols::ipv4::options::Ipv4Option;
+use crate::protocols::igmp::{self, IgmpPacket, IgmpType, IgmpVersion};
+use crate::protocols::icmp::{self, IcmpType, IcmpPacket};
+use crate::protocols::tcp::{self, TcpPacket};
+
+/// Maximum number of events kept until they are polled
+pub const MAX_EVENTS: usize = 32;
+/// Maximum number of packets waiting to be taken by a router
+pub const MAX_HANDOFFS: usize = 64;
+
+/// IPv4 packets an interface attached to a `Router` can't deliver by itself
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Handoff {
+    /// A received packet addressed to another host, if forwarding is enabled
+    Forward(Vec<u8>),
+    /// A packet we originated that none of the interface's routes match
+    Route(Vec<u8>),
+    /// A packet for one of our addresses on a masquerading interface, which may be a reply to a translated connection
+    Inbound(Vec<u8>),
+    /// A GRE or IP-in-IP packet for one of our addresses, carrying a packet for a tunnel interface
+    Tunneled(Vec<u8>),
+}
+
+/// Notable things that happened while polling an interface
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Event {
+    /// No ARP reply was received for the address after all retries
+    NeighborUnreachable([u8; 4]),
+    /// Probing found no other user of the address
+    AddressClaimed([u8; 4]),
+    /// Another host is using one of our addresses
+    AddressConflict {
+        address: [u8; 4],
+        hardware_address: [u8; 6],
+        action: ConflictAction,
+    },
+    /// A router on the way to the destination can't pass packets larger than `mtu` without fragmenting them
+    PathMtuReduced {
+        destination: [u8; 4],
+        mtu: usize,
+    },
+}
+
+pub struct Interface<D: Device> {
+    device: D,
+    config: InterfaceConfig,
+    stats: Stats,
+    neighbor_cache: NeighborCache,
+    path_mtu: PathMtuCache,
+    pending: PendingQueue,
+    claims: AddressClaims,
+    reassembler: Reassembler,
+    routes: RoutingTable,
+    multicast: MulticastGroups,
+    filter: Filter,
+    handoffs: VecDeque<Handoff>,
+    /// Whether a `Router` takes the handoffs
+    attached: bool,
+    /// Identification field of the next IPv4 packet we originate
+    identification: u16,
+    events: VecDeque<Event>,
+}
+
+impl<D: Device> Interface<D> {
+    pub fn new(device: D, config: InterfaceConfig) -> Interface<D> {
+        let mut claims = AddressClaims::new();
+        // Links without Ethernet headers have no ARP to probe with
+        let ethernet = device.capabilities().medium == Medium::Ethernet;
+        claims.sync(&config.addresses, config.duplicate_address_detection && ethernet);
+        let mut routes = RoutingTable::new();
+        routes.sync(0, &config.addresses, config.gateway);
+
+        Interface {
+            device,
+            config,
+            stats: Stats::default(),
+            neighbor_cache: NeighborCache::new(neighbor::DEFAULT_NEIGHBOR_TIMEOUT),
+            path_mtu: PathMtuCache::new(pmtu::DEFAULT_PATH_MTU_TIMEOUT),
+            pending: PendingQueue::new(),
+            claims,
+            reassembler: Reassembler::new(),
+            routes,
+            multicast: MulticastGroups::new(),
+            filter: Filter::new(),
+            handoffs: VecDeque::new(),
+            attached: false,
+            identification: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn config(&self) -> &InterfaceConfig {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut InterfaceConfig {
+        &mut self.config
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn neighbor_cache(&self) -> &NeighborCache {
+        &self.neighbor_cache
+    }
+
+    pub fn neighbor_cache_mut(&mut self) -> &mut NeighborCache {
+        &mut self.neighbor_cache
+    }
+
+    pub fn path_mtu_cache(&self) -> &PathMtuCache {
+        &self.path_mtu
+    }
+
+    pub fn path_mtu_cache_mut(&mut self) -> &mut PathMtuCache {
+        &mut self.path_mtu
+    }
+
+    pub fn pending(&self) -> &PendingQueue {
+        &self.pending
+    }
+
+    pub fn claims(&self) -> &AddressClaims {
+        &self.claims
+    }
+
+    pub fn reassembler(&self) -> &Reassembler {
+        &self.reassembler
+    }
+
+    /// Routes of the interface. Connected and gateway routes follow the configuration,
+    /// static routes can be added through `routes_mut`.
+    pub fn routes(&self) -> &RoutingTable {
+        &self.routes
+    }
+
+    pub fn routes_mut(&mut self) -> &mut RoutingTable {
+        &mut self.routes
+    }
+
+    pub fn multicast_groups(&self) -> &MulticastGroups {
+        &self.multicast
+    }
+
+    /// Join an IPv4 multicast group, reporting it to the routers on the next poll.
+    /// Returns false if `group` isn't a multicast group or was already joined.
+    pub fn join_multicast_group(&mut self, group: [u8; 4], now: Instant) -> bool {
+        self.multicast.join(group, now)
+    }
+
+    /// Leave an IPv4 multicast group. Returns false if it wasn't joined.
+    pub fn leave_multicast_group(&mut self, group: [u8; 4], now: Instant) -> bool {
+        self.multicast.leave(group, now)
+    }
+
+    /// Rules for the frames the interface receives and transmits
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn filter_mut(&mut self) -> &mut Filter {
+        &mut self.filter
+    }
+
+    /// Largest IPv4 packet that can be sent without fragmenting it. Never below `config::MIN_MTU`,
+    /// packets are only fragmented that far.
+    pub fn mtu(&self) -> usize {
+        self.config.mtu.min(self.device.capabilities().mtu).max(config::MIN_MTU)
+    }
+
+    /// Largest datagram to send to `destination`: the MTU, or less if a router on the way said so.
+    /// Transports sending with DF set size their datagrams by this (RFC 1191).
+    pub fn path_mtu(&self, destination: &[u8; 4], now: Instant) -> usize {
+        self.path_mtu.lookup(destination, now).map_or(self.mtu(), |mtu| mtu.min(self.mtu()))
+    }
+
+    /// Whether `address` is configured on the interface and done probing
+    pub fn has_address(&self, address: &[u8; 4]) -> bool {
+        self.config.has_address(address) && self.claims.is_usable(address)
+    }
+
+    /// Pick the address to send from when talking to `destination`: the first usable one
+    /// on the same subnet as the next hop, or the first usable one overall
+    pub fn source_address_for(&self, destination: &[u8; 4]) -> Option<[u8; 4]> {
+        let next_hop = self.routes.lookup(destination)
+            .map_or(*destination, |route| route.next_hop_for(destination));
+        let mut usable = self.config.addresses.iter()
+            .filter(|a| self.claims.is_usable(&a.address));
+        usable.clone()
+            .find(|a| a.contains(&next_hop))
+            .or_else(|| usable.next())
+            .map(|a| a.address)
+    }
+
+    /// Whether packets for `address` are ours to process: sent to one of our addresses,
+    /// broadcast on our subnets or to a multicast group we joined
+    pub fn accepts(&self, address: &[u8; 4]) -> bool {
+        self.has_address(address) || self.config.is_broadcast(address) || self.multicast.is_member(address)
+    }
+
+    /// Pick the address to answer a packet from `sender` to `destination` with: the destination itself
+    /// if it is one of ours, the address of the subnet it is the broadcast address of, or otherwise
+    /// the one `source_address_for` picks for the sender
+    pub fn reply_address_for(&self, destination: &[u8; 4], sender: &[u8; 4]) -> Option<[u8; 4]> {
+        if self.has_address(destination) {
+            return Some(*destination);
+        }
+        self.config.addresses.iter()
+            .filter(|a| self.claims.is_usable(&a.address))
+            .find(|a| a.broadcast().as_ref() == Some(destination))
+            .map(|a| a.address)
+            .or_else(|| self.source_address_for(sender))
+    }
+
+    /// Take the oldest packet handed off for a router to deliver
+    pub fn take_handoff(&mut self) -> Option<Handoff> {
+        self.handoffs.pop_front()
+    }
+
+    fn push_handoff(&mut self, handoff: Handoff) {
+        if self.handoffs.len() >= MAX_HANDOFFS {
+            println!("Handoff queue is full, dropping packet");
+            self.stats.tx_dropped += 1;
+            return;
+        }
+        self.handoffs.push_back(handoff);
+    }
+
+    /// Take the oldest event that hasn't been polled yet
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn next_identification(&mut self) -> u16 {
+        self.identification = self.identification.wrapping_add(1);
+        self.identification
+    }
+
+    fn push_event(&mut self, event: Event) {
+        println!("{:?}", event);
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Length of the link layer header in front of the packets the device passes: 14 for Ethernet, none for IP
+    fn link_header_len(&self) -> usize {
+        match self.device.capabilities().medium {
+            Medium::Ethernet => ethernet::HEADER_LEN,
+            Medium::Ip => 0,
+        }
+    }
+
+    /// Describe a frame of the device's medium for the filter
+    fn packet_info(&self, frame: &[u8]) -> PacketInfo {
+        match self.device.capabilities().medium {
+            Medium::Ethernet => PacketInfo::from_frame(frame),
+            Medium::Ip => PacketInfo::from_ipv4(frame),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if self.filter.is_active(Chain::Egress) {
+            let verdict = self.filter.evaluate(Chain::Egress, &self.packet_info(frame));
+            if verdict != Verdict::Accept {
+                println!("Filter denied sending {} bytes", frame.len());
+                self.stats.tx_denied += 1;
+                return Ok(());
+            }
+        }
+
+        println!("Sending {} bytes", frame.len());
+        let sent = self.device.transmit(frame)?;
+        self.stats.tx_frames += 1;
+        println!("Sent: {}", sent);
+        Ok(())
+    }
+
+    /// Fill in the Ethernet header of the IPv4 packet at the start of `tx_buffer` and transmit it to its next hop.
+    /// Without a `next_hop` from the caller, the interface's routes pick one.
+    fn dispatch_ipv4(&mut self, now: Instant, tx_buffer: &mut [u8], size: usize, next_hop: Option<[u8; 4]>) -> Result<(), Error> {
+        let destination = {
+            let mut frame = EthernetFrame::try_from(&mut tx_buffer[..size])?;
+            match frame.payload() {
+                Payload::IPv4(ipv4_packet) => ipv4_packet.header().destination_ip().get_address(),
+                _ => panic!("Dispatching a frame without an IPv4 payload"),
+            }
+        };
+
+        // The other end of a point-to-point link gets everything, the packet goes without the Ethernet header
+        if self.link_header_len() == 0 {
+            return self.transmit(&tx_buffer[ethernet::HEADER_LEN..size]);
+        }
+
+        // Broadcasts go to every host on the link, there is nobody to resolve
+        if self.config.is_broadcast(&destination) {
+            let mut frame = EthernetFrame::uninitialized(tx_buffer);
+            frame.source_mac().set_address(&self.config.mac);
+            frame.destination_mac().set_address(&[0xFF; 6]);
+            return self.transmit(&tx_buffer[..size]);
+        }
+        if multicast::is_multicast(&destination) {
+            let mut frame = EthernetFrame::uninitialized(tx_buffer);
+            frame.source_mac().set_address(&self.config.mac);
+            frame.destination_mac().set_address(&multicast::multicast_mac(&destination));
+            return self.transmit(&tx_buffer[..size]);
+        }
+
+        let route = self.routes.lookup(&destination);
+        let next_hop = match next_hop.or_else(|| route.map(|route| route.next_hop_for(&destination))) {
+            Some(next_hop) => next_hop,
+            None if self.attached => {
+                self.push_handoff(Handoff::Route(tx_buffer[ethernet::HEADER_LEN..size].to_vec()));
+                return Ok(());
+            }
+            None => {
+                println!("No route to {:?}, dropping packet", destination);
+                self.stats.tx_no_route += 1;
+                return Ok(());
+            }
+        };
+
+        let hardware_address = match self.neighbor_cache.lookup(&next_hop, now) {
+            Some(hardware_address) => hardware_address,
+            None => return self.resolve(now, next_hop, tx_buffer, size),
+        };
+
+        let mut frame = EthernetFrame::uninitialized(tx_buffer);
+        frame.source_mac().set_address(&self.config.mac);
+        frame.destination_mac().set_address(&hardware_address);
+
+        self.transmit(&tx_buffer[..size])
+    }
+
+    /// Queue the IPv4 packet in `tx_buffer` until `next_hop` answers an ARP request
+    fn resolve(&mut self, now: Instant, next_hop: [u8; 4], tx_buffer: &mut [u8], size: usize) -> Result<(), Error> {
+        if !self.pending.enqueue(next_hop, &tx_buffer[ethernet::HEADER_LEN..size]) {
+            println!("Pending queue is full, dropping packet for {:?}", next_hop);
+            self.stats.tx_unresolved += 1;
+            return Ok(());
+        }
+
+        if !self.pending.is_resolving(&next_hop) {
+            let source = self.source_address_for(&next_hop).unwrap_or([0; 4]);
+            self.send_arp_request(source, next_hop, tx_buffer)?;
+            self.pending.start_resolution(next_hop, now);
+        }
+
+        Ok(())
+    }
+
+    /// Broadcast an ARP request. Probes use 0.0.0.0 as the source, announcements our own address as the target.
+    fn send_arp_request(&mut self, source: [u8; 4], target: [u8; 4], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        if self.link_header_len() == 0 {
+            return Ok(());
         }
 
+        let mut my_mac_bytes = self.config.mac;
+        let mut my_ip_bytes = source;
+        let mut target_mac_bytes = [0_u8; 6];
+        let mut target_ip_bytes = target;
+
+        let mut arp_request_eth_frame = EthernetFrame::uninitialized(tx_buffer);
+        arp_request_eth_frame.source_mac().set_address(&self.config.mac);
+        arp_request_eth_frame.destination_mac().set_address(&[0xFF; 6]);
+
+        let buf = arp_request_eth_frame.take_payload_buffer();
+        let arp_request = ArpPacket::new(
+            buf,
+            ArpOperation::REQUEST,
+            &HardwareAddress::MAC((&mut my_mac_bytes).into()),
+            &ProtocolAddress::IPv4((&mut my_ip_bytes).into()),
+            &HardwareAddress::MAC((&mut target_mac_bytes).into()),
+            &ProtocolAddress::IPv4((&mut target_ip_bytes).into()),
+        );
+        arp_request_eth_frame.set_payload(Payload::ARP(arp_request));
+
+        println!("{:#x?}", arp_request_eth_frame);
+        let size = arp_request_eth_frame.size();
+        self.transmit(&tx_buffer[..size])
+    }
+
+    /// Transmit an IPv4 packet that was held back while resolving its next hop
+    fn transmit_pending(&mut self, hardware_address: [u8; 6], packet: &[u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let size = ethernet::HEADER_LEN + packet.len();
+        let mut frame = EthernetFrame::uninitialized(&mut tx_buffer[..size]);
+        frame.source_mac().set_address(&self.config.mac);
+        frame.destination_mac().set_address(&hardware_address);
+
+        let payload_buffer = frame.take_payload_buffer();
+        payload_buffer.copy_from_slice(packet);
+        frame.set_payload(Payload::IPv4(Ipv4Packet::try_from(payload_buffer)?));
+
+        self.transmit(&tx_buffer[..size])
+    }
+
+    /// Run the timers: claim new addresses, expire neighbors and retry or give up on pending ARP requests
+    fn process_timers(&mut self, now: Instant, tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let ethernet = self.link_header_len() > 0;
+        self.claims.sync(&self.config.addresses, self.config.duplicate_address_detection && ethernet);
+        self.routes.sync(0, &self.config.addresses, self.config.gateway);
+        for action in self.claims.poll(now) {
+            match action {
+                ClaimAction::Probe(address) => self.send_arp_request([0; 4], address, tx_buffer)?,
+                ClaimAction::Announce(address) => self.send_arp_request(address, address, tx_buffer)?,
+                ClaimAction::Claimed(address) => self.push_event(Event::AddressClaimed(address)),
+            }
+        }
+
+        for action in self.multicast.poll(now) {
+            self.send_igmp(now, action, tx_buffer)?;
+        }
+
+        self.neighbor_cache.expire(now);
+        self.path_mtu.expire(now);
+        self.stats.rx_reassembly_failed += self.reassembler.expire(now) as u64;
+
+        for action in self.pending.retry(now) {
+            match action {
+                RetryAction::Request(next_hop) => {
+                    let source = self.source_address_for(&next_hop).unwrap_or([0; 4]);
+                    self.send_arp_request(source, next_hop, tx_buffer)?
+                },
+                RetryAction::Unreachable { next_hop, dropped } => {
+                    self.stats.tx_unresolved += dropped.len() as u64;
+                    self.push_event(Event::NeighborUnreachable(next_hop));
+                    for mut packet in dropped {
+                        self.send_icmp_error(
+                            now,
+                            IcmpType::DestinationUnreachable,
+                            icmp::CODE_HOST_UNREACHABLE,
+                            [0; 4],
+                            &mut packet,
+                            tx_buffer,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How long the caller may wait for incoming frames before the timers need to run
+    pub fn poll_delay(&self, now: Instant) -> Option<Duration> {
+        [self.pending.next_retry(), self.claims.next_poll(), self.reassembler.next_expiry(), self.multicast.next_poll()]
+            .iter()
+            .flatten()
+            .min()
+            .map(|at| at.saturating_duration_since(now))
+    }
+
+    fn address_conflict(
+        &mut self,
+        now: Instant,
+        address: [u8; 4],
+        hardware_address: [u8; 6],
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let action = match self.claims.conflict(&address, now) {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+
+        match action {
+            ConflictAction::Defended => self.send_arp_request(address, address, tx_buffer)?,
+            ConflictAction::Refused | ConflictAction::Released => {
+                self.config.addresses.retain(|a| a.address != address);
+            }
+        }
+
+        self.push_event(Event::AddressConflict { address, hardware_address, action });
+        Ok(())
+    }
+
+    fn process_arp(&mut self, now: Instant, arp_packet: &mut ArpPacket, tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let addresses = (
+            arp_packet.sha().as_mac().map(|sha| sha.get_address()),
+            arp_packet.spa().as_ipv4().map(|spa| spa.get_address()),
+            arp_packet.tpa().as_ipv4().map(|tpa| tpa.get_address()),
+        );
+        let (sender_hardware_address, sender_protocol_address, target_protocol_address) = match addresses {
+            (Some(sha), Some(spa), Some(tpa)) => (sha, spa, tpa),
+            _ => {
+                let (_, hardware, protocol) = arp_packet.fmt_header();
+                println!("Ignoring ARP for {} / {}", hardware, protocol);
+                self.stats.rx_unsupported += 1;
+                return Ok(());
+            }
+        };
+
+        if sender_hardware_address != self.config.mac {
+            let conflicting_address = if sender_protocol_address == [0; 4] {
+                // Another host probing for an address we are probing for as well
+                Some(target_protocol_address).filter(|a| self.claims.is_tentative(a))
+            } else {
+                Some(sender_protocol_address).filter(|a| self.config.has_address(a))
+            };
+
+            if let Some(address) = conflicting_address {
+                return self.address_conflict(now, address, sender_hardware_address, tx_buffer);
+            }
+        }
+
+        let for_us = self.has_address(&target_protocol_address);
+        // Answer for proxied addresses, except when their owner is probing or announcing itself
+        let proxied = !for_us
+            && sender_protocol_address != [0; 4]
+            && sender_protocol_address != target_protocol_address
+            && self.config.is_proxied(&target_protocol_address);
+
+        // RFC 826: refresh a sender we already know, only learn new ones from packets meant for us
+        if sender_protocol_address != [0; 4]
+            && (for_us || proxied || self.neighbor_cache.contains(&sender_protocol_address)) {
+            self.neighbor_cache.insert(sender_protocol_address, sender_hardware_address, now);
+
+            for packet in self.pending.resolved(&sender_protocol_address) {
+                self.transmit_pending(sender_hardware_address, &packet, tx_buffer)?;
+            }
+        }
+
+        if arp_packet.oper() == ArpOperation::REQUEST {
+            if for_us {
+                println!("Hey, it's us!");
+                self.reply_arp(sender_hardware_address, sender_protocol_address, target_protocol_address, tx_buffer)?;
+            } else if proxied {
+                println!("Answering for {:?} as a proxy", target_protocol_address);
+                self.reply_arp(sender_hardware_address, sender_protocol_address, target_protocol_address, tx_buffer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer an ARP request for `target` with our hardware address
+    fn reply_arp(
+        &mut self,
+        mut requester_mac: [u8; 6],
+        mut requester_ip: [u8; 4],
+        target: [u8; 4],
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut my_ip_bytes = target;
+        let mut my_mac_bytes = self.config.mac;
+        let my_hardware_address = HardwareAddress::MAC((&mut my_mac_bytes).into());
+        let my_protocol_address = ProtocolAddress::IPv4((&mut my_ip_bytes).into());
+
         /*
          * Ethernet header
          */
-        let HardwareAddress::MAC(ref my_mac) = my_hardware_address;
-
         let mut arp_reply_eth_frame = EthernetFrame::uninitialized(tx_buffer);
-        arp_reply_eth_frame.source_mac().set_address(&my_mac.get_address());
-        arp_reply_eth_frame.destination_mac().set_address(&request_sha_bytes);
+        arp_reply_eth_frame.source_mac().set_address(&self.config.mac);
+        arp_reply_eth_frame.destination_mac().set_address(&requester_mac);
 
         /*
          * ARP header
@@ -54,105 +622,610 @@ where
             ArpOperation::REPLY,
             &my_hardware_address,
             &my_protocol_address,
-            &HardwareAddress::MAC(request_sha_bytes.as_mut().into()),
-            &ProtocolAddress::IPv4(request_spa_bytes.as_mut().into()),
+            &HardwareAddress::MAC((&mut requester_mac).into()),
+            &ProtocolAddress::IPv4((&mut requester_ip).into()),
         );
         arp_reply_eth_frame.set_payload(Payload::ARP(arp_reply));
 
         println!("{:#x?}", arp_reply_eth_frame);
-        send(&tx_buffer, 42);
+        let size = arp_reply_eth_frame.size();
+        self.transmit(&tx_buffer[..size])?;
+
+        Ok(())
     }
-}
 
-fn reply_ping<'a, F> (
-    my_hardware_address: &HardwareAddress<'a>,
-    my_protocol_address: &ProtocolAddress<'a>,
-    echo_request: &mut IcmpPacket<'a>,
-    tx_buffer: &mut [u8],
-    mut send: F
-) -> ()
-    where
-        F: FnMut(&[u8], usize) -> (),
-{
-    let mut arp_reply_eth_frame = EthernetFrame::uninitialized(tx_buffer);
-
-    let HardwareAddress::MAC(ref my_mac) = my_hardware_address;
-    arp_reply_eth_frame.source_mac().set_address(&my_mac.get_address());
-    arp_reply_eth_frame.destination_mac().set_address(&[0x72, 0x59, 0x69, 0x20, 0x9a, 0xaf]);
-}
+    /// IPv4 packet answering `echo_request` with the same identifier, sequence number and data
+    fn echo_reply(
+        &mut self,
+        source_address: &mut [u8; 4],
+        destination_address: &mut [u8; 4],
+        ip_options: &[u8],
+        dscp: u8,
+        echo_request: &IcmpPacket,
+    ) -> Vec<u8> {
+        println!("Pong..?");
+
+        let ip_options = options::Ipv4Options::new(ip_options)
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
 
-pub fn update<F>(rx_buffer: &mut [u8], tx_buffer: &mut [u8], mut send: F) -> ()
-where
-    F: FnMut(&[u8], usize) -> (),
-{
-    let my_mac_bytes: &mut [u8] = &mut [0x02, 0xDE, 0xAD, 0x00, 0xBE, 0xEF];
-    let my_hardware_address = HardwareAddress::MAC(my_mac_bytes.into());
-    let my_ip_bytes: &mut [u8] = &mut [169, 254, 0, 2];
-    let my_protocol_address = ProtocolAddress::IPv4(my_ip_bytes.into());
-
-    println!("Received {} bytes", rx_buffer.len());
-    let mut frame = EthernetFrame::from_slice(rx_buffer);
-    println!("{:#x?}", &frame);
-    match frame.payload() {
-        Payload::ARP(ref mut arp_request)  => {
-            if ArpOperation::REQUEST == arp_request.oper() {
-                reply_arp(
-                    &my_hardware_address, &my_protocol_address,
-                    arp_request,
-                    tx_buffer,  send
-                )
-            }
-        },
-        Payload::IPv4(ref mut ipv4_packet) => {
-            let ProtocolAddress::IPv4(my_ipv4_addresss) = my_protocol_address;
-            if ipv4_packet.header().destination_ip() == &my_ipv4_addresss {
-                let response_source_address_bytes = &mut [0 as u8; 4];
-                let response_destination_address_bytes = &mut [0 as u8; 4];
-                response_source_address_bytes.copy_from_slice(&ipv4_packet.header().destination_ip().get_address());
-                response_destination_address_bytes.copy_from_slice(&ipv4_packet.header().source_ip().get_address());
-
-                match ipv4_packet.payload() {
-                    IpPayload::ICMP(icmp_packet)  => {
-                        match icmp_packet.icmp_type() {
-                            IcmpType::EchoRequest => {
-                                //let mut icmp_seq_id = [0u8; 4];
-                                //icmp_seq_id.copy_from_slice(icmp_packet.rest_of_header());
-
-                                println!("Pong..?");
-
-
-                                let mut reply_ethernet_frame = EthernetFrame::uninitialized(tx_buffer);
-                                let ethernet_payload_buffer = reply_ethernet_frame.take_payload_buffer();
-                                let mut ipv4_packet = Ipv4Packet::new(
-                                    ethernet_payload_buffer,
-                                    &response_source_address_bytes.as_mut().into(),
-                                    &response_destination_address_bytes.as_mut().into(),
-                                );
-
-                                let ip_payload_buffer = ipv4_packet.take_payload_buffer();
-                                let mut icmp_response_packet = IcmpPacket::new(
-                                    ip_payload_buffer,
-                                );
-
-                                icmp_response_packet.set_rest_of_header(&icmp_packet.rest_of_header());
-                                icmp_response_packet.data[0..56].copy_from_slice(icmp_packet.data);
-                                icmp_response_packet.calculate_checksum();
-
-                                ipv4_packet.set_payload(IpPayload::ICMP(icmp_response_packet));
-                                ipv4_packet.header().set_length(84);
-                                ipv4_packet.header().calculate_checksum();
-                                reply_ethernet_frame.set_payload(Payload::IPv4(ipv4_packet));
-                                println!("{:#x?}", reply_ethernet_frame);
-                                send(&tx_buffer, 98);
-                            }
-                            _  => {}
-                        }
+        // Built separately from the frame, as replies to reassembled requests may need fragmenting
+        let mut packet = vec![0_u8; 20 + options::padded_len(&ip_options) + echo_request.size()];
+        let mut ipv4_packet = Ipv4Packet::new_with_options(
+            &mut packet,
+            &source_address.into(),
+            &destination_address.into(),
+            &ip_options,
+        );
+        ipv4_packet.header().set_identification(self.next_identification());
+        // Replies are sent with the same type of service as the request (RFC 1812 4.3.2.5)
+        ipv4_packet.header().set_dscp(dscp);
+
+        let ip_payload_buffer = ipv4_packet.take_payload_buffer();
+        let mut icmp_response_packet = IcmpPacket::new(
+            ip_payload_buffer,
+        );
+
+        icmp_response_packet.set_rest_of_header(&echo_request.rest_of_header());
+        icmp_response_packet.set_data(echo_request.data);
+        icmp_response_packet.calculate_checksum();
+
+        ipv4_packet.set_payload(IpPayload::ICMP(icmp_response_packet));
+        ipv4_packet.header().calculate_checksum();
+        println!("{:#x?}", ipv4_packet);
+        packet
+    }
+
+    /// Answer the IPv4 packet `original` with an ICMP error, unless RFC 1122 3.2.2 forbids it
+    fn send_icmp_error(
+        &mut self,
+        now: Instant,
+        icmp_type: IcmpType,
+        code: u8,
+        rest_of_header: [u8; 4],
+        original: &mut [u8],
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let (mut source, mut destination, quoted_length) = {
+            let mut original_packet = Ipv4Packet::try_from(&mut *original)?;
+            let about_error = match original_packet.payload() {
+                IpPayload::ICMP(icmp_packet) => icmp_packet.icmp_type().is_error(),
+                _ => false,
+            };
+            let header = original_packet.header();
+            let destination = header.source_ip().get_address();
+            let non_initial_fragment = header.fragment_offset() != 0;
+            let original_destination = header.destination_ip().get_address();
+            let to_broadcast = self.config.is_broadcast(&original_destination) || !is_unicast(&original_destination);
+
+            // Errors about our own packets have nobody to go to but ourselves
+            let own_packet = self.config.has_address(&destination);
+
+            if about_error || non_initial_fragment || own_packet || to_broadcast || !is_unicast(&destination) {
+                return Ok(());
+            }
+
+            // The original header and the first eight bytes of its payload
+            let quoted_length = header.ihl() as usize * 4 + 8;
+            let source = match self.source_address_for(&destination) {
+                Some(source) => source,
+                None => return Ok(()),
+            };
+            (source, destination, quoted_length)
+        };
+        let quoted = &original[..quoted_length.min(original.len())];
+
+        let mut packet = vec![0_u8; 20 + 8 + quoted.len()];
+        let mut ipv4_packet = Ipv4Packet::new(&mut packet, &(&mut source).into(), &(&mut destination).into());
+        ipv4_packet.header().set_identification(self.next_identification());
+
+        let mut icmp_packet = IcmpPacket::new(ipv4_packet.take_payload_buffer());
+        icmp_packet.set_icmp_type(icmp_type);
+        icmp_packet.set_icmp_code(code);
+        icmp_packet.set_rest_of_header(&rest_of_header);
+        icmp_packet.set_data(quoted);
+        icmp_packet.calculate_checksum();
+
+        ipv4_packet.set_payload(IpPayload::ICMP(icmp_packet));
+        ipv4_packet.header().calculate_checksum();
+        println!("{:#x?}", ipv4_packet);
+        self.send_ipv4(now, &mut packet, tx_buffer)
+    }
+
+    /// Answer a TCP segment with a reset, as if nothing listened on its port (RFC 793 3.4)
+    fn send_tcp_reset(&mut self, now: Instant, original: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let mut original_packet = Ipv4Packet::try_from(&mut *original)?;
+        let mut source = original_packet.header().destination_ip().get_address();
+        let mut destination = original_packet.header().source_ip().get_address();
+
+        // Resets are never answered, and only hosts get one
+        let segment = match original_packet.payload() {
+            IpPayload::TCP(segment) if !segment.has_flag(tcp::FLAG_RST) => segment,
+            _ => return Ok(()),
+        };
+        if self.config.is_broadcast(&source) || !is_unicast(&source) || !is_unicast(&destination) {
+            return Ok(());
+        }
+
+        let mut packet = vec![0_u8; 20 + 20];
+        let mut ipv4_packet = Ipv4Packet::new(&mut packet, &(&mut source).into(), &(&mut destination).into());
+        ipv4_packet.header().set_identification(self.next_identification());
+
+        let mut reset = TcpPacket::new(ipv4_packet.take_payload_buffer());
+        reset.set_source_port(segment.destination_port());
+        reset.set_destination_port(segment.source_port());
+        if segment.has_flag(tcp::FLAG_ACK) {
+            reset.set_sequence_number(segment.acknowledgment_number());
+            reset.set_flags(tcp::FLAG_RST);
+        } else {
+            reset.set_acknowledgment_number(segment.sequence_number().wrapping_add(segment.segment_length()));
+            reset.set_flags(tcp::FLAG_RST | tcp::FLAG_ACK);
+        }
+        reset.calculate_checksum(&source, &destination);
+
+        ipv4_packet.set_payload(IpPayload::TCP(reset));
+        ipv4_packet.header().calculate_checksum();
+        println!("{:#x?}", ipv4_packet);
+        self.send_ipv4(now, &mut packet, tx_buffer)
+    }
+
+    /// Tell the sender of a packet the filter rejected that it was turned away
+    fn reject(&mut self, now: Instant, rejection: Rejection, original: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let is_tcp = Ipv4Packet::try_from(&mut *original)?.header().protocol_number() == IpProtocol::TCP as u8;
+        let code = match rejection {
+            Rejection::TcpReset if is_tcp => return self.send_tcp_reset(now, original, tx_buffer),
+            Rejection::TcpReset => filter::CODE_PORT_UNREACHABLE,
+            Rejection::IcmpUnreachable(code) => code,
+        };
+        self.send_icmp_error(now, IcmpType::DestinationUnreachable, code, [0; 4], original, tx_buffer)
+    }
+
+    /// Send a complete IPv4 packet we originated, fragmenting it if it exceeds the path MTU.
+    /// Oversized packets with DF set are answered with ICMP fragmentation needed instead.
+    fn send_ipv4(&mut self, now: Instant, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let destination = Ipv4Packet::try_from(&mut *packet)?.header().destination_ip().get_address();
+        let mtu = self.path_mtu(&destination, now);
+        self.send_ipv4_sized(now, packet, None, mtu, tx_buffer)
+    }
+
+    /// Like `send_ipv4`, but to a next hop chosen by the caller instead of the interface's routes,
+    /// and fragmenting by the MTU of the link only
+    fn send_ipv4_via(
+        &mut self,
+        now: Instant,
+        packet: &mut [u8],
+        next_hop: Option<[u8; 4]>,
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mtu = self.mtu();
+        self.send_ipv4_sized(now, packet, next_hop, mtu, tx_buffer)
+    }
+
+    fn send_ipv4_sized(
+        &mut self,
+        now: Instant,
+        packet: &mut [u8],
+        next_hop: Option<[u8; 4]>,
+        mtu: usize,
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        if packet.len() <= mtu {
+            return self.send_ipv4_frame(now, packet, next_hop, tx_buffer);
+        }
+
+        let dont_fragment = Ipv4Packet::try_from(&mut *packet)?.header().dont_fragment();
+
+        if dont_fragment {
+            println!("Packet of {} bytes exceeds the MTU of {} and may not be fragmented", packet.len(), mtu);
+            self.stats.tx_fragmentation_needed += 1;
+            let next_hop_mtu = (mtu as u16).to_be_bytes();
+            return self.send_icmp_error(
+                now,
+                IcmpType::DestinationUnreachable,
+                icmp::CODE_FRAGMENTATION_NEEDED,
+                [0, 0, next_hop_mtu[0], next_hop_mtu[1]],
+                packet,
+                tx_buffer,
+            );
+        }
+
+        for fragment in fragmentation::fragment(packet, mtu) {
+            self.stats.tx_fragments += 1;
+            self.send_ipv4_frame(now, &fragment, next_hop, tx_buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Wrap an IPv4 packet that fits the MTU in an Ethernet frame and send it towards its destination
+    fn send_ipv4_frame(
+        &mut self,
+        now: Instant,
+        packet: &[u8],
+        next_hop: Option<[u8; 4]>,
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let size = ethernet::HEADER_LEN + packet.len();
+        let mut frame = EthernetFrame::uninitialized(&mut tx_buffer[..size]);
+        let payload_buffer = frame.take_payload_buffer();
+        payload_buffer.copy_from_slice(packet);
+        frame.set_payload(Payload::IPv4(Ipv4Packet::try_from(payload_buffer)?));
+        self.dispatch_ipv4(now, tx_buffer, size, next_hop)
+    }
+
+    /// Process a received IPv4 packet. `forwardable` is set for packets that came in a frame sent to our hardware address.
+    fn process_ipv4(
+        &mut self,
+        now: Instant,
+        ipv4_packet: &mut Ipv4Packet,
+        forwardable: bool,
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let verify_checksums = !self.device.capabilities().rx_checksum_offload;
+
+        if verify_checksums && !ipv4_packet.header().verify_checksum() {
+            println!("Dropping IPv4 packet with bad header checksum");
+            self.stats.rx_bad_checksum += 1;
+            return Ok(());
+        }
+
+        // Fragments to be forwarded are reassembled too when the ingress rules have to see the whole datagram
+        let destination = ipv4_packet.header().destination_ip().get_address();
+        let accepted = self.accepts(&destination);
+        if ipv4_packet.header().is_fragment() && (accepted || self.filter.is_active(Chain::Ingress)) {
+            return self.process_fragment(now, ipv4_packet, forwardable, tx_buffer);
+        }
+
+        if !accepted {
+            if forwardable && self.attached && self.config.forwarding && is_unicast(&destination) {
+                self.push_handoff(Handoff::Forward(ipv4_packet.to_bytes()));
+            }
+            return Ok(());
+        }
+
+        let broadcast = !self.has_address(&destination);
+        if multicast::is_multicast(&destination) {
+            self.stats.rx_multicast += 1;
+        } else if broadcast {
+            self.stats.rx_broadcast += 1;
+        }
+
+        if let IpPayload::IGMP(igmp_packet) = ipv4_packet.payload() {
+            if verify_checksums && !igmp_packet.verify_checksum() {
+                println!("Dropping IGMP packet with bad checksum");
+                self.stats.rx_bad_checksum += 1;
+                return Ok(());
+            }
+            self.process_igmp(now, igmp_packet);
+            return Ok(());
+        }
+
+        if forwardable && self.attached && self.config.masquerade && !broadcast {
+            self.push_handoff(Handoff::Inbound(ipv4_packet.to_bytes()));
+            return Ok(());
+        }
+
+        if let IpPayload::GRE(_) | IpPayload::IPIP(_) = ipv4_packet.payload() {
+            if self.attached && !broadcast {
+                self.push_handoff(Handoff::Tunneled(ipv4_packet.to_bytes()));
+            }
+            return Ok(());
+        }
+
+        let response_destination_address_bytes = &mut [0_u8; 4];
+        response_destination_address_bytes.copy_from_slice(&ipv4_packet.header().source_ip().get_address());
+        // Broadcasts are answered from the address on the subnet they were sent to
+        let response_source_address_bytes = &mut match self.reply_address_for(&destination, response_destination_address_bytes) {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+
+        // Record route and timestamp options come back in the reply, with us recorded in them
+        let mut reply_options = [0_u8; options::MAX_OPTIONS_LEN];
+        let mut reply_options_len = options::copy_for_reply(
+            ipv4_packet.header().options().unwrap_or(&[]),
+            &mut reply_options,
+        );
+        options::record(&mut reply_options[..reply_options_len], *response_source_address_bytes, timestamp());
+
+        // A source route the request came through is taken back in reverse, the reply going to its last hop first
+        let mut reply_next_hop = *response_destination_address_bytes;
+        if let Some((length, first_hop)) = options::reverse_source_route(
+            ipv4_packet.header().options().unwrap_or(&[]),
+            *response_destination_address_bytes,
+            &mut reply_options[reply_options_len..],
+        ) {
+            reply_options_len += length;
+            reply_next_hop = first_hop;
+        }
+        let reply_options = &reply_options[..reply_options_len];
+        let reply_dscp = ipv4_packet.header().dscp();
+
+        if let IpPayload::ICMP(icmp_packet) = ipv4_packet.payload() {
+            if verify_checksums && !icmp_packet.verify_checksum() {
+                println!("Dropping ICMP packet with bad checksum");
+                self.stats.rx_bad_checksum += 1;
+                return Ok(());
+            }
+
+            if icmp_packet.icmp_type() == IcmpType::DestinationUnreachable
+                && icmp_packet.icmp_code() == icmp::CODE_FRAGMENTATION_NEEDED {
+                self.process_fragmentation_needed(now, icmp_packet);
+            }
+
+            if let IcmpType::EchoRequest = icmp_packet.icmp_type() {
+                if broadcast && !self.config.answer_broadcast_echo {
+                    println!("Ignoring echo request to broadcast address {:?}", destination);
+                    return Ok(());
+                }
+                let mut reply = self.echo_reply(
+                    response_source_address_bytes, &mut reply_next_hop,
+                    reply_options,
+                    reply_dscp,
+                    icmp_packet,
+                );
+                self.send_ipv4(now, &mut reply, tx_buffer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lower the path MTU to the destination of a packet we sent that a router couldn't pass on (RFC 1191)
+    fn process_fragmentation_needed(&mut self, now: Instant, icmp_packet: &IcmpPacket) {
+        // The quoted header of the packet that was too large
+        let quoted = &icmp_packet.data;
+        if quoted.len() < 20 || quoted[0] >> 4 != 4 {
+            return;
+        }
+        let source = [quoted[12], quoted[13], quoted[14], quoted[15]];
+        let destination = [quoted[16], quoted[17], quoted[18], quoted[19]];
+        if !self.has_address(&source) {
+            return;
+        }
+
+        let packet_length = u16::from_be_bytes([quoted[2], quoted[3]]) as usize;
+        let rest_of_header = icmp_packet.rest_of_header();
+        let next_hop_mtu = u16::from_be_bytes([rest_of_header[2], rest_of_header[3]]) as usize;
+
+        if let Some(mtu) = self.path_mtu.update(destination, next_hop_mtu, packet_length, now) {
+            println!("Path MTU to {:?} is now {}", destination, mtu);
+            self.push_event(Event::PathMtuReduced { destination, mtu });
+        }
+    }
+
+    fn process_igmp(&mut self, now: Instant, igmp_packet: &IgmpPacket) {
+        match igmp_packet.igmp_type() {
+            IgmpType::MembershipQuery => {
+                let version = match igmp_packet.query_version() {
+                    Some(version) => version,
+                    None => {
+                        println!("Dropping IGMP query of invalid length");
+                        self.stats.rx_malformed += 1;
+                        return;
                     }
-                    _ => {}
+                };
+                let max_response = match version {
+                    IgmpVersion::V1 => multicast::IGMPV1_MAX_RESPONSE_TIME,
+                    _ => igmp_packet.max_response_time(),
+                };
+                let group = Some(igmp_packet.group()).filter(|group| group != &[0; 4]);
+                self.multicast.query(now, version, group, max_response);
+            },
+            IgmpType::V1MembershipReport | IgmpType::V2MembershipReport => {
+                self.multicast.report_heard(&igmp_packet.group(), now);
+            },
+            _ => {},
+        }
+    }
+
+    /// Send an IGMP report or leave, with TTL 1 and the router alert option (RFC 2236, RFC 3376)
+    fn send_igmp(&mut self, now: Instant, action: MembershipAction, tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let (mut destination, igmp_type, group, records) = match action {
+            MembershipAction::Report { group, version: IgmpVersion::V1, .. } =>
+                (group, IgmpType::V1MembershipReport, group, Vec::new()),
+            MembershipAction::Report { group, version: IgmpVersion::V2, .. } =>
+                (group, IgmpType::V2MembershipReport, group, Vec::new()),
+            MembershipAction::Report { group, version: IgmpVersion::V3, state_change } => {
+                let record_type = if state_change {
+                    igmp::RECORD_CHANGE_TO_EXCLUDE_MODE
+                } else {
+                    igmp::RECORD_MODE_IS_EXCLUDE
+                };
+                (multicast::ALL_IGMPV3_ROUTERS, IgmpType::V3MembershipReport, [0; 4], vec![igmp::group_record(record_type, &group)])
+            },
+            MembershipAction::ReportAll => {
+                let records = self.multicast.groups()
+                    .map(|group| igmp::group_record(igmp::RECORD_MODE_IS_EXCLUDE, group))
+                    .collect();
+                (multicast::ALL_IGMPV3_ROUTERS, IgmpType::V3MembershipReport, [0; 4], records)
+            },
+            MembershipAction::Leave { version: IgmpVersion::V1, .. } => return Ok(()),
+            MembershipAction::Leave { group, version: IgmpVersion::V2 } =>
+                (multicast::ALL_ROUTERS, IgmpType::V2LeaveGroup, group, Vec::new()),
+            MembershipAction::Leave { group, version: IgmpVersion::V3 } => (
+                multicast::ALL_IGMPV3_ROUTERS,
+                IgmpType::V3MembershipReport,
+                [0; 4],
+                vec![igmp::group_record(igmp::RECORD_CHANGE_TO_INCLUDE_MODE, &group)],
+            ),
+        };
+        println!("Sending IGMP {:?} for {:?}", igmp_type, action);
+
+        // Reports may go out before an address has been claimed (RFC 3376 4.2.13)
+        let mut source = self.source_address_for(&destination).unwrap_or([0; 4]);
+        let records = records.concat();
+        let ip_options = [Ipv4Option::RouterAlert(0)];
+
+        let mut packet = vec![0_u8; 20 + options::padded_len(&ip_options) + 8 + records.len()];
+        let mut ipv4_packet = Ipv4Packet::new_with_options(
+            &mut packet,
+            &(&mut source).into(),
+            &(&mut destination).into(),
+            &ip_options,
+        );
+        ipv4_packet.header().set_identification(self.next_identification());
+        ipv4_packet.header().set_time_to_live(1);
+
+        let mut igmp_packet = IgmpPacket::new(ipv4_packet.take_payload_buffer());
+        igmp_packet.set_igmp_type(igmp_type);
+        if igmp_type == IgmpType::V3MembershipReport {
+            igmp_packet.set_number_of_records((records.len() / 8) as u16);
+        } else {
+            igmp_packet.set_group(&group);
+        }
+        igmp_packet.set_data(&records);
+        igmp_packet.calculate_checksum();
+
+        ipv4_packet.set_payload(IpPayload::IGMP(igmp_packet));
+        ipv4_packet.header().calculate_checksum();
+        self.send_ipv4(now, &mut packet, tx_buffer)
+    }
+
+    /// Hand a fragment to the reassembler and process the datagram once it is complete
+    fn process_fragment(
+        &mut self,
+        now: Instant,
+        ipv4_packet: &mut Ipv4Packet,
+        forwardable: bool,
+        tx_buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        self.stats.rx_fragments += 1;
+
+        let header = ipv4_packet.header();
+        let key = FragmentKey {
+            source: header.source_ip().get_address(),
+            destination: header.destination_ip().get_address(),
+            identification: header.identification(),
+            protocol: header.protocol_number(),
+        };
+        let offset = header.fragment_offset() as usize * 8;
+        let more_fragments = header.more_fragments();
+        let header_bytes = header.to_bytes();
+
+        let data = match ipv4_packet.payload() {
+            IpPayload::Fragment(data) => data,
+            _ => return Ok(()),
+        };
+
+        let mut datagram = match self.reassembler.insert(now, key, &header_bytes, offset, more_fragments, data) {
+            Some(datagram) => datagram,
+            None => return Ok(()),
+        };
+        self.stats.rx_reassembled += 1;
+        println!("Reassembled a datagram of {} bytes", datagram.len());
+
+        if self.filter.is_active(Chain::Ingress) {
+            let info = PacketInfo::from_ipv4(&datagram);
+            if !self.filter_ingress(now, &info, &mut datagram, tx_buffer)? {
+                return Ok(());
+            }
+        }
+
+        match Ipv4Packet::try_from(datagram.as_mut_slice()) {
+            Ok(mut datagram) => self.process_ipv4(now, &mut datagram, forwardable, tx_buffer),
+            Err(err) => {
+                println!("Dropping malformed reassembled datagram: {:?}", err);
+                self.stats.rx_malformed += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Run a received frame or reassembled datagram through the ingress rules, answering it if they reject it.
+    /// `packet` is the IPv4 packet, if any. Returns whether to go on processing it.
+    fn filter_ingress(&mut self, now: Instant, info: &PacketInfo, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<bool, Error> {
+        let verdict = self.filter.evaluate(Chain::Ingress, info);
+        if verdict == Verdict::Accept {
+            return Ok(true);
+        }
+
+        println!("Filter denied receiving {} bytes", packet.len());
+        self.stats.rx_denied += 1;
+        if let (Verdict::Reject(rejection), Some(_)) = (verdict, info.protocol) {
+            self.reject(now, rejection, packet, tx_buffer)?;
+        }
+        Ok(false)
+    }
+
+    /// Run the timers, then receive a single frame from the device and process it.
+    /// Returns false if the device had no frame available.
+    pub fn poll(&mut self, now: Instant, rx_buffer: &mut [u8], tx_buffer: &mut [u8]) -> Result<bool, Error> {
+        self.process_timers(now, tx_buffer)?;
+
+        let size = self.device.receive(rx_buffer)?;
+        if size == 0 {
+            return Ok(false);
+        }
+
+        self.update(now, &mut rx_buffer[..size], tx_buffer)?;
+        Ok(true)
+    }
+
+    pub fn update(&mut self, now: Instant, rx_buffer: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        println!("Received {} bytes", rx_buffer.len());
+        self.stats.rx_frames += 1;
+
+        // Fragments are let through to be reassembled, the datagram is filtered once it is complete
+        if self.filter.is_active(Chain::Ingress) {
+            let info = self.packet_info(rx_buffer);
+            let link_header_len = self.link_header_len();
+            if !info.fragment && !self.filter_ingress(now, &info, &mut rx_buffer[link_header_len..], tx_buffer)? {
+                return Ok(());
+            }
+        }
+
+        // Point-to-point links without Ethernet headers only carry IPv4 for us
+        if self.link_header_len() == 0 {
+            return match Ipv4Packet::try_from(rx_buffer) {
+                Ok(mut ipv4_packet) => self.process_ipv4(now, &mut ipv4_packet, true, tx_buffer),
+                Err(err) => {
+                    println!("Dropping malformed packet: {:?}", err);
+                    self.stats.rx_malformed += 1;
+                    Ok(())
                 }
+            };
+        }
+
+        let mut frame = match EthernetFrame::try_from(rx_buffer) {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("Dropping malformed frame: {:?}", err);
+                self.stats.rx_malformed += 1;
+                return Ok(());
             }
+        };
+        println!("{:#x?}", &frame);
+        let destination_mac = frame.destination_mac().get_address();
+        let to_us = destination_mac == self.config.mac;
+
+        // Frames for multicast groups we haven't joined are left to the hosts that did
+        let multicast = destination_mac[0] & 0x01 != 0 && destination_mac != [0xFF; 6];
+        if multicast && !self.multicast.accepts_mac(&destination_mac) {
+            self.stats.rx_filtered += 1;
+            return Ok(());
+        }
 
+        match frame.payload() {
+            Payload::ARP(ref mut arp_packet) => {
+                self.process_arp(now, arp_packet, tx_buffer)?
+            },
+            Payload::IPv4(ref mut ipv4_packet) => {
+                self.process_ipv4(now, ipv4_packet, to_us, tx_buffer)?
+            },
+            _ => {}
         }
-        _ => {}
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Milliseconds since midnight UT, as used in the IPv4 timestamp option
+fn timestamp() -> u32 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_millis() % (24 * 60 * 60 * 1000)) as u32
+}
+
+/// Whether `address` can name a single host: not unspecified, broadcast, loopback or multicast
+fn is_unicast(address: &[u8; 4]) -> bool {
+    address::Ipv4Address(*address).is_unicast()
+}
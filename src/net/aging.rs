@@ -0,0 +1,100 @@
+use std::time::Instant;
+
+use heapless::{ArrayLength, Bucket, FnvIndexMap, Pos, PowerOfTwo};
+
+/// Entries that stop being valid at some point
+pub trait Expires {
+    fn expires_at(&self) -> Instant;
+}
+
+/// Fixed capacity map from IPv4 addresses to entries that expire, making room for new entries
+/// by evicting the one closest to expiry. The caches of an interface are built on it.
+pub struct AgingMap<V, N>
+where
+    N: ArrayLength<Bucket<[u8; 4], V>> + ArrayLength<Option<Pos>> + ArrayLength<[u8; 4]>,
+{
+    entries: FnvIndexMap<[u8; 4], V, N>,
+}
+
+impl<V, N> Default for AgingMap<V, N>
+where
+    V: Expires,
+    N: ArrayLength<Bucket<[u8; 4], V>> + ArrayLength<Option<Pos>> + ArrayLength<[u8; 4]> + PowerOfTwo,
+{
+    fn default() -> Self {
+        AgingMap::new()
+    }
+}
+
+impl<V, N> AgingMap<V, N>
+where
+    V: Expires,
+    N: ArrayLength<Bucket<[u8; 4], V>> + ArrayLength<Option<Pos>> + ArrayLength<[u8; 4]> + PowerOfTwo,
+{
+    pub fn new() -> AgingMap<V, N> {
+        AgingMap { entries: FnvIndexMap::new() }
+    }
+
+    /// The entry for `address` if it hasn't expired by `now`
+    pub fn get(&self, address: &[u8; 4], now: Instant) -> Option<&V> {
+        self.entries.get(address).filter(|entry| entry.expires_at() > now)
+    }
+
+    /// Whether there is an entry for the address, expired or not
+    pub fn contains(&self, address: &[u8; 4]) -> bool {
+        self.entries.contains_key(address)
+    }
+
+    /// Add or replace an entry. When the map is full, expired entries are dropped first,
+    /// then the entry closest to expiry if that didn't make room.
+    pub fn insert(&mut self, address: [u8; 4], entry: V, now: Instant) {
+        if !self.entries.contains_key(&address) && self.entries.len() == self.entries.capacity() {
+            self.expire(now);
+            if self.entries.len() == self.entries.capacity() {
+                let oldest = self.entries.iter()
+                    .min_by_key(|(_, entry)| entry.expires_at())
+                    .map(|(address, _)| *address);
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+
+        // There is always room after the eviction above
+        let _ = self.entries.insert(address, entry);
+    }
+
+    pub fn remove(&mut self, address: &[u8; 4]) -> Option<V> {
+        self.entries.remove(address)
+    }
+
+    /// Drop all entries that have expired by `now`, returning how many there were
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let expired: heapless::Vec<[u8; 4], N> = self.entries.iter()
+            .filter(|(_, entry)| entry.expires_at() <= now)
+            .map(|(address, _)| *address)
+            .collect();
+        for address in expired.iter() {
+            self.entries.remove(address);
+        }
+        expired.len()
+    }
+
+    pub fn clear(&mut self) {
+        // Not entries.clear(): in heapless 0.5.6 that goes through Vec::truncate, which drops each element
+        // through get_unchecked_mut(len) on a slice already shortened to len, out of bounds
+        self.entries = FnvIndexMap::new();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8; 4], &V)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
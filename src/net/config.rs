@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::address::{Ipv4Address, Ipv4Cidr};
+use crate::error::AddressParseError;
+
+/// IPv4 address assigned to an interface, together with the prefix length of its subnet
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub struct InterfaceAddress {
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+}
+
+impl InterfaceAddress {
+    pub fn new(address: [u8; 4], prefix_len: u8) -> InterfaceAddress {
+        assert!(prefix_len <= 32);
+        InterfaceAddress { address, prefix_len }
+    }
+
+    pub fn cidr(&self) -> Ipv4Cidr {
+        Ipv4Cidr::new(Ipv4Address(self.address), self.prefix_len)
+    }
+
+    pub fn netmask(&self) -> [u8; 4] {
+        self.cidr().netmask().octets()
+    }
+
+    /// The subnet itself, with the host bits cleared
+    pub fn network(&self) -> InterfaceAddress {
+        self.cidr().network().into()
+    }
+
+    /// Directed broadcast address of the subnet, `None` for /31 and /32 which have none
+    pub fn broadcast(&self) -> Option<[u8; 4]> {
+        self.cidr().broadcast().map(|broadcast| broadcast.octets())
+    }
+
+    /// Whether `address` is inside this address's subnet
+    pub fn contains(&self, address: &[u8; 4]) -> bool {
+        self.cidr().contains(&Ipv4Address(*address))
+    }
+}
+
+impl From<Ipv4Cidr> for InterfaceAddress {
+    fn from(cidr: Ipv4Cidr) -> InterfaceAddress {
+        InterfaceAddress::new(cidr.address().octets(), cidr.prefix_len())
+    }
+}
+
+impl From<InterfaceAddress> for Ipv4Cidr {
+    fn from(address: InterfaceAddress) -> Ipv4Cidr {
+        address.cidr()
+    }
+}
+
+impl FromStr for InterfaceAddress {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Ipv4Cidr>().map(InterfaceAddress::from)
+    }
+}
+
+impl fmt::Display for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.cidr(), f)
+    }
+}
+
+impl fmt::Debug for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.cidr(), f)
+    }
+}
+
+/// Smallest MTU every IPv4 link has to support (RFC 791), anything less can't carry a fragment with a full header
+pub const MIN_MTU: usize = 68;
+
+#[derive(Debug, Clone)]
+pub struct InterfaceConfig {
+    pub mac: [u8; 6],
+    pub addresses: Vec<InterfaceAddress>,
+    /// Values below `MIN_MTU` are treated as `MIN_MTU`
+    pub mtu: usize,
+    pub gateway: Option<[u8; 4]>,
+    /// Probe for conflicts before using an address and defend it afterwards
+    pub duplicate_address_detection: bool,
+    /// Prefixes of other hosts whose ARP requests are answered with our hardware address
+    pub proxy_arp: Vec<InterfaceAddress>,
+    /// Hand packets for other hosts to the `Router` the interface is attached to
+    pub forwarding: bool,
+    /// Rewrite the source of packets forwarded out of this interface to its own address,
+    /// and replies to them back to the host that sent the original
+    pub masquerade: bool,
+    /// Answer ICMP echo requests sent to a broadcast or multicast address, which RFC 1122 allows ignoring
+    pub answer_broadcast_echo: bool,
+}
+
+impl InterfaceConfig {
+    pub fn has_address(&self, address: &[u8; 4]) -> bool {
+        self.addresses.iter().any(|a| &a.address == address)
+    }
+
+    /// Whether `address` is the limited broadcast address or the directed broadcast address of one of our subnets
+    pub fn is_broadcast(&self, address: &[u8; 4]) -> bool {
+        address == &[0xFF; 4] || self.addresses.iter().any(|a| a.broadcast().as_ref() == Some(address))
+    }
+
+    /// Whether ARP requests for `address` should be answered on its behalf
+    pub fn is_proxied(&self, address: &[u8; 4]) -> bool {
+        !self.has_address(address) && self.proxy_arp.iter().any(|prefix| prefix.contains(address))
+    }
+}
+
+impl Default for InterfaceConfig {
+    fn default() -> Self {
+        InterfaceConfig {
+            mac: [0x02, 0xDE, 0xAD, 0x00, 0xBE, 0xEF],
+            addresses: vec![InterfaceAddress::new([169, 254, 0, 2], 24)],
+            mtu: 1500,
+            gateway: None,
+            duplicate_address_detection: true,
+            proxy_arp: Vec::new(),
+            forwarding: false,
+            masquerade: false,
+            answer_broadcast_echo: false,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use crate::net::config::InterfaceAddress;
+
+/// Number of probes sent before claiming an address
+pub const PROBE_NUM: u8 = 3;
+/// Delay between probes
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay after the last probe before the address is claimed
+pub const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+/// Number of announcements sent after claiming an address
+pub const ANNOUNCE_NUM: u8 = 2;
+/// Delay between announcements
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+/// Minimum time between defending an address, a second conflict within it releases the address
+pub const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ClaimState {
+    /// Probing whether another host uses the address. The first probe goes out on the next poll.
+    Probing { sent: u8, next_at: Option<Instant> },
+    /// The address is ours and is being announced
+    Announcing { sent: u8, next_at: Instant },
+    Claimed,
+}
+
+/// Something that needs to be sent for an address
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ClaimAction {
+    Probe([u8; 4]),
+    Announce([u8; 4]),
+    /// Probing finished without conflicts
+    Claimed([u8; 4]),
+}
+
+/// How a conflict with another host was resolved
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ConflictAction {
+    /// The address was still being probed and won't be used
+    Refused,
+    /// An announcement was sent to reclaim the address
+    Defended,
+    /// The address was already defended recently and has been given up
+    Released,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Claim {
+    address: [u8; 4],
+    state: ClaimState,
+    last_defended: Option<Instant>,
+}
+
+/// Duplicate address detection state of every address configured on an interface,
+/// claimed with ARP probes and announcements as described in RFC 5227
+#[derive(Debug, Default)]
+pub struct AddressClaims {
+    claims: Vec<Claim>,
+}
+
+impl AddressClaims {
+    pub fn new() -> AddressClaims {
+        AddressClaims::default()
+    }
+
+    /// Start probing addresses that were added to `addresses` and forget ones that were removed.
+    /// With `probe` disabled, new addresses are claimed right away.
+    pub fn sync(&mut self, addresses: &[InterfaceAddress], probe: bool) {
+        self.claims.retain(|c| addresses.iter().any(|a| a.address == c.address));
+
+        for address in addresses {
+            if self.claims.iter().any(|c| c.address == address.address) {
+                continue;
+            }
+
+            self.claims.push(Claim {
+                address: address.address,
+                state: if probe {
+                    ClaimState::Probing { sent: 0, next_at: None }
+                } else {
+                    ClaimState::Claimed
+                },
+                last_defended: None,
+            });
+        }
+    }
+
+    pub fn state(&self, address: &[u8; 4]) -> Option<ClaimState> {
+        self.claims.iter().find(|c| &c.address == address).map(|c| c.state)
+    }
+
+    /// Whether the address has been claimed and may be used
+    pub fn is_usable(&self, address: &[u8; 4]) -> bool {
+        match self.state(address) {
+            Some(ClaimState::Announcing { .. }) | Some(ClaimState::Claimed) => true,
+            Some(ClaimState::Probing { .. }) | None => false,
+        }
+    }
+
+    /// Whether the address is still being probed
+    pub fn is_tentative(&self, address: &[u8; 4]) -> bool {
+        matches!(self.state(address), Some(ClaimState::Probing { .. }))
+    }
+
+    /// Advance the probe and announcement timers
+    pub fn poll(&mut self, now: Instant) -> Vec<ClaimAction> {
+        let mut actions = Vec::new();
+
+        for claim in self.claims.iter_mut() {
+            match claim.state {
+                ClaimState::Probing { sent, next_at } if next_at.is_none_or(|at| at <= now) => {
+                    if sent < PROBE_NUM {
+                        let wait = if sent + 1 == PROBE_NUM { ANNOUNCE_WAIT } else { PROBE_INTERVAL };
+                        claim.state = ClaimState::Probing { sent: sent + 1, next_at: Some(now + wait) };
+                        actions.push(ClaimAction::Probe(claim.address));
+                    } else {
+                        claim.state = ClaimState::Announcing { sent: 1, next_at: now + ANNOUNCE_INTERVAL };
+                        actions.push(ClaimAction::Claimed(claim.address));
+                        actions.push(ClaimAction::Announce(claim.address));
+                    }
+                }
+                ClaimState::Announcing { sent, next_at } if next_at <= now => {
+                    claim.state = if sent + 1 < ANNOUNCE_NUM {
+                        ClaimState::Announcing { sent: sent + 1, next_at: now + ANNOUNCE_INTERVAL }
+                    } else {
+                        ClaimState::Claimed
+                    };
+                    actions.push(ClaimAction::Announce(claim.address));
+                }
+                _ => {}
+            }
+        }
+
+        actions
+    }
+
+    /// Earliest time at which `poll` has something to do
+    pub fn next_poll(&self) -> Option<Instant> {
+        self.claims.iter()
+            .filter_map(|c| match c.state {
+                ClaimState::Probing { next_at, .. } => Some(next_at.unwrap_or_else(Instant::now)),
+                ClaimState::Announcing { next_at, .. } => Some(next_at),
+                ClaimState::Claimed => None,
+            })
+            .min()
+    }
+
+    /// Another host was seen using `address`. Refused and released addresses are forgotten,
+    /// the caller is expected to remove them from the interface configuration.
+    pub fn conflict(&mut self, address: &[u8; 4], now: Instant) -> Option<ConflictAction> {
+        let index = self.claims.iter().position(|c| &c.address == address)?;
+        let claim = &mut self.claims[index];
+
+        let action = match claim.state {
+            ClaimState::Probing { .. } => ConflictAction::Refused,
+            _ => match claim.last_defended {
+                Some(at) if now < at + DEFEND_INTERVAL => ConflictAction::Released,
+                _ => {
+                    claim.last_defended = Some(now);
+                    ConflictAction::Defended
+                }
+            },
+        };
+
+        if action != ConflictAction::Defended {
+            self.claims.remove(index);
+        }
+
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 4] = [169, 254, 0, 2];
+
+    fn probing() -> AddressClaims {
+        let mut claims = AddressClaims::new();
+        claims.sync(&[InterfaceAddress::new(ADDRESS, 16)], true);
+        claims
+    }
+
+    /// Probe and announce until the address is claimed, returning when that finished
+    fn claim(claims: &mut AddressClaims, start: Instant) -> Instant {
+        let mut now = start;
+        while let Some(next) = claims.next_poll() {
+            now = now.max(next);
+            claims.poll(now);
+        }
+        now
+    }
+
+    #[test]
+    fn probes_then_announces_then_claims() {
+        let mut claims = probing();
+        let start = Instant::now();
+        assert!(claims.is_tentative(&ADDRESS));
+        assert!(!claims.is_usable(&ADDRESS));
+
+        assert_eq!(claims.poll(start), vec![ClaimAction::Probe(ADDRESS)]);
+        assert!(claims.poll(start).is_empty());
+        assert_eq!(claims.next_poll(), Some(start + PROBE_INTERVAL));
+        assert_eq!(claims.poll(start + PROBE_INTERVAL), vec![ClaimAction::Probe(ADDRESS)]);
+        let last_probe = start + PROBE_INTERVAL * 2;
+        assert_eq!(claims.poll(last_probe), vec![ClaimAction::Probe(ADDRESS)]);
+        assert_eq!(claims.next_poll(), Some(last_probe + ANNOUNCE_WAIT));
+        assert!(claims.is_tentative(&ADDRESS));
+
+        let claimed_at = last_probe + ANNOUNCE_WAIT;
+        assert_eq!(claims.poll(claimed_at), vec![ClaimAction::Claimed(ADDRESS), ClaimAction::Announce(ADDRESS)]);
+        assert!(claims.is_usable(&ADDRESS));
+        assert!(!claims.is_tentative(&ADDRESS));
+        assert_eq!(claims.poll(claimed_at + ANNOUNCE_INTERVAL), vec![ClaimAction::Announce(ADDRESS)]);
+        assert_eq!(claims.state(&ADDRESS), Some(ClaimState::Claimed));
+        assert_eq!(claims.next_poll(), None);
+    }
+
+    #[test]
+    fn claims_right_away_without_probing() {
+        let mut claims = AddressClaims::new();
+        claims.sync(&[InterfaceAddress::new(ADDRESS, 16)], false);
+        assert_eq!(claims.state(&ADDRESS), Some(ClaimState::Claimed));
+        assert!(claims.poll(Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn sync_keeps_existing_claims_and_forgets_removed_addresses() {
+        let mut claims = probing();
+        claims.poll(Instant::now());
+        let other = [10, 0, 0, 1];
+        claims.sync(&[InterfaceAddress::new(ADDRESS, 16), InterfaceAddress::new(other, 8)], true);
+        assert!(matches!(claims.state(&ADDRESS), Some(ClaimState::Probing { sent: 1, .. })));
+        assert_eq!(claims.state(&other), Some(ClaimState::Probing { sent: 0, next_at: None }));
+
+        claims.sync(&[InterfaceAddress::new(other, 8)], true);
+        assert_eq!(claims.state(&ADDRESS), None);
+    }
+
+    #[test]
+    fn conflicts_while_probing_refuse_the_address() {
+        let mut claims = probing();
+        claims.poll(Instant::now());
+        assert_eq!(claims.conflict(&ADDRESS, Instant::now()), Some(ConflictAction::Refused));
+        assert_eq!(claims.state(&ADDRESS), None);
+        assert_eq!(claims.conflict(&[10, 0, 0, 1], Instant::now()), None);
+    }
+
+    #[test]
+    fn claimed_addresses_are_defended_once_per_interval() {
+        let mut claims = probing();
+        let now = claim(&mut claims, Instant::now());
+        assert_eq!(claims.state(&ADDRESS), Some(ClaimState::Claimed));
+
+        assert_eq!(claims.conflict(&ADDRESS, now), Some(ConflictAction::Defended));
+        assert_eq!(claims.conflict(&ADDRESS, now + DEFEND_INTERVAL), Some(ConflictAction::Defended));
+        assert_eq!(claims.conflict(&ADDRESS, now + DEFEND_INTERVAL + Duration::from_secs(1)), Some(ConflictAction::Released));
+        assert!(!claims.is_usable(&ADDRESS));
+    }
+}
@@ -0,0 +1,421 @@
+use std::convert::{TryFrom, TryInto};
+
+use crate::net::config::InterfaceAddress;
+use crate::protocols::ethernet::{self, EtherType};
+use crate::protocols::icmp::IcmpType;
+use crate::protocols::ipv4::{IpPayload, IpProtocol, Ipv4Packet};
+
+/// Where in the stack a packet is being filtered
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Chain {
+    /// Frames received by an interface, before anything else looks at them
+    Ingress,
+    /// Frames about to be transmitted by an interface
+    Egress,
+    /// IPv4 packets a `Router` is about to send on to another host
+    Forward,
+}
+
+/// How to turn a rejected packet's sender away
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Rejection {
+    /// ICMP destination unreachable with this code
+    IcmpUnreachable(u8),
+    /// TCP reset, for TCP segments. Other packets get an ICMP port unreachable instead.
+    TcpReset,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Action {
+    Accept,
+    Drop,
+    /// Drop the packet and tell the sender. Acts like drop on egress, where the sender is ourselves.
+    Reject(Rejection),
+    /// Print the packet and go on with the next rule
+    Log,
+}
+
+/// Outcome of running a packet through a chain
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Reject(Rejection),
+}
+
+/// ICMP destination unreachable code sent when rejecting with a TCP reset isn't possible
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+
+/// What rules can match a packet on. Fields the packet doesn't have never match.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct PacketInfo {
+    pub ethertype: Option<u16>,
+    pub source_mac: Option<[u8; 6]>,
+    pub destination_mac: Option<[u8; 6]>,
+    pub source: Option<[u8; 4]>,
+    pub destination: Option<[u8; 4]>,
+    pub protocol: Option<u8>,
+    /// Part of a fragmented datagram. Only the first fragment carries the transport header,
+    /// later ones have no ports or ICMP type and never match rules on them.
+    pub fragment: bool,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    pub icmp_type: Option<IcmpType>,
+    /// Router interface index the packet arrived on, for forwarded packets
+    pub in_interface: Option<usize>,
+    /// Router interface index the packet leaves through, for forwarded packets
+    pub out_interface: Option<usize>,
+}
+
+impl PacketInfo {
+    /// Describe an Ethernet frame. Frames that can't be parsed only have what could be read.
+    pub fn from_frame(frame: &[u8]) -> PacketInfo {
+        if frame.len() < ethernet::HEADER_LEN {
+            return PacketInfo::default();
+        }
+
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        let mut info = if ethertype == EtherType::IPv4 as u16 {
+            PacketInfo::from_ipv4(&frame[ethernet::HEADER_LEN..])
+        } else {
+            PacketInfo::default()
+        };
+        info.ethertype = Some(ethertype);
+        info.destination_mac = frame[0..6].try_into().ok();
+        info.source_mac = frame[6..12].try_into().ok();
+        info
+    }
+
+    /// Describe an IPv4 packet without its link layer
+    pub fn from_ipv4(packet: &[u8]) -> PacketInfo {
+        let mut bytes = packet.to_vec();
+        match Ipv4Packet::try_from(bytes.as_mut_slice()) {
+            Ok(mut ipv4_packet) => PacketInfo::from_ipv4_packet(&mut ipv4_packet),
+            Err(_) => PacketInfo::default(),
+        }
+    }
+
+    fn from_ipv4_packet(ipv4_packet: &mut Ipv4Packet) -> PacketInfo {
+        let header = ipv4_packet.header();
+        let mut info = PacketInfo {
+            source: Some(header.source_ip().get_address()),
+            destination: Some(header.destination_ip().get_address()),
+            protocol: Some(header.protocol_number()),
+            fragment: header.is_fragment(),
+            ..PacketInfo::default()
+        };
+        let first_fragment = header.fragment_offset() == 0;
+        let protocol = header.protocol();
+
+        match ipv4_packet.payload() {
+            IpPayload::TCP(tcp_packet) => {
+                info.source_port = Some(tcp_packet.source_port());
+                info.destination_port = Some(tcp_packet.destination_port());
+            },
+            IpPayload::UDP(udp_packet) => {
+                info.source_port = Some(udp_packet.source_port());
+                info.destination_port = Some(udp_packet.destination_port());
+            },
+            IpPayload::ICMP(icmp_packet) => info.icmp_type = Some(icmp_packet.icmp_type()),
+            // TCP and UDP both start with the ports, ICMP with the type
+            IpPayload::Fragment(data) if first_fragment => match protocol {
+                IpProtocol::TCP | IpProtocol::UDP if data.len() >= 4 => {
+                    info.source_port = Some(u16::from_be_bytes([data[0], data[1]]));
+                    info.destination_port = Some(u16::from_be_bytes([data[2], data[3]]));
+                },
+                IpProtocol::ICMP if !data.is_empty() => info.icmp_type = Some(IcmpType::from(data[0])),
+                _ => {},
+            },
+            _ => {},
+        }
+        info
+    }
+}
+
+/// Conditions a packet has to meet for a rule to apply, `None` matching anything
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct Match {
+    pub ethertype: Option<u16>,
+    pub source_mac: Option<[u8; 6]>,
+    pub destination_mac: Option<[u8; 6]>,
+    pub source: Option<InterfaceAddress>,
+    pub destination: Option<InterfaceAddress>,
+    pub protocol: Option<u8>,
+    /// Inclusive range of source ports
+    pub source_ports: Option<(u16, u16)>,
+    /// Inclusive range of destination ports
+    pub destination_ports: Option<(u16, u16)>,
+    pub icmp_type: Option<IcmpType>,
+    pub in_interface: Option<usize>,
+    pub out_interface: Option<usize>,
+}
+
+impl Match {
+    pub fn matches(&self, info: &PacketInfo) -> bool {
+        fn field<T: PartialEq>(wanted: &Option<T>, actual: &Option<T>) -> bool {
+            wanted.is_none() || wanted == actual
+        }
+        fn prefix(wanted: &Option<InterfaceAddress>, actual: &Option<[u8; 4]>) -> bool {
+            match (wanted, actual) {
+                (None, _) => true,
+                (Some(wanted), Some(actual)) => wanted.contains(actual),
+                (Some(_), None) => false,
+            }
+        }
+        fn ports(wanted: &Option<(u16, u16)>, actual: &Option<u16>) -> bool {
+            match (wanted, actual) {
+                (None, _) => true,
+                (Some((first, last)), Some(actual)) => first <= actual && actual <= last,
+                (Some(_), None) => false,
+            }
+        }
+
+        field(&self.ethertype, &info.ethertype)
+            && field(&self.source_mac, &info.source_mac)
+            && field(&self.destination_mac, &info.destination_mac)
+            && prefix(&self.source, &info.source)
+            && prefix(&self.destination, &info.destination)
+            && field(&self.protocol, &info.protocol)
+            && ports(&self.source_ports, &info.source_port)
+            && ports(&self.destination_ports, &info.destination_port)
+            && field(&self.icmp_type, &info.icmp_type)
+            && field(&self.in_interface, &info.in_interface)
+            && field(&self.out_interface, &info.out_interface)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Rule {
+    pub chain: Chain,
+    pub matches: Match,
+    pub action: Action,
+    /// Packets the rule matched
+    pub hits: u64,
+}
+
+impl Rule {
+    pub fn new(chain: Chain, matches: Match, action: Action) -> Rule {
+        Rule { chain, matches, action, hits: 0 }
+    }
+}
+
+/// Ordered rules deciding what happens to packets, the first accept, drop or reject to match wins.
+/// Interfaces run their ingress and egress rules, a `Router` its forward rules.
+#[derive(Debug)]
+pub struct Filter {
+    rules: Vec<Rule>,
+    /// Verdicts for packets no rule decided on, by chain
+    policies: [Verdict; 3],
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            rules: Vec::new(),
+            policies: [Verdict::Accept; 3],
+        }
+    }
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// Append a rule, returning its index
+    pub fn add(&mut self, rule: Rule) -> usize {
+        self.rules.push(rule);
+        self.rules.len() - 1
+    }
+
+    /// Insert a rule before the one at `index`. Panics if `index` is past the end.
+    pub fn insert(&mut self, index: usize, rule: Rule) {
+        self.rules.insert(index, rule);
+    }
+
+    /// Remove the rule at `index`. Panics if there is none.
+    pub fn remove(&mut self, index: usize) -> Rule {
+        self.rules.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn reset_counters(&mut self) {
+        for rule in self.rules.iter_mut() {
+            rule.hits = 0;
+        }
+    }
+
+    pub fn policy(&self, chain: Chain) -> Verdict {
+        self.policies[chain as usize]
+    }
+
+    /// Set what happens to packets on `chain` that no rule decides on
+    pub fn set_policy(&mut self, chain: Chain, verdict: Verdict) {
+        self.policies[chain as usize] = verdict;
+    }
+
+    /// Whether evaluating `chain` can come to anything but accept
+    pub fn is_active(&self, chain: Chain) -> bool {
+        self.policy(chain) != Verdict::Accept || self.rules.iter().any(|r| r.chain == chain)
+    }
+
+    /// Run a packet through the rules of `chain`, counting the hits
+    pub fn evaluate(&mut self, chain: Chain, info: &PacketInfo) -> Verdict {
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            if rule.chain != chain || !rule.matches.matches(info) {
+                continue;
+            }
+            rule.hits += 1;
+
+            match rule.action {
+                Action::Accept => return Verdict::Accept,
+                Action::Drop => return Verdict::Drop,
+                Action::Reject(rejection) => return Verdict::Reject(rejection),
+                Action::Log => println!("Filter {:?} rule {} matched {:x?}", chain, index, info),
+            }
+        }
+        self.policy(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::device::Device;
+    use crate::net::tests::*;
+
+    fn port_rule(chain: Chain, port: u16, action: Action) -> Rule {
+        let matches = Match { protocol: Some(PROTOCOL_TCP), destination_ports: Some((port, port)), ..Match::default() };
+        Rule::new(chain, matches, action)
+    }
+
+    /// A SYN to `port` with 16 bytes of data, in fragments of 16 bytes so the first one only has the ports
+    fn fragmented_syn(source: [u8; 4], destination: [u8; 4], port: u16) -> Vec<Vec<u8>> {
+        ipv4_fragments(source, destination, PROTOCOL_TCP, 7, &tcp(1000, port, 0x02, &[0; 16]), 16)
+    }
+
+    #[test]
+    fn reads_ports_from_whole_datagrams_and_first_fragments() {
+        let whole = PacketInfo::from_ipv4(&ipv4(PEER_IP, STACK_IP, PROTOCOL_TCP, 1, 0, &tcp(1000, 22, 0x02, &[])));
+        assert_eq!((whole.source_port, whole.destination_port, whole.fragment), (Some(1000), Some(22), false));
+
+        let fragments = fragmented_syn(PEER_IP, STACK_IP, 22);
+        let first = PacketInfo::from_ipv4(&fragments[0]);
+        assert_eq!((first.source_port, first.destination_port, first.fragment), (Some(1000), Some(22), true));
+        let later = PacketInfo::from_ipv4(&fragments[1]);
+        assert_eq!((later.source_port, later.destination_port, later.fragment), (None, None, true));
+
+        let echo = ipv4_fragments(PEER_IP, STACK_IP, PROTOCOL_ICMP, 1, &echo_request(1, 1, 32), 16);
+        assert_eq!(PacketInfo::from_ipv4(&echo[0]).icmp_type, Some(IcmpType::EchoRequest));
+        assert_eq!(PacketInfo::from_ipv4(&echo[1]).icmp_type, None);
+    }
+
+    #[test]
+    fn matches_prefixes_and_port_ranges() {
+        let info = PacketInfo {
+            source: Some([10, 0, 1, 5]),
+            protocol: Some(PROTOCOL_TCP),
+            destination_port: Some(8080),
+            ..PacketInfo::default()
+        };
+        let rule = |source: &str, ports| Match {
+            source: Some(source.parse().unwrap()),
+            destination_ports: Some(ports),
+            ..Match::default()
+        };
+
+        assert!(Match::default().matches(&info));
+        assert!(rule("10.0.1.0/24", (8000, 8080)).matches(&info));
+        assert!(!rule("10.0.2.0/24", (8000, 8080)).matches(&info));
+        assert!(!rule("10.0.1.0/24", (8081, 9000)).matches(&info));
+        // Fields the packet doesn't have don't match
+        assert!(!Match { source_ports: Some((0, 65535)), ..Match::default() }.matches(&info));
+    }
+
+    #[test]
+    fn first_deciding_rule_wins_and_counts_hits() {
+        let mut filter = Filter::new();
+        filter.add(Rule::new(Chain::Ingress, Match::default(), Action::Log));
+        filter.add(port_rule(Chain::Ingress, 22, Action::Drop));
+        filter.add(port_rule(Chain::Ingress, 22, Action::Accept));
+        filter.add(port_rule(Chain::Egress, 80, Action::Drop));
+        filter.set_policy(Chain::Ingress, Verdict::Reject(Rejection::TcpReset));
+
+        let ssh = PacketInfo { protocol: Some(PROTOCOL_TCP), destination_port: Some(22), ..PacketInfo::default() };
+        let http = PacketInfo { destination_port: Some(80), ..ssh };
+        assert_eq!(filter.evaluate(Chain::Ingress, &ssh), Verdict::Drop);
+        assert_eq!(filter.evaluate(Chain::Ingress, &http), Verdict::Reject(Rejection::TcpReset));
+        assert_eq!(filter.evaluate(Chain::Forward, &http), Verdict::Accept);
+        assert_eq!(filter.rules().iter().map(|rule| rule.hits).collect::<Vec<_>>(), vec![2, 1, 0, 0]);
+
+        assert!(filter.is_active(Chain::Egress));
+        assert!(!filter.is_active(Chain::Forward));
+        filter.reset_counters();
+        assert!(filter.rules().iter().all(|rule| rule.hits == 0));
+    }
+
+    #[test]
+    fn ingress_port_rules_see_fragmented_datagrams() {
+        let (mut peer, mut interface) = interface();
+        interface.filter_mut().add(port_rule(Chain::Ingress, 22, Action::Reject(Rejection::TcpReset)));
+
+        for fragment in fragmented_syn(PEER_IP, STACK_IP, 22) {
+            peer.transmit(&ethernet(STACK_MAC, 0x0800, &fragment)).unwrap();
+            poll(&mut interface);
+        }
+        assert_eq!(interface.stats().rx_denied, 1);
+        let resets = receive_all(&mut peer);
+        assert_eq!(resets.len(), 1);
+        assert_eq!(resets[0][14 + 9], PROTOCOL_TCP);
+        assert_eq!(resets[0][14 + 20 + 13] & 0x04, 0x04);
+
+        for fragment in fragmented_syn(PEER_IP, STACK_IP, 80) {
+            peer.transmit(&ethernet(STACK_MAC, 0x0800, &fragment)).unwrap();
+            poll(&mut interface);
+        }
+        assert_eq!(interface.stats().rx_denied, 1);
+        assert_eq!(interface.stats().rx_reassembled, 2);
+    }
+
+    #[test]
+    fn ingress_icmp_rules_see_fragmented_datagrams() {
+        let (mut peer, mut interface) = interface();
+        let matches = Match { icmp_type: Some(IcmpType::EchoRequest), ..Match::default() };
+        interface.filter_mut().add(Rule::new(Chain::Ingress, matches, Action::Drop));
+
+        for fragment in ipv4_fragments(PEER_IP, STACK_IP, PROTOCOL_ICMP, 1, &echo_request(1, 1, 100), 64) {
+            peer.transmit(&ethernet(STACK_MAC, 0x0800, &fragment)).unwrap();
+            poll(&mut interface);
+        }
+        assert_eq!(interface.stats().rx_denied, 1);
+        assert!(receive_all(&mut peer).is_empty());
+    }
+
+    #[test]
+    fn forward_port_rules_see_fragmented_datagrams() {
+        let now = Instant::now();
+        let (mut link_a, mut link_b, mut router) = router(now);
+        router.filter_mut().add(port_rule(Chain::Forward, 22, Action::Drop));
+
+        for port in &[22, 80] {
+            for fragment in fragmented_syn(HOST_A.1, HOST_B.1, *port) {
+                link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &fragment)).unwrap();
+                poll_router(&mut router, now);
+            }
+        }
+        assert_eq!(router.stats().denied, 1);
+        let forwarded = receive_all(&mut link_b);
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(&forwarded[0][14 + 20 + 2..14 + 20 + 4], &80_u16.to_be_bytes());
+        assert!(receive_all(&mut link_a).is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use std::convert::TryFrom;
+
+use crate::protocols::ipv4::Ipv4Header;
+use crate::protocols::ipv4::options::{self, Ipv4Options};
+
+/// Options with this bit set in their kind are repeated in every fragment (RFC 791)
+const OPTION_COPIED: u8 = 0x80;
+
+/// Split the IPv4 packet into fragments of at most `mtu` bytes.
+/// Only the first fragment carries all options, the others just the ones flagged as copied.
+/// Fragmenting a fragment keeps its offset and MF flag intact.
+/// Panics if the packet is malformed or the MTU can't fit any payload next to the header.
+pub fn fragment(packet: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+    let header_length = (packet[0] & 0x0F) as usize * 4;
+    let (first_header, payload) = packet.split_at(header_length);
+
+    let mut first_header = first_header.to_vec();
+    let (base_offset, more_fragments) = {
+        let header = Ipv4Header::try_from(first_header.as_mut_slice()).expect("Fragmenting a malformed IPv4 packet");
+        (header.fragment_offset() as usize * 8, header.more_fragments())
+    };
+
+    let mut other_header = first_header[..20].to_vec();
+    let copied = Ipv4Options::new(&first_header[20..])
+        .filter_map(Result::ok)
+        .filter(|option| option.kind() & OPTION_COPIED != 0)
+        .collect::<Vec<_>>();
+    other_header.resize(20 + options::padded_len(&copied), 0);
+    options::emit(&copied, &mut other_header[20..]);
+    other_header[0] = (other_header[0] & 0xF0) | (other_header.len() / 4) as u8;
+
+    let mut fragments = Vec::new();
+    let mut offset = 0;
+    while offset < payload.len() {
+        let header = if offset == 0 { &first_header } else { &other_header };
+        assert!(mtu >= header.len() + 8, "MTU {} too small to fragment into", mtu);
+
+        // All but the last fragment carry a multiple of eight bytes
+        let space = (mtu - header.len()) / 8 * 8;
+        let end = (offset + space).min(payload.len());
+        let last = end == payload.len();
+
+        let mut fragment = header.clone();
+        fragment.extend_from_slice(&payload[offset..end]);
+
+        let length = fragment.len();
+        let mut fragment_header = Ipv4Header::try_from(&mut fragment[..header.len()])
+            .expect("Fragment header was copied from a valid one");
+        fragment_header.set_length(length as u16);
+        fragment_header.set_fragment_offset(((base_offset + offset) / 8) as u16);
+        fragment_header.set_more_fragments(!last || more_fragments);
+        fragment_header.calculate_checksum();
+
+        fragments.push(fragment);
+        offset = end;
+    }
+
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tests::{checksum, ipv4, ipv4_with_options, MORE_FRAGMENTS, PEER_IP, STACK_IP};
+
+    fn header_length(fragment: &[u8]) -> usize {
+        (fragment[0] & 0x0F) as usize * 4
+    }
+
+    /// Offset in bytes and MF flag of a fragment
+    fn position(fragment: &[u8]) -> (usize, bool) {
+        let field = u16::from_be_bytes([fragment[6], fragment[7]]);
+        ((field & 0x1FFF) as usize * 8, field & MORE_FRAGMENTS != 0)
+    }
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    /// Check every fragment's header and that the payloads put back together give `payload`
+    fn assert_fragments_of(fragments: &[Vec<u8>], payload: &[u8], base_offset: usize) {
+        let mut data = Vec::new();
+        for fragment in fragments {
+            let header_length = header_length(fragment);
+            assert_eq!(checksum(&fragment[..header_length]), [0, 0]);
+            assert_eq!(u16::from_be_bytes([fragment[2], fragment[3]]) as usize, fragment.len());
+            assert_eq!(position(fragment).0, base_offset + data.len());
+            data.extend_from_slice(&fragment[header_length..]);
+        }
+        assert_eq!(data, payload);
+    }
+
+    #[test]
+    fn splits_payloads_at_multiples_of_eight() {
+        let payload = payload(1000);
+        let packet = ipv4(PEER_IP, STACK_IP, 17, 1, 0, &payload);
+        // 100 bytes leave room for 80, not 76, bytes of payload
+        let fragments = fragment(&packet, 100);
+        assert_eq!(fragments.len(), 13);
+        assert!(fragments[..12].iter().all(|fragment| fragment.len() == 20 + 80));
+        assert_eq!(fragments[12].len(), 20 + 40);
+        assert_fragments_of(&fragments, &payload, 0);
+
+        let more = fragments.iter().map(|fragment| position(fragment).1).collect::<Vec<_>>();
+        assert!(more[..12].iter().all(|&more| more));
+        assert!(!more[12]);
+        assert!(fragments.iter().all(|fragment| fragment[4..6] == packet[4..6]));
+    }
+
+    #[test]
+    fn packets_that_fit_are_left_whole() {
+        let packet = ipv4(PEER_IP, STACK_IP, 17, 1, 0, &payload(80));
+        assert_eq!(fragment(&packet, 100), vec![packet]);
+    }
+
+    #[test]
+    fn refragmenting_keeps_the_offset_and_mf_flag() {
+        let payload = payload(200);
+        let middle = ipv4(PEER_IP, STACK_IP, 17, 1, MORE_FRAGMENTS | (800 / 8), &payload);
+        let fragments = fragment(&middle, 100);
+        assert_eq!(fragments.len(), 3);
+        assert_fragments_of(&fragments, &payload, 800);
+        assert!(fragments.iter().all(|fragment| position(fragment).1));
+
+        let last = ipv4(PEER_IP, STACK_IP, 17, 1, 800 / 8, &payload);
+        let fragments = fragment(&last, 100);
+        assert_fragments_of(&fragments, &payload, 800);
+        assert_eq!(fragments.iter().map(|fragment| position(fragment).1).collect::<Vec<_>>(), vec![true, true, false]);
+    }
+
+    #[test]
+    fn later_fragments_only_carry_copied_options() {
+        // Loose source route, which is copied, then record route, which isn't
+        let options = [131, 7, 4, 10, 0, 0, 1, 7, 7, 4, 0, 0, 0, 0, 0, 0];
+        let payload = payload(200);
+        let packet = ipv4_with_options(PEER_IP, STACK_IP, 17, &options, &payload);
+        let fragments = fragment(&packet, 100);
+        assert_fragments_of(&fragments, &payload, 0);
+
+        assert_eq!(&fragments[0][20..36], &options);
+        for fragment in &fragments[1..] {
+            assert_eq!(header_length(fragment), 28);
+            assert_eq!(&fragment[20..28], &[131, 7, 4, 10, 0, 0, 1, 0]);
+        }
+        // The first fragment has less room for payload next to the longer header
+        assert_eq!(fragments[0].len(), 36 + 64);
+        assert_eq!(fragments[1].len(), 28 + 72);
+    }
+}
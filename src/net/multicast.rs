@@ -0,0 +1,377 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::address::Ipv4Address;
+use crate::protocols::igmp::IgmpVersion;
+
+/// Group every host belongs to without joining or reporting it (RFC 2236)
+pub const ALL_SYSTEMS: [u8; 4] = [224, 0, 0, 1];
+/// Destination of IGMPv2 leave messages
+pub const ALL_ROUTERS: [u8; 4] = [224, 0, 0, 2];
+/// Destination of IGMPv3 reports
+pub const ALL_IGMPV3_ROUTERS: [u8; 4] = [224, 0, 0, 22];
+/// Number of times unsolicited reports and leaves are sent (Robustness Variable)
+pub const ROBUSTNESS: u8 = 2;
+/// Delay between the repetitions of unsolicited reports and leaves
+pub const UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long an IGMPv1 or IGMPv2 querier is assumed present after its last query (RFC 3376 8.12)
+pub const OLDER_VERSION_QUERIER_TIMEOUT: Duration = Duration::from_secs(260);
+/// Maximum response time of IGMPv1 queries, which don't carry one
+pub const IGMPV1_MAX_RESPONSE_TIME: Duration = Duration::from_secs(10);
+
+/// Whether `address` is an IPv4 multicast group
+pub fn is_multicast(address: &[u8; 4]) -> bool {
+    Ipv4Address(*address).is_multicast()
+}
+
+/// Ethernet address the group is sent to: 01:00:5e followed by the low 23 bits of the group (RFC 1112)
+pub fn multicast_mac(group: &[u8; 4]) -> [u8; 6] {
+    [0x01, 0x00, 0x5E, group[1] & 0x7F, group[2], group[3]]
+}
+
+/// Something that needs to be sent for the groups
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MembershipAction {
+    /// Report membership of the group. State changes are the unsolicited reports sent after joining.
+    Report { group: [u8; 4], version: IgmpVersion, state_change: bool },
+    /// Report membership of every group at once, answering an IGMPv3 general query
+    ReportAll,
+    /// Tell the routers the group was left
+    Leave { group: [u8; 4], version: IgmpVersion },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Membership {
+    group: [u8; 4],
+    /// When the next report is due
+    report_at: Option<Instant>,
+    /// Unsolicited reports left to send after joining
+    unsolicited_left: u8,
+    /// Whether we sent the latest report for the group, which makes leaving it ours to announce in IGMPv2
+    last_reporter: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Leaving {
+    group: [u8; 4],
+    left: u8,
+    next_at: Instant,
+}
+
+/// Multicast groups joined on an interface and their IGMP host state (RFC 2236, RFC 3376)
+#[derive(Debug)]
+pub struct MulticastGroups {
+    memberships: Vec<Membership>,
+    leaving: Vec<Leaving>,
+    /// Version of an older querier on the link and when it is assumed gone
+    older_querier: Option<(IgmpVersion, Instant)>,
+    /// When the answer to an IGMPv3 general query is due
+    general_report_at: Option<Instant>,
+    /// Random number generator state for response delays
+    random: u32,
+}
+
+impl Default for MulticastGroups {
+    fn default() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        MulticastGroups {
+            memberships: Vec::new(),
+            leaving: Vec::new(),
+            older_querier: None,
+            general_report_at: None,
+            random: seed | 1,
+        }
+    }
+}
+
+impl MulticastGroups {
+    pub fn new() -> MulticastGroups {
+        MulticastGroups::default()
+    }
+
+    /// IGMP version to speak: the one of an older querier heard recently, or IGMPv3
+    pub fn version(&self, now: Instant) -> IgmpVersion {
+        match self.older_querier {
+            Some((version, until)) if now < until => version,
+            _ => IgmpVersion::V3,
+        }
+    }
+
+    /// Whether packets for `group` are to be received
+    pub fn is_member(&self, group: &[u8; 4]) -> bool {
+        group == &ALL_SYSTEMS || self.memberships.iter().any(|m| &m.group == group)
+    }
+
+    /// Whether frames sent to the Ethernet multicast address may be for one of our groups
+    pub fn accepts_mac(&self, mac: &[u8; 6]) -> bool {
+        mac == &multicast_mac(&ALL_SYSTEMS) || self.memberships.iter().any(|m| &multicast_mac(&m.group) == mac)
+    }
+
+    /// Groups joined, not including the all systems group
+    pub fn groups(&self) -> impl Iterator<Item = &[u8; 4]> {
+        self.memberships.iter().map(|m| &m.group)
+    }
+
+    /// Join `group`, reporting it on the next poll. Returns false if it isn't a multicast group
+    /// or is already joined.
+    pub fn join(&mut self, group: [u8; 4], now: Instant) -> bool {
+        if !is_multicast(&group) || self.is_member(&group) {
+            return false;
+        }
+
+        self.leaving.retain(|l| l.group != group);
+        self.memberships.push(Membership {
+            group,
+            report_at: Some(now),
+            unsolicited_left: ROBUSTNESS,
+            last_reporter: true,
+        });
+        true
+    }
+
+    /// Leave `group`, telling the routers on the next poll if the IGMP version calls for it.
+    /// Returns false if the group wasn't joined.
+    pub fn leave(&mut self, group: [u8; 4], now: Instant) -> bool {
+        let index = match self.memberships.iter().position(|m| m.group == group) {
+            Some(index) => index,
+            None => return false,
+        };
+        let membership = self.memberships.remove(index);
+
+        // IGMPv1 has no leave message, IGMPv2 leaves are up to whoever reported last
+        let leaves = match self.version(now) {
+            IgmpVersion::V1 => 0,
+            IgmpVersion::V2 if membership.last_reporter => 1,
+            IgmpVersion::V2 => 0,
+            IgmpVersion::V3 => ROBUSTNESS,
+        };
+        if leaves > 0 {
+            self.leaving.push(Leaving { group, left: leaves, next_at: now });
+        }
+        true
+    }
+
+    /// A membership query was received. `group` is `None` for general queries.
+    /// Reports are scheduled at a random time within the maximum response time.
+    pub fn query(&mut self, now: Instant, version: IgmpVersion, group: Option<[u8; 4]>, max_response: Duration) {
+        if version != IgmpVersion::V3 {
+            // An IGMPv1 querier takes precedence over an IGMPv2 one
+            let current = self.version(now);
+            if !(version == IgmpVersion::V2 && current == IgmpVersion::V1) {
+                self.older_querier = Some((version, now + OLDER_VERSION_QUERIER_TIMEOUT));
+            }
+        }
+
+        let report_at = now + self.random_delay(max_response);
+        let earliest = |current: Option<Instant>| Some(current.map_or(report_at, |at| at.min(report_at)));
+
+        match group {
+            None if self.version(now) == IgmpVersion::V3 => {
+                if !self.memberships.is_empty() {
+                    self.general_report_at = earliest(self.general_report_at);
+                }
+            },
+            None => {
+                for membership in self.memberships.iter_mut() {
+                    membership.report_at = earliest(membership.report_at);
+                }
+            },
+            Some(group) => {
+                if let Some(membership) = self.memberships.iter_mut().find(|m| m.group == group) {
+                    membership.report_at = earliest(membership.report_at);
+                }
+            },
+        }
+    }
+
+    /// Another host reported `group`. With IGMPv1 and IGMPv2 that answers a pending query for us.
+    pub fn report_heard(&mut self, group: &[u8; 4], now: Instant) {
+        if self.version(now) == IgmpVersion::V3 {
+            return;
+        }
+        if let Some(membership) = self.memberships.iter_mut().find(|m| &m.group == group) {
+            if membership.unsolicited_left == 0 {
+                membership.report_at = None;
+            }
+            membership.last_reporter = false;
+        }
+    }
+
+    /// Advance the report and leave timers
+    pub fn poll(&mut self, now: Instant) -> Vec<MembershipAction> {
+        let mut actions = Vec::new();
+        let version = self.version(now);
+        if version == IgmpVersion::V3 {
+            self.older_querier = None;
+        }
+
+        if self.general_report_at.is_some_and(|at| at <= now) {
+            self.general_report_at = None;
+            actions.push(MembershipAction::ReportAll);
+        }
+
+        for membership in self.memberships.iter_mut() {
+            if membership.report_at.is_none_or(|at| at > now) {
+                continue;
+            }
+
+            let state_change = membership.unsolicited_left > 0;
+            if state_change {
+                membership.unsolicited_left -= 1;
+            }
+            membership.report_at = if membership.unsolicited_left > 0 {
+                Some(now + UNSOLICITED_REPORT_INTERVAL)
+            } else {
+                None
+            };
+            membership.last_reporter = true;
+            actions.push(MembershipAction::Report { group: membership.group, version, state_change });
+        }
+
+        for leaving in self.leaving.iter_mut().filter(|l| l.next_at <= now) {
+            leaving.left -= 1;
+            leaving.next_at = now + UNSOLICITED_REPORT_INTERVAL;
+            actions.push(MembershipAction::Leave { group: leaving.group, version });
+        }
+        self.leaving.retain(|l| l.left > 0);
+
+        actions
+    }
+
+    /// Earliest time at which `poll` has something to do
+    pub fn next_poll(&self) -> Option<Instant> {
+        self.memberships.iter()
+            .filter_map(|m| m.report_at)
+            .chain(self.leaving.iter().map(|l| l.next_at))
+            .chain(self.general_report_at)
+            .min()
+    }
+
+    /// Pick a delay up to `max` (xorshift32)
+    fn random_delay(&mut self, max: Duration) -> Duration {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        let millis = max.as_millis() as u64;
+        Duration::from_millis(self.random as u64 % (millis + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GROUP: [u8; 4] = [239, 129, 1, 2];
+    const MAX_RESPONSE: Duration = Duration::from_secs(10);
+
+    /// A member of `GROUP` whose unsolicited reports have all been sent
+    fn member(now: Instant) -> MulticastGroups {
+        let mut groups = MulticastGroups::new();
+        groups.join(GROUP, now);
+        groups.poll(now);
+        groups.poll(now + UNSOLICITED_REPORT_INTERVAL);
+        groups
+    }
+
+    #[test]
+    fn maps_groups_to_ethernet_addresses() {
+        assert_eq!(multicast_mac(&GROUP), [0x01, 0x00, 0x5E, 0x01, 0x01, 0x02]);
+        assert_eq!(multicast_mac(&ALL_SYSTEMS), [0x01, 0x00, 0x5E, 0x00, 0x00, 0x01]);
+
+        let mut groups = MulticastGroups::new();
+        assert!(groups.is_member(&ALL_SYSTEMS));
+        assert!(!groups.accepts_mac(&multicast_mac(&GROUP)));
+        groups.join(GROUP, Instant::now());
+        assert!(groups.is_member(&GROUP));
+        // Groups differing only in the bits left out share the address
+        assert!(groups.accepts_mac(&multicast_mac(&[224, 1, 1, 2])));
+        assert!(!groups.is_member(&[224, 1, 1, 2]));
+    }
+
+    #[test]
+    fn joining_sends_unsolicited_reports() {
+        let mut groups = MulticastGroups::new();
+        let now = Instant::now();
+        assert!(!groups.join([10, 0, 0, 1], now));
+        assert!(groups.join(GROUP, now));
+        assert!(!groups.join(GROUP, now));
+        assert_eq!(groups.groups().collect::<Vec<_>>(), vec![&GROUP]);
+
+        let report = MembershipAction::Report { group: GROUP, version: IgmpVersion::V3, state_change: true };
+        assert_eq!(groups.poll(now), vec![report]);
+        assert_eq!(groups.next_poll(), Some(now + UNSOLICITED_REPORT_INTERVAL));
+        assert_eq!(groups.poll(now + UNSOLICITED_REPORT_INTERVAL), vec![report]);
+        assert_eq!(groups.next_poll(), None);
+    }
+
+    #[test]
+    fn answers_igmpv3_general_queries_with_one_report() {
+        let now = Instant::now();
+        let mut groups = MulticastGroups::new();
+        groups.query(now, IgmpVersion::V3, None, MAX_RESPONSE);
+        assert_eq!(groups.next_poll(), None);
+
+        let mut groups = member(now);
+        groups.query(now, IgmpVersion::V3, None, MAX_RESPONSE);
+        let report_at = groups.next_poll().unwrap();
+        assert!(report_at <= now + MAX_RESPONSE);
+        assert_eq!(groups.poll(report_at), vec![MembershipAction::ReportAll]);
+        assert_eq!(groups.version(now), IgmpVersion::V3);
+    }
+
+    #[test]
+    fn igmpv2_reports_from_other_hosts_suppress_ours_and_the_leave() {
+        let now = Instant::now();
+        let mut groups = member(now);
+        groups.query(now, IgmpVersion::V2, Some(GROUP), MAX_RESPONSE);
+        assert_eq!(groups.version(now), IgmpVersion::V2);
+        assert!(groups.next_poll().is_some());
+
+        groups.report_heard(&GROUP, now);
+        assert_eq!(groups.next_poll(), None);
+        assert!(groups.leave(GROUP, now));
+        assert!(groups.poll(now).is_empty());
+        assert!(!groups.leave(GROUP, now));
+    }
+
+    #[test]
+    fn igmpv2_queries_are_answered_per_group() {
+        let now = Instant::now();
+        let mut groups = member(now);
+        groups.query(now, IgmpVersion::V2, None, MAX_RESPONSE);
+        let report_at = groups.next_poll().unwrap();
+        let report = MembershipAction::Report { group: GROUP, version: IgmpVersion::V2, state_change: false };
+        assert_eq!(groups.poll(report_at), vec![report]);
+
+        // Having reported last, leaving is ours to announce, once
+        assert!(groups.leave(GROUP, report_at));
+        assert_eq!(groups.poll(report_at), vec![MembershipAction::Leave { group: GROUP, version: IgmpVersion::V2 }]);
+        assert_eq!(groups.next_poll(), None);
+    }
+
+    #[test]
+    fn igmpv3_leaves_are_repeated() {
+        let now = Instant::now();
+        let mut groups = member(now);
+        groups.leave(GROUP, now);
+        let leave = MembershipAction::Leave { group: GROUP, version: IgmpVersion::V3 };
+        assert_eq!(groups.poll(now), vec![leave]);
+        assert_eq!(groups.poll(now + UNSOLICITED_REPORT_INTERVAL), vec![leave]);
+        assert_eq!(groups.next_poll(), None);
+    }
+
+    #[test]
+    fn older_queriers_are_forgotten_after_a_while() {
+        let now = Instant::now();
+        let mut groups = MulticastGroups::new();
+        groups.query(now, IgmpVersion::V1, None, IGMPV1_MAX_RESPONSE_TIME);
+        // An IGMPv2 query doesn't override the IGMPv1 querier
+        groups.query(now, IgmpVersion::V2, None, MAX_RESPONSE);
+        assert_eq!(groups.version(now), IgmpVersion::V1);
+
+        groups.join(GROUP, now);
+        assert!(groups.leave(GROUP, now));
+        assert!(groups.poll(now).is_empty());
+
+        assert_eq!(groups.version(now + OLDER_VERSION_QUERIER_TIMEOUT), IgmpVersion::V3);
+    }
+}
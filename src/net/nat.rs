@@ -0,0 +1,560 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use crate::protocols::adjust_checksum;
+use crate::protocols::icmp::{IcmpPacket, IcmpType};
+use crate::protocols::ipv4::{IpPayload, IpProtocol, Ipv4Header, Ipv4Packet};
+use crate::protocols::tcp;
+
+/// How long an ICMP echo mapping is kept after its last packet
+pub const ICMP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a UDP mapping is kept after its last packet
+pub const UDP_TIMEOUT: Duration = Duration::from_secs(180);
+/// How long an established TCP connection may be idle (RFC 5382)
+pub const TCP_ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(7440);
+/// How long a TCP connection that is still opening or already closing is kept
+pub const TCP_TRANSITORY_TIMEOUT: Duration = Duration::from_secs(240);
+/// Maximum number of connections tracked at once
+pub const MAX_CONNECTIONS: usize = 1024;
+/// Start of the ports, and ICMP identifiers, handed out when a connection's own one is taken
+pub const FIRST_DYNAMIC_PORT: u16 = 49152;
+
+/// An address and a port, or the identifier of an ICMP echo request for the side that sent it
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Endpoint {
+    pub address: [u8; 4],
+    pub port: u16,
+}
+
+impl Endpoint {
+    pub fn new(address: [u8; 4], port: u16) -> Endpoint {
+        Endpoint { address, port }
+    }
+
+    /// Address followed by port, as covered by the TCP and UDP checksums
+    fn to_bytes(self) -> [u8; 6] {
+        let mut bytes = [0; 6];
+        bytes[0..4].copy_from_slice(&self.address);
+        bytes[4..6].copy_from_slice(&self.port.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ConnectionState {
+    /// Only the internal host has sent anything
+    New,
+    /// The remote end has replied
+    Established,
+    /// A TCP FIN or RST was seen
+    Closing,
+}
+
+/// A flow whose source is rewritten on the way out and whose replies are rewritten on the way back
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Connection {
+    pub protocol: u8,
+    /// The host behind the NAT
+    pub internal: Endpoint,
+    /// What the host appears as to the remote end
+    pub external: Endpoint,
+    pub remote: Endpoint,
+    pub state: ConnectionState,
+    pub expires_at: Instant,
+}
+
+impl Connection {
+    fn timeout(&self) -> Duration {
+        match self.protocol {
+            p if p == IpProtocol::TCP as u8 && self.state == ConnectionState::Established => TCP_ESTABLISHED_TIMEOUT,
+            p if p == IpProtocol::TCP as u8 => TCP_TRANSITORY_TIMEOUT,
+            p if p == IpProtocol::UDP as u8 => UDP_TIMEOUT,
+            _ => ICMP_TIMEOUT,
+        }
+    }
+
+    /// Account for a packet of the connection, sent by the internal host if `outbound`
+    fn update(&mut self, now: Instant, outbound: bool, tcp_flags: u8) {
+        if tcp_flags & (tcp::FLAG_FIN | tcp::FLAG_RST) != 0 {
+            self.state = ConnectionState::Closing;
+        } else if !outbound && self.state == ConnectionState::New {
+            self.state = ConnectionState::Established;
+        }
+        self.expires_at = now + self.timeout();
+    }
+}
+
+/// What identifies the connection a packet belongs to
+struct Flow {
+    protocol: u8,
+    source: Endpoint,
+    destination: Endpoint,
+    tcp_flags: u8,
+}
+
+fn icmp_identifier(icmp_packet: &IcmpPacket) -> u16 {
+    let rest_of_header = icmp_packet.rest_of_header();
+    u16::from_be_bytes([rest_of_header[0], rest_of_header[1]])
+}
+
+/// The flow of a TCP, UDP or ICMP echo packet. Fragments and other packets have none.
+fn flow(packet: &mut Ipv4Packet) -> Option<Flow> {
+    let protocol = packet.header().protocol_number();
+    let source_address = packet.header().source_ip().get_address();
+    let destination_address = packet.header().destination_ip().get_address();
+
+    let (source_port, destination_port, tcp_flags) = match packet.payload() {
+        IpPayload::TCP(tcp_packet) => (tcp_packet.source_port(), tcp_packet.destination_port(), tcp_packet.flags()),
+        IpPayload::UDP(udp_packet) => (udp_packet.source_port(), udp_packet.destination_port(), 0),
+        // The identifier belongs to the side sending the requests
+        IpPayload::ICMP(icmp_packet) => match icmp_packet.icmp_type() {
+            IcmpType::EchoRequest => (icmp_identifier(icmp_packet), 0, 0),
+            IcmpType::EchoReply => (0, icmp_identifier(icmp_packet), 0),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(Flow {
+        protocol,
+        source: Endpoint::new(source_address, source_port),
+        destination: Endpoint::new(destination_address, destination_port),
+        tcp_flags,
+    })
+}
+
+/// Replace the source, or the destination, of a packet with `endpoint` and patch the checksums to match
+fn rewrite(packet: &mut Ipv4Packet, source: bool, endpoint: Endpoint) {
+    let header = packet.header();
+    let address = if source { header.source_ip() } else { header.destination_ip() };
+    let old_address = address.get_address();
+    address.set_address(&endpoint.address);
+    let checksum = adjust_checksum(header.checksum(), &old_address, &endpoint.address);
+    header.set_checksum(checksum);
+
+    match packet.payload() {
+        IpPayload::TCP(tcp_packet) => {
+            let old_port = if source { tcp_packet.source_port() } else { tcp_packet.destination_port() };
+            if source { tcp_packet.set_source_port(endpoint.port) } else { tcp_packet.set_destination_port(endpoint.port) }
+            let old = Endpoint::new(old_address, old_port).to_bytes();
+            let checksum = adjust_checksum(tcp_packet.checksum(), &old, &endpoint.to_bytes());
+            tcp_packet.set_checksum(checksum);
+        },
+        IpPayload::UDP(udp_packet) => {
+            let old_port = if source { udp_packet.source_port() } else { udp_packet.destination_port() };
+            if source { udp_packet.set_source_port(endpoint.port) } else { udp_packet.set_destination_port(endpoint.port) }
+            // A zero checksum means the sender didn't compute one
+            if udp_packet.checksum() != 0 {
+                let old = Endpoint::new(old_address, old_port).to_bytes();
+                let checksum = adjust_checksum(udp_packet.checksum(), &old, &endpoint.to_bytes());
+                udp_packet.set_checksum(if checksum == 0 { 0xFFFF } else { checksum });
+            }
+        },
+        IpPayload::ICMP(icmp_packet) => {
+            let identifies = match icmp_packet.icmp_type() {
+                IcmpType::EchoRequest => source,
+                IcmpType::EchoReply => !source,
+                _ => false,
+            };
+            if identifies {
+                let mut rest_of_header = icmp_packet.rest_of_header();
+                let old_identifier = [rest_of_header[0], rest_of_header[1]];
+                rest_of_header[0..2].copy_from_slice(&endpoint.port.to_be_bytes());
+                icmp_packet.set_rest_of_header(&rest_of_header);
+                let checksum = adjust_checksum(icmp_packet.checksum(), &old_identifier, &endpoint.port.to_be_bytes());
+                icmp_packet.set_checksum(checksum);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Source NAT: rewrites internal hosts' packets to come from an address of the router
+/// and tracks the connections so replies can be rewritten back
+#[derive(Debug, Default)]
+pub struct Nat {
+    connections: Vec<Connection>,
+    /// Offset of the next dynamic port to try
+    next_port: u32,
+}
+
+impl Nat {
+    pub fn new() -> Nat {
+        Nat::default()
+    }
+
+    /// Rewrite the source of an outgoing packet to `external_address`, tracking a new connection if needed.
+    /// Returns false if the packet can't be translated: it isn't TCP, UDP or ICMP echo, is a fragment,
+    /// or there are no ports left.
+    pub fn translate_outbound(&mut self, now: Instant, packet: &mut [u8], external_address: [u8; 4]) -> bool {
+        let mut ipv4_packet = match Ipv4Packet::try_from(packet) {
+            Ok(ipv4_packet) => ipv4_packet,
+            Err(_) => return false,
+        };
+        let flow = match flow(&mut ipv4_packet) {
+            Some(flow) => flow,
+            None => return false,
+        };
+        self.expire(now);
+
+        let existing = self.connections.iter().position(|c| {
+            c.protocol == flow.protocol && c.internal == flow.source && c.remote == flow.destination
+        });
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                if self.connections.len() >= MAX_CONNECTIONS {
+                    println!("Connection table full, not translating {:?}", flow.source);
+                    return false;
+                }
+                let port = match self.allocate_port(flow.protocol, flow.source, external_address, flow.destination) {
+                    Some(port) => port,
+                    None => {
+                        println!("No free port on {:?} for {:?}", external_address, flow.destination);
+                        return false;
+                    }
+                };
+
+                let external = Endpoint::new(external_address, port);
+                println!("Translating {:?} to {:?} for {:?}", flow.source, external, flow.destination);
+                self.connections.push(Connection {
+                    protocol: flow.protocol,
+                    internal: flow.source,
+                    external,
+                    remote: flow.destination,
+                    state: ConnectionState::New,
+                    expires_at: now,
+                });
+                self.connections.len() - 1
+            }
+        };
+
+        let connection = &mut self.connections[index];
+        connection.update(now, true, flow.tcp_flags);
+        rewrite(&mut ipv4_packet, true, connection.external);
+        true
+    }
+
+    /// Rewrite the destination of a reply, or of an ICMP error about a translated packet, back to the
+    /// internal host. Returns false if the packet doesn't belong to a tracked connection.
+    pub fn translate_inbound(&mut self, now: Instant, packet: &mut [u8]) -> bool {
+        let mut ipv4_packet = match Ipv4Packet::try_from(packet) {
+            Ok(ipv4_packet) => ipv4_packet,
+            Err(_) => return false,
+        };
+        self.expire(now);
+
+        let flow = match flow(&mut ipv4_packet) {
+            Some(flow) => flow,
+            None => return self.translate_icmp_error(&mut ipv4_packet),
+        };
+
+        let connection = self.connections.iter_mut().find(|c| {
+            c.protocol == flow.protocol && c.external == flow.destination && c.remote == flow.source
+        });
+        match connection {
+            Some(connection) => {
+                connection.update(now, false, flow.tcp_flags);
+                rewrite(&mut ipv4_packet, false, connection.internal);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Rewrite an ICMP error quoting a translated packet: the quoted source goes back to the
+    /// internal host, and so does the error itself
+    fn translate_icmp_error(&self, packet: &mut Ipv4Packet) -> bool {
+        let internal = match packet.payload() {
+            IpPayload::ICMP(icmp_packet) if icmp_packet.icmp_type().is_error() => {
+                let before = icmp_packet.data.to_vec();
+                let internal = match self.translate_quoted(icmp_packet.data) {
+                    Some(internal) => internal,
+                    None => return false,
+                };
+
+                // Everything changed is 16 bit aligned, so a trailing odd byte can be left out
+                let even = before.len() & !1;
+                let checksum = adjust_checksum(icmp_packet.checksum(), &before[..even], &icmp_packet.data[..even]);
+                icmp_packet.set_checksum(checksum);
+                internal
+            },
+            _ => return false,
+        };
+
+        let header = packet.header();
+        let old_address = header.destination_ip().get_address();
+        header.destination_ip().set_address(&internal.address);
+        let checksum = adjust_checksum(header.checksum(), &old_address, &internal.address);
+        header.set_checksum(checksum);
+        true
+    }
+
+    /// Rewrite the source of the packet quoted in an ICMP error back to the internal host it was
+    /// translated from. Returns that host, or `None` if the packet isn't from a tracked connection.
+    fn translate_quoted(&self, quoted: &mut [u8]) -> Option<Endpoint> {
+        if quoted.len() < 20 {
+            return None;
+        }
+        let header_length = (quoted[0] & 0x0F) as usize * 4;
+        // The error has to include the ports, or the ICMP identifier
+        if header_length < 20 || quoted.len() < header_length + 8 {
+            return None;
+        }
+
+        let (header_bytes, payload) = quoted.split_at_mut(header_length);
+        let mut header = Ipv4Header::try_from(header_bytes).ok()?;
+        let protocol = header.protocol_number();
+        let source_address = header.source_ip().get_address();
+        let destination_address = header.destination_ip().get_address();
+
+        // Offsets of the port or identifier of the sender and of the checksum covering it
+        let (port_offset, checksum_offset, destination_port) = match protocol {
+            p if p == IpProtocol::TCP as u8 => (0, 16, u16::from_be_bytes([payload[2], payload[3]])),
+            p if p == IpProtocol::UDP as u8 => (0, 6, u16::from_be_bytes([payload[2], payload[3]])),
+            p if p == IpProtocol::ICMP as u8 && IcmpType::from(payload[0]) == IcmpType::EchoRequest => (4, 2, 0),
+            _ => return None,
+        };
+        let source_port = u16::from_be_bytes([payload[port_offset], payload[port_offset + 1]]);
+
+        let external = Endpoint::new(source_address, source_port);
+        let remote = Endpoint::new(destination_address, destination_port);
+        let connection = self.connections.iter().find(|c| {
+            c.protocol == protocol && c.external == external && c.remote == remote
+        })?;
+        let internal = connection.internal;
+
+        header.source_ip().set_address(&internal.address);
+        let checksum = adjust_checksum(header.checksum(), &source_address, &internal.address);
+        header.set_checksum(checksum);
+
+        payload[port_offset..port_offset + 2].copy_from_slice(&internal.port.to_be_bytes());
+        // Only part of the quoted transport header may be included
+        if payload.len() >= checksum_offset + 2 {
+            let old_checksum = u16::from_be_bytes([payload[checksum_offset], payload[checksum_offset + 1]]);
+            let checksum = if protocol == IpProtocol::ICMP as u8 {
+                adjust_checksum(old_checksum, &source_port.to_be_bytes(), &internal.port.to_be_bytes())
+            } else if protocol == IpProtocol::UDP as u8 && old_checksum == 0 {
+                0
+            } else {
+                adjust_checksum(old_checksum, &external.to_bytes(), &internal.to_bytes())
+            };
+            payload[checksum_offset..checksum_offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        Some(internal)
+    }
+
+    /// Pick the port `internal` appears as on `external_address` towards `remote`.
+    /// Its own port is kept if nothing else uses it.
+    fn allocate_port(&mut self, protocol: u8, internal: Endpoint, external_address: [u8; 4], remote: Endpoint) -> Option<u16> {
+        let connections = &self.connections;
+        let taken = |port: u16| connections.iter().any(|c| {
+            c.protocol == protocol && c.remote == remote && c.external == Endpoint::new(external_address, port)
+        });
+
+        if internal.port != 0 && !taken(internal.port) {
+            return Some(internal.port);
+        }
+
+        let dynamic_ports = 65536 - FIRST_DYNAMIC_PORT as u32;
+        for _ in 0..dynamic_ports {
+            let port = (FIRST_DYNAMIC_PORT as u32 + self.next_port) as u16;
+            self.next_port = (self.next_port + 1) % dynamic_ports;
+            if !taken(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Forget the connections that have been idle for too long. Returns how many there were.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.connections.len();
+        self.connections.retain(|c| c.expires_at > now);
+        before - self.connections.len()
+    }
+
+    /// Earliest time at which a connection times out
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.connections.iter().map(|c| c.expires_at).min()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tests::{checksum, echo_request, ipv4, tcp, MORE_FRAGMENTS, PROTOCOL_ICMP, PROTOCOL_TCP};
+
+    const INTERNAL: [u8; 4] = [192, 168, 1, 10];
+    const OTHER_INTERNAL: [u8; 4] = [192, 168, 1, 11];
+    const EXTERNAL: [u8; 4] = [203, 0, 113, 1];
+    const REMOTE: [u8; 4] = [198, 51, 100, 7];
+
+    /// TCP segment in an IPv4 packet, with both checksums valid
+    fn tcp_packet(source: Endpoint, destination: Endpoint, flags: u8) -> Vec<u8> {
+        let mut segment = tcp(source.port, destination.port, flags, b"data");
+        let tcp_checksum = checksum(&[&pseudo_header(source.address, destination.address, &segment)[..], &segment].concat());
+        segment[16..18].copy_from_slice(&tcp_checksum);
+        ipv4(source.address, destination.address, PROTOCOL_TCP, 1, 0, &segment)
+    }
+
+    fn pseudo_header(source: [u8; 4], destination: [u8; 4], segment: &[u8]) -> Vec<u8> {
+        let mut pseudo_header = source.to_vec();
+        pseudo_header.extend_from_slice(&destination);
+        pseudo_header.extend_from_slice(&[0, PROTOCOL_TCP]);
+        pseudo_header.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        pseudo_header
+    }
+
+    fn assert_tcp_checksums(packet: &[u8]) {
+        assert_eq!(checksum(&packet[..20]), [0, 0]);
+        let source = [packet[12], packet[13], packet[14], packet[15]];
+        let destination = [packet[16], packet[17], packet[18], packet[19]];
+        let segment = &packet[20..];
+        assert_eq!(checksum(&[&pseudo_header(source, destination, segment)[..], segment].concat()), [0, 0]);
+    }
+
+    fn source(packet: &[u8]) -> Endpoint {
+        Endpoint::new([packet[12], packet[13], packet[14], packet[15]], u16::from_be_bytes([packet[20], packet[21]]))
+    }
+
+    fn destination(packet: &[u8]) -> Endpoint {
+        Endpoint::new([packet[16], packet[17], packet[18], packet[19]], u16::from_be_bytes([packet[22], packet[23]]))
+    }
+
+    #[test]
+    fn rewrites_tcp_both_ways_keeping_checksums_valid() {
+        let mut nat = Nat::new();
+        let now = Instant::now();
+        let internal = Endpoint::new(INTERNAL, 40000);
+        let remote = Endpoint::new(REMOTE, 80);
+
+        let mut outbound = tcp_packet(internal, remote, tcp::FLAG_SYN);
+        assert!(nat.translate_outbound(now, &mut outbound, EXTERNAL));
+        assert_eq!(source(&outbound), Endpoint::new(EXTERNAL, 40000));
+        assert_eq!(destination(&outbound), remote);
+        assert_tcp_checksums(&outbound);
+        assert_eq!(nat.iter().next().unwrap().state, ConnectionState::New);
+
+        let mut inbound = tcp_packet(remote, Endpoint::new(EXTERNAL, 40000), tcp::FLAG_SYN | tcp::FLAG_ACK);
+        assert!(nat.translate_inbound(now, &mut inbound));
+        assert_eq!(source(&inbound), remote);
+        assert_eq!(destination(&inbound), internal);
+        assert_tcp_checksums(&inbound);
+
+        let connection = nat.iter().next().unwrap();
+        assert_eq!(connection.state, ConnectionState::Established);
+        assert_eq!(connection.expires_at, now + TCP_ESTABLISHED_TIMEOUT);
+    }
+
+    #[test]
+    fn hands_out_dynamic_ports_when_a_port_is_taken() {
+        let mut nat = Nat::new();
+        let now = Instant::now();
+        let remote = Endpoint::new(REMOTE, 80);
+
+        let mut first = tcp_packet(Endpoint::new(INTERNAL, 40000), remote, tcp::FLAG_SYN);
+        let mut second = tcp_packet(Endpoint::new(OTHER_INTERNAL, 40000), remote, tcp::FLAG_SYN);
+        assert!(nat.translate_outbound(now, &mut first, EXTERNAL));
+        assert!(nat.translate_outbound(now, &mut second, EXTERNAL));
+        assert_eq!(source(&first).port, 40000);
+        assert_eq!(source(&second).port, FIRST_DYNAMIC_PORT);
+        assert_tcp_checksums(&second);
+
+        let mut reply = tcp_packet(remote, Endpoint::new(EXTERNAL, FIRST_DYNAMIC_PORT), tcp::FLAG_ACK);
+        assert!(nat.translate_inbound(now, &mut reply));
+        assert_eq!(destination(&reply), Endpoint::new(OTHER_INTERNAL, 40000));
+    }
+
+    #[test]
+    fn rewrites_echo_identifiers() {
+        let mut nat = Nat::new();
+        let now = Instant::now();
+        let mut request = ipv4(INTERNAL, REMOTE, PROTOCOL_ICMP, 1, 0, &echo_request(7, 1, 8));
+        assert!(nat.translate_outbound(now, &mut request, EXTERNAL));
+        assert_eq!(&request[12..16], &EXTERNAL);
+        assert_eq!(&request[24..26], &7u16.to_be_bytes());
+
+        let mut reply = ipv4(REMOTE, EXTERNAL, PROTOCOL_ICMP, 1, 0, &echo_request(7, 1, 8));
+        reply[20] = 0;
+        let icmp_checksum = checksum(&[&[0, 0, 0, 0][..], &reply[24..]].concat());
+        reply[22..24].copy_from_slice(&icmp_checksum);
+        assert!(nat.translate_inbound(now, &mut reply));
+        assert_eq!(&reply[16..20], &INTERNAL);
+        assert_eq!(checksum(&reply[..20]), [0, 0]);
+        assert_eq!(checksum(&reply[20..]), [0, 0]);
+
+        // A second host pinging with the same identifier gets another one
+        let mut request = ipv4(OTHER_INTERNAL, REMOTE, PROTOCOL_ICMP, 1, 0, &echo_request(7, 1, 8));
+        assert!(nat.translate_outbound(now, &mut request, EXTERNAL));
+        assert_eq!(&request[24..26], &FIRST_DYNAMIC_PORT.to_be_bytes());
+        assert_eq!(checksum(&request[20..]), [0, 0]);
+    }
+
+    #[test]
+    fn translates_icmp_errors_quoting_translated_packets() {
+        let mut nat = Nat::new();
+        let now = Instant::now();
+        let internal = Endpoint::new(INTERNAL, 40000);
+        let remote = Endpoint::new(REMOTE, 80);
+        nat.translate_outbound(now, &mut tcp_packet(internal, remote, tcp::FLAG_SYN), EXTERNAL);
+
+        // What the remote received from the second host, translated to a dynamic port, of which errors quote the header and 8 bytes
+        let mut sent = tcp_packet(Endpoint::new(OTHER_INTERNAL, 40000), remote, tcp::FLAG_SYN);
+        assert!(nat.translate_outbound(now, &mut sent, EXTERNAL));
+        let quoted_length = 20 + 8;
+        let mut error = vec![3, 1, 0, 0, 0, 0, 0, 0];
+        error.extend_from_slice(&sent[..quoted_length]);
+        let icmp_checksum = checksum(&error);
+        error[2..4].copy_from_slice(&icmp_checksum);
+        let mut error = ipv4(REMOTE, EXTERNAL, PROTOCOL_ICMP, 1, 0, &error);
+
+        assert!(nat.translate_inbound(now, &mut error));
+        assert_eq!(&error[16..20], &OTHER_INTERNAL);
+        assert_eq!(checksum(&error[..20]), [0, 0]);
+        assert_eq!(checksum(&error[20..]), [0, 0]);
+
+        let quoted = &error[28..];
+        assert_eq!(checksum(&quoted[..20]), [0, 0]);
+        assert_eq!(source(quoted), Endpoint::new(OTHER_INTERNAL, 40000));
+        assert_eq!(destination(quoted), remote);
+        // Only the first 8 bytes of the segment are quoted, so its checksum can't be checked
+        let original = tcp_packet(Endpoint::new(OTHER_INTERNAL, 40000), remote, tcp::FLAG_SYN);
+        assert_eq!(&quoted[20..], &original[20..quoted_length]);
+    }
+
+    #[test]
+    fn ignores_untracked_packets_and_forgets_idle_connections() {
+        let mut nat = Nat::new();
+        let now = Instant::now();
+        let remote = Endpoint::new(REMOTE, 80);
+
+        let mut unsolicited = tcp_packet(remote, Endpoint::new(EXTERNAL, 40000), tcp::FLAG_SYN);
+        assert!(!nat.translate_inbound(now, &mut unsolicited));
+
+        let mut fragment = ipv4(INTERNAL, REMOTE, PROTOCOL_TCP, 1, MORE_FRAGMENTS, &tcp(40000, 80, tcp::FLAG_SYN, &[0; 4]));
+        assert!(!nat.translate_outbound(now, &mut fragment, EXTERNAL));
+        assert!(nat.is_empty());
+
+        assert!(nat.translate_outbound(now, &mut tcp_packet(Endpoint::new(INTERNAL, 40000), remote, tcp::FLAG_SYN), EXTERNAL));
+        assert_eq!(nat.next_expiry(), Some(now + TCP_TRANSITORY_TIMEOUT));
+        assert_eq!(nat.expire(now + TCP_TRANSITORY_TIMEOUT - Duration::from_secs(1)), 0);
+        assert_eq!(nat.expire(now + TCP_TRANSITORY_TIMEOUT), 1);
+
+        let mut late_reply = tcp_packet(remote, Endpoint::new(EXTERNAL, 40000), tcp::FLAG_ACK);
+        assert!(!nat.translate_inbound(now + TCP_TRANSITORY_TIMEOUT, &mut late_reply));
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use heapless::consts::*;
+
+use crate::net::aging::{AgingMap, Expires};
+
+/// Maximum number of neighbors remembered per interface
+pub type NeighborCacheSize = U64;
+
+pub const DEFAULT_NEIGHBOR_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Neighbor {
+    pub hardware_address: [u8; 6],
+    pub expires_at: Instant,
+}
+
+impl Expires for Neighbor {
+    fn expires_at(&self) -> Instant {
+        self.expires_at
+    }
+}
+
+/// Fixed capacity IPv4 to MAC address mapping learned from ARP
+pub struct NeighborCache {
+    entries: AgingMap<Neighbor, NeighborCacheSize>,
+    timeout: Duration,
+}
+
+impl NeighborCache {
+    pub fn new(timeout: Duration) -> NeighborCache {
+        NeighborCache {
+            entries: AgingMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Change how long new entries are kept. Existing entries keep their expiry time.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn lookup(&self, protocol_address: &[u8; 4], now: Instant) -> Option<[u8; 6]> {
+        self.entries.get(protocol_address, now).map(|neighbor| neighbor.hardware_address)
+    }
+
+    /// Whether there is an entry for the address, expired or not
+    pub fn contains(&self, protocol_address: &[u8; 4]) -> bool {
+        self.entries.contains(protocol_address)
+    }
+
+    /// Add or refresh an entry. When the cache is full, the entry closest to expiry is evicted.
+    pub fn insert(&mut self, protocol_address: [u8; 4], hardware_address: [u8; 6], now: Instant) {
+        let neighbor = Neighbor {
+            hardware_address,
+            expires_at: now + self.timeout,
+        };
+        self.entries.insert(protocol_address, neighbor, now);
+    }
+
+    pub fn remove(&mut self, protocol_address: &[u8; 4]) -> Option<Neighbor> {
+        self.entries.remove(protocol_address)
+    }
+
+    /// Drop all entries whose timeout has passed
+    pub fn expire(&mut self, now: Instant) {
+        self.entries.expire(now);
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8; 4], &Neighbor)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_timeout() {
+        let now = Instant::now();
+        let mut cache = NeighborCache::new(Duration::from_secs(10));
+        cache.insert([10, 0, 0, 1], [2, 0, 0, 0, 0, 1], now);
+        assert_eq!(cache.lookup(&[10, 0, 0, 1], now + Duration::from_secs(9)), Some([2, 0, 0, 0, 0, 1]));
+        assert_eq!(cache.lookup(&[10, 0, 0, 1], now + Duration::from_secs(10)), None);
+        assert!(cache.contains(&[10, 0, 0, 1]));
+
+        cache.expire(now + Duration::from_secs(10));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_entry_closest_to_expiry() {
+        let now = Instant::now();
+        let mut cache = NeighborCache::new(Duration::from_secs(3600));
+        for i in 0..64_u8 {
+            cache.insert([10, 0, 0, i], [2, 0, 0, 0, 0, i], now + Duration::from_secs(i as u64));
+        }
+        cache.insert([10, 0, 1, 0], [2, 0, 0, 0, 1, 0], now + Duration::from_secs(64));
+        assert_eq!(cache.len(), 64);
+        assert!(!cache.contains(&[10, 0, 0, 0]));
+        assert!(cache.contains(&[10, 0, 0, 1]));
+        assert!(cache.contains(&[10, 0, 1, 0]));
+    }
+
+    #[test]
+    fn flush_empties_the_cache_for_reuse() {
+        let now = Instant::now();
+        let mut cache = NeighborCache::new(Duration::from_secs(60));
+        for i in 0..64_u8 {
+            cache.insert([10, 0, 0, i], [2, 0, 0, 0, 0, i], now);
+        }
+        cache.flush();
+        assert!(cache.is_empty());
+        assert_eq!(cache.lookup(&[10, 0, 0, 1], now), None);
+
+        cache.insert([10, 0, 0, 1], [2, 0, 0, 0, 0, 1], now);
+        assert_eq!(cache.lookup(&[10, 0, 0, 1], now), Some([2, 0, 0, 0, 0, 1]));
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Maximum number of packets held while waiting for ARP replies
+pub const MAX_PENDING_PACKETS: usize = 16;
+/// ARP requests sent for a neighbor before giving up on it
+pub const MAX_RESOLUTION_ATTEMPTS: u8 = 3;
+/// Delay before the first retry, doubled after every further attempt
+pub const INITIAL_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct PendingPacket {
+    next_hop: [u8; 4],
+    packet: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Resolution {
+    next_hop: [u8; 4],
+    attempts: u8,
+    next_attempt: Instant,
+}
+
+/// What to do about a neighbor whose retry timer has fired
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RetryAction {
+    /// Send another ARP request
+    Request([u8; 4]),
+    /// All attempts were used up, the neighbor's packets have been dropped
+    Unreachable { next_hop: [u8; 4], dropped: Vec<Vec<u8>> },
+}
+
+/// IPv4 packets waiting for their next hop's hardware address to be resolved
+#[derive(Debug, Default)]
+pub struct PendingQueue {
+    packets: VecDeque<PendingPacket>,
+    resolutions: Vec<Resolution>,
+}
+
+impl PendingQueue {
+    pub fn new() -> PendingQueue {
+        PendingQueue::default()
+    }
+
+    /// Whether an ARP request for `next_hop` is already outstanding
+    pub fn is_resolving(&self, next_hop: &[u8; 4]) -> bool {
+        self.resolutions.iter().any(|r| &r.next_hop == next_hop)
+    }
+
+    /// Queue a packet for `next_hop`. Returns false if the queue is full.
+    pub fn enqueue(&mut self, next_hop: [u8; 4], packet: &[u8]) -> bool {
+        if self.packets.len() >= MAX_PENDING_PACKETS {
+            return false;
+        }
+        self.packets.push_back(PendingPacket { next_hop, packet: packet.to_vec() });
+        true
+    }
+
+    /// Record that the first ARP request for `next_hop` was just sent
+    pub fn start_resolution(&mut self, next_hop: [u8; 4], now: Instant) {
+        if !self.is_resolving(&next_hop) {
+            self.resolutions.push(Resolution {
+                next_hop,
+                attempts: 1,
+                next_attempt: now + INITIAL_RETRY_INTERVAL,
+            });
+        }
+    }
+
+    /// Stop resolving `next_hop` and hand back its packets in the order they were queued
+    pub fn resolved(&mut self, next_hop: &[u8; 4]) -> Vec<Vec<u8>> {
+        self.resolutions.retain(|r| &r.next_hop != next_hop);
+        self.take_packets(next_hop)
+    }
+
+    fn take_packets(&mut self, next_hop: &[u8; 4]) -> Vec<Vec<u8>> {
+        let (matching, rest) = self.packets.drain(..).partition(|p| &p.next_hop == next_hop);
+        self.packets = rest;
+        matching.into_iter().map(|p: PendingPacket| p.packet).collect()
+    }
+
+    /// Advance the retry timers, returning what to do for every neighbor that is due
+    pub fn retry(&mut self, now: Instant) -> Vec<RetryAction> {
+        let mut actions = Vec::new();
+        let mut unreachable = Vec::new();
+
+        for resolution in self.resolutions.iter_mut().filter(|r| r.next_attempt <= now) {
+            if resolution.attempts >= MAX_RESOLUTION_ATTEMPTS {
+                unreachable.push(resolution.next_hop);
+            } else {
+                resolution.next_attempt = now + INITIAL_RETRY_INTERVAL * 2_u32.pow(resolution.attempts as u32);
+                resolution.attempts += 1;
+                actions.push(RetryAction::Request(resolution.next_hop));
+            }
+        }
+
+        for next_hop in unreachable {
+            let dropped = self.resolved(&next_hop);
+            actions.push(RetryAction::Unreachable { next_hop, dropped });
+        }
+
+        actions
+    }
+
+    /// Earliest time at which `retry` has something to do
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.resolutions.iter().map(|r| r.next_attempt).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEXT_HOP: [u8; 4] = [169, 254, 0, 1];
+    const OTHER_NEXT_HOP: [u8; 4] = [169, 254, 0, 3];
+
+    #[test]
+    fn hands_back_a_neighbors_packets_in_order() {
+        let mut queue = PendingQueue::new();
+        assert!(queue.enqueue(NEXT_HOP, &[1]));
+        assert!(queue.enqueue(OTHER_NEXT_HOP, &[2]));
+        assert!(queue.enqueue(NEXT_HOP, &[3]));
+        queue.start_resolution(NEXT_HOP, Instant::now());
+        assert!(queue.is_resolving(&NEXT_HOP));
+
+        assert_eq!(queue.resolved(&NEXT_HOP), vec![vec![1], vec![3]]);
+        assert!(!queue.is_resolving(&NEXT_HOP));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.resolved(&OTHER_NEXT_HOP), vec![vec![2]]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn refuses_packets_when_full() {
+        let mut queue = PendingQueue::new();
+        for i in 0..MAX_PENDING_PACKETS {
+            assert!(queue.enqueue(NEXT_HOP, &[i as u8]));
+        }
+        assert!(!queue.enqueue(OTHER_NEXT_HOP, &[0]));
+        assert_eq!(queue.len(), MAX_PENDING_PACKETS);
+    }
+
+    #[test]
+    fn retries_with_backoff_then_gives_up() {
+        let mut queue = PendingQueue::new();
+        let start = Instant::now();
+        queue.enqueue(NEXT_HOP, &[1]);
+        queue.enqueue(OTHER_NEXT_HOP, &[2]);
+        queue.start_resolution(NEXT_HOP, start);
+        // A second start doesn't reset the attempts
+        queue.start_resolution(NEXT_HOP, start + Duration::from_millis(500));
+
+        assert!(queue.retry(start).is_empty());
+        let first_retry = start + INITIAL_RETRY_INTERVAL;
+        assert_eq!(queue.next_retry(), Some(first_retry));
+        assert_eq!(queue.retry(first_retry), vec![RetryAction::Request(NEXT_HOP)]);
+        let second_retry = first_retry + INITIAL_RETRY_INTERVAL * 2;
+        assert_eq!(queue.next_retry(), Some(second_retry));
+        assert_eq!(queue.retry(second_retry), vec![RetryAction::Request(NEXT_HOP)]);
+
+        let give_up = second_retry + INITIAL_RETRY_INTERVAL * 4;
+        assert!(queue.retry(give_up - Duration::from_millis(1)).is_empty());
+        assert_eq!(queue.retry(give_up), vec![RetryAction::Unreachable { next_hop: NEXT_HOP, dropped: vec![vec![1]] }]);
+        assert!(!queue.is_resolving(&NEXT_HOP));
+        assert_eq!(queue.next_retry(), None);
+        assert_eq!(queue.len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use heapless::consts::*;
+
+use crate::net::aging::{AgingMap, Expires};
+use crate::net::config::MIN_MTU;
+
+/// Maximum number of destinations remembered per interface
+pub type PathMtuCacheSize = U64;
+
+/// How long a reduced path MTU is trusted before trying the link MTU again (RFC 1191 6.3)
+pub const DEFAULT_PATH_MTU_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Common MTUs to guess from when a router doesn't say what its next hop supports (RFC 1191 7)
+pub const PLATEAUS: [usize; 11] = [65535, 32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, 68];
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PathMtu {
+    pub mtu: usize,
+    pub expires_at: Instant,
+}
+
+impl Expires for PathMtu {
+    fn expires_at(&self) -> Instant {
+        self.expires_at
+    }
+}
+
+/// Largest datagrams known to reach destinations without fragmentation, learned from
+/// ICMP fragmentation needed messages (RFC 1191)
+pub struct PathMtuCache {
+    entries: AgingMap<PathMtu, PathMtuCacheSize>,
+    timeout: Duration,
+}
+
+impl PathMtuCache {
+    pub fn new(timeout: Duration) -> PathMtuCache {
+        PathMtuCache {
+            entries: AgingMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Change how long new entries are kept. Existing entries keep their expiry time.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn lookup(&self, destination: &[u8; 4], now: Instant) -> Option<usize> {
+        self.entries.get(destination, now).map(|path| path.mtu)
+    }
+
+    /// A router reported `next_hop_mtu` for packets of `packet_length` bytes to `destination`.
+    /// Routers that predate RFC 1191 report 0, then the next plateau below the packet length is used.
+    /// Returns the new path MTU if it went down, reports of larger MTUs are ignored.
+    pub fn update(&mut self, destination: [u8; 4], next_hop_mtu: usize, packet_length: usize, now: Instant) -> Option<usize> {
+        let mtu = if next_hop_mtu == 0 || next_hop_mtu >= packet_length {
+            PLATEAUS.iter().copied().find(|&plateau| plateau < packet_length).unwrap_or(MIN_MTU)
+        } else {
+            next_hop_mtu
+        }.max(MIN_MTU);
+
+        if self.lookup(&destination, now).is_some_and(|current| current <= mtu) {
+            return None;
+        }
+
+        self.entries.insert(destination, PathMtu { mtu, expires_at: now + self.timeout }, now);
+        Some(mtu)
+    }
+
+    pub fn remove(&mut self, destination: &[u8; 4]) -> Option<PathMtu> {
+        self.entries.remove(destination)
+    }
+
+    /// Drop all entries whose timeout has passed
+    pub fn expire(&mut self, now: Instant) {
+        self.entries.expire(now);
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8; 4], &PathMtu)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESTINATION: [u8; 4] = [10, 0, 0, 1];
+
+    #[test]
+    fn only_lowers_the_path_mtu() {
+        let now = Instant::now();
+        let mut cache = PathMtuCache::new(DEFAULT_PATH_MTU_TIMEOUT);
+        assert_eq!(cache.update(DESTINATION, 1400, 1500, now), Some(1400));
+        assert_eq!(cache.update(DESTINATION, 1450, 1500, now), None);
+        assert_eq!(cache.update(DESTINATION, 576, 1400, now), Some(576));
+        assert_eq!(cache.lookup(&DESTINATION, now), Some(576));
+        // Nothing goes below the minimum every link supports
+        assert_eq!(cache.update(DESTINATION, 20, 576, now), Some(MIN_MTU));
+    }
+
+    #[test]
+    fn guesses_a_plateau_when_the_router_reports_none() {
+        let now = Instant::now();
+        let mut cache = PathMtuCache::new(DEFAULT_PATH_MTU_TIMEOUT);
+        assert_eq!(cache.update(DESTINATION, 0, 1500, now), Some(1492));
+        assert_eq!(cache.update(DESTINATION, 0, 1492, now), Some(1006));
+        // Reports not below the packet length are as good as none
+        assert_eq!(cache.update([10, 0, 0, 2], 1500, 1500, now), Some(1492));
+    }
+
+    #[test]
+    fn entries_expire_and_flush() {
+        let now = Instant::now();
+        let mut cache = PathMtuCache::new(Duration::from_secs(10));
+        cache.update(DESTINATION, 1400, 1500, now);
+        assert_eq!(cache.lookup(&DESTINATION, now + Duration::from_secs(10)), None);
+        // Expired entries don't keep larger values from being learned again
+        assert_eq!(cache.update(DESTINATION, 1450, 1500, now + Duration::from_secs(10)), Some(1450));
+
+        cache.update([10, 0, 0, 2], 1400, 1500, now);
+        cache.expire(now + Duration::from_secs(10));
+        assert_eq!(cache.len(), 1);
+        cache.flush();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use crate::protocols::ipv4::Ipv4Header;
+
+/// How long the fragments of a datagram are kept after the first one arrived
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of datagrams reassembled at once
+pub const MAX_REASSEMBLY_DATAGRAMS: usize = 16;
+/// Maximum number of payload bytes buffered over all datagrams
+pub const MAX_REASSEMBLY_BYTES: usize = 256 * 1024;
+/// Largest datagram the total length field can describe, header included
+const MAX_DATAGRAM: usize = u16::MAX as usize;
+
+/// Fragments belong to the same datagram if all of these match (RFC 791)
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct FragmentKey {
+    pub source: [u8; 4],
+    pub destination: [u8; 4],
+    pub identification: u16,
+    pub protocol: u8,
+}
+
+#[derive(Debug)]
+struct Datagram {
+    key: FragmentKey,
+    /// Header of the first fragment, once it has arrived
+    header: Option<Vec<u8>>,
+    data: Vec<u8>,
+    /// Sorted, non-overlapping byte ranges of `data` received so far
+    received: Vec<(usize, usize)>,
+    /// Payload length, known once the last fragment has arrived
+    total_length: Option<usize>,
+    expires_at: Instant,
+}
+
+impl Datagram {
+    fn is_complete(&self) -> bool {
+        self.header.is_some()
+            && matches!((self.total_length, self.received.as_slice()), (Some(total), [(0, end)]) if *end == total)
+    }
+
+    /// Copy the bytes of `data` at `start` that haven't been received yet.
+    /// Where fragments overlap, the data that arrived first is kept.
+    fn fill(&mut self, start: usize, data: &[u8]) {
+        let end = start + data.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+
+        let mut position = start;
+        for &(received_start, received_end) in &self.received {
+            if received_end <= position {
+                continue;
+            }
+            if received_start >= end {
+                break;
+            }
+            if received_start > position {
+                self.data[position..received_start].copy_from_slice(&data[position - start..received_start - start]);
+            }
+            position = position.max(received_end);
+        }
+        if position < end {
+            self.data[position..end].copy_from_slice(&data[position - start..]);
+        }
+
+        self.received.push((start, end));
+        self.received.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.received.len());
+        for &(range_start, range_end) in &self.received {
+            match merged.last_mut() {
+                Some(last) if range_start <= last.1 => last.1 = last.1.max(range_end),
+                _ => merged.push((range_start, range_end)),
+            }
+        }
+        self.received = merged;
+    }
+
+    /// Build the complete datagram from the first fragment's header and the collected payload
+    fn assemble(self) -> Vec<u8> {
+        let mut packet = self.header.expect("Only complete datagrams are assembled");
+        let header_length = packet.len();
+        packet.extend_from_slice(&self.data);
+
+        let mut header = Ipv4Header::try_from(&mut packet[..header_length])
+            .expect("Header was validated when its fragment arrived");
+        header.set_more_fragments(false);
+        header.set_fragment_offset(0);
+        header.set_length((header_length + self.data.len()) as u16);
+        header.calculate_checksum();
+
+        packet
+    }
+}
+
+/// IPv4 datagrams being put back together from their fragments
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    datagrams: Vec<Datagram>,
+    /// Datagrams given up on since `expire` last ran
+    failed: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// Add a fragment. `header` is the fragment's IPv4 header, `offset` the position of `data`
+    /// in the datagram's payload in bytes. Returns the complete datagram once all fragments are in.
+    pub fn insert(
+        &mut self,
+        now: Instant,
+        key: FragmentKey,
+        header: &[u8],
+        offset: usize,
+        more_fragments: bool,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        let index = match self.datagrams.iter().position(|d| d.key == key) {
+            Some(index) => index,
+            None => {
+                self.make_room(true, 0);
+                self.datagrams.push(Datagram {
+                    key,
+                    header: None,
+                    data: Vec::new(),
+                    received: Vec::new(),
+                    total_length: None,
+                    expires_at: now + REASSEMBLY_TIMEOUT,
+                });
+                self.datagrams.len() - 1
+            }
+        };
+
+        let end = offset + data.len();
+        let datagram = &self.datagrams[index];
+        // The first fragment's header goes in front of the payload, and with its options the whole has to fit
+        // the total length. Until it arrives, the header of this fragment stands in for it.
+        let header_length = datagram.header.as_ref().map_or(header.len(), Vec::len);
+        let consistent = end.max(datagram.data.len()) + header_length <= MAX_DATAGRAM
+            // Only the last fragment may have a length that isn't a multiple of eight
+            && (!more_fragments || data.len().is_multiple_of(8))
+            && match (datagram.total_length, more_fragments) {
+                (Some(total), false) => total == end,
+                (Some(total), true) => end <= total,
+                (None, false) => datagram.received.last().is_none_or(|r| r.1 <= end),
+                (None, true) => true,
+            };
+        if !consistent {
+            println!("Dropping inconsistent fragment of {:?}", key);
+            self.datagrams.swap_remove(index);
+            self.failed += 1;
+            return None;
+        }
+
+        let growth = end.saturating_sub(datagram.data.len());
+        self.make_room(false, growth);
+        // The datagram itself may have been evicted to make room
+        let index = self.datagrams.iter().position(|d| d.key == key)?;
+        let datagram = &mut self.datagrams[index];
+
+        if offset == 0 && datagram.header.is_none() {
+            datagram.header = Some(header.to_vec());
+        }
+        if !more_fragments {
+            datagram.total_length = Some(end);
+        }
+        datagram.fill(offset, data);
+
+        if datagram.is_complete() {
+            Some(self.datagrams.swap_remove(index).assemble())
+        } else {
+            None
+        }
+    }
+
+    /// Evict the datagrams closest to timing out until `bytes` more can be buffered
+    /// and, if `new_datagram` is set, another datagram fits
+    fn make_room(&mut self, new_datagram: bool, bytes: usize) {
+        while !self.datagrams.is_empty()
+            && ((new_datagram && self.datagrams.len() >= MAX_REASSEMBLY_DATAGRAMS)
+                || self.buffered() + bytes > MAX_REASSEMBLY_BYTES) {
+            let oldest = self.datagrams.iter().enumerate()
+                .min_by_key(|(_, d)| d.expires_at)
+                .map(|(index, _)| index)
+                .unwrap();
+            println!("Evicting fragments of {:?}", self.datagrams[oldest].key);
+            self.datagrams.swap_remove(oldest);
+            self.failed += 1;
+        }
+    }
+
+    /// Drop the datagrams whose timer ran out. Returns the number of datagrams given up on
+    /// since the last call, including ones evicted to stay under the caps or with inconsistent fragments.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.datagrams.len();
+        self.datagrams.retain(|d| d.expires_at > now);
+        let failed = self.failed + before - self.datagrams.len();
+        self.failed = 0;
+        failed
+    }
+
+    /// Earliest time at which a datagram times out
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.datagrams.iter().map(|d| d.expires_at).min()
+    }
+
+    /// Number of payload bytes currently buffered
+    pub fn buffered(&self) -> usize {
+        self.datagrams.iter().map(|d| d.data.len()).sum()
+    }
+
+    /// Number of datagrams being reassembled
+    pub fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tests::{ipv4, PEER_IP, STACK_IP};
+
+    const KEY: FragmentKey = FragmentKey { source: PEER_IP, destination: STACK_IP, identification: 1, protocol: 17 };
+
+    /// Header of a fragment of `KEY`, with `options_length` bytes of options
+    fn header(options_length: usize) -> Vec<u8> {
+        let mut header = ipv4(PEER_IP, STACK_IP, 17, 1, 0, &[]);
+        header[0] = 0x40 | (5 + options_length / 4) as u8;
+        header.resize(20 + options_length, 1);
+        header
+    }
+
+    #[test]
+    fn datagrams_have_to_fit_the_total_length_with_their_header() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        let largest = MAX_DATAGRAM - 60;
+        let first = vec![0; largest / 2 / 8 * 8];
+        let last = vec![0; largest - first.len()];
+        let too_long = vec![0; last.len() + 1];
+
+        // With 40 bytes of options the last byte would be one past what the total length can describe
+        assert!(reassembler.insert(now, KEY, &header(40), 0, true, &first).is_none());
+        assert!(reassembler.insert(now, KEY, &header(0), first.len(), false, &too_long).is_none());
+        assert!(reassembler.is_empty());
+        assert_eq!(reassembler.expire(now), 1);
+
+        // Same when the first fragment comes in last
+        assert!(reassembler.insert(now, KEY, &header(0), first.len(), false, &too_long).is_none());
+        assert!(reassembler.insert(now, KEY, &header(40), 0, true, &first).is_none());
+        assert!(reassembler.is_empty());
+
+        assert!(reassembler.insert(now, KEY, &header(40), 0, true, &first).is_none());
+        let datagram = reassembler.insert(now, KEY, &header(0), first.len(), false, &last).unwrap();
+        assert_eq!(datagram.len(), MAX_DATAGRAM);
+        assert_eq!(&datagram[2..4], &[0xFF, 0xFF]);
+    }
+
+    fn key(identification: u16) -> FragmentKey {
+        FragmentKey { identification, ..KEY }
+    }
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn assembles_fragments_in_any_order() {
+        let now = Instant::now();
+        let data = payload(40);
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.insert(now, KEY, &header(0), 32, false, &data[32..]).is_none());
+        assert!(reassembler.insert(now, KEY, &header(0), 16, true, &data[16..32]).is_none());
+        let datagram = reassembler.insert(now, KEY, &header(0), 0, true, &data[..16]).unwrap();
+
+        assert_eq!(&datagram[20..], &data[..]);
+        assert_eq!(&datagram[2..4], &60_u16.to_be_bytes());
+        assert_eq!(&datagram[6..8], &[0, 0]);
+        assert_eq!(crate::net::tests::checksum(&datagram[..20]), [0, 0]);
+        assert!(reassembler.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_data_that_arrived_first_where_fragments_overlap() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.insert(now, KEY, &header(0), 8, true, &[1; 16]).is_none());
+        // Overlaps the start and end of what is there, and repeats it
+        assert!(reassembler.insert(now, KEY, &header(0), 0, true, &[2; 32]).is_none());
+        assert!(reassembler.insert(now, KEY, &header(0), 8, true, &[3; 16]).is_none());
+        let datagram = reassembler.insert(now, KEY, &header(0), 24, false, &[4; 16]).unwrap();
+
+        let mut expected = vec![2; 8];
+        expected.extend_from_slice(&[1; 16]);
+        expected.extend_from_slice(&[2; 8]);
+        expected.extend_from_slice(&[4; 8]);
+        assert_eq!(&datagram[20..], &expected[..]);
+    }
+
+    #[test]
+    fn drops_datagrams_with_inconsistent_fragments() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        // Only the last fragment may have a length that isn't a multiple of eight
+        reassembler.insert(now, key(1), &header(0), 0, true, &[0; 12]);
+        // Two different ends
+        reassembler.insert(now, key(2), &header(0), 16, false, &[0; 8]);
+        reassembler.insert(now, key(2), &header(0), 16, false, &[0; 16]);
+        // Data past the end
+        reassembler.insert(now, key(3), &header(0), 16, false, &[0; 8]);
+        reassembler.insert(now, key(3), &header(0), 24, true, &[0; 8]);
+
+        assert!(reassembler.is_empty());
+        assert_eq!(reassembler.expire(now), 3);
+    }
+
+    #[test]
+    fn gives_up_on_datagrams_after_the_timeout() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        reassembler.insert(now, key(1), &header(0), 0, true, &[0; 8]);
+        reassembler.insert(now + Duration::from_secs(1), key(2), &header(0), 0, true, &[0; 8]);
+        assert_eq!(reassembler.next_expiry(), Some(now + REASSEMBLY_TIMEOUT));
+
+        assert_eq!(reassembler.expire(now + REASSEMBLY_TIMEOUT), 1);
+        assert_eq!(reassembler.len(), 1);
+        // The rest of the datagram given up on starts over
+        assert!(reassembler.insert(now + REASSEMBLY_TIMEOUT, key(1), &header(0), 8, false, &[0; 8]).is_none());
+        assert_eq!(reassembler.len(), 2);
+    }
+
+    #[test]
+    fn evicts_the_oldest_datagrams_to_stay_under_the_limits() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        for identification in 0..=MAX_REASSEMBLY_DATAGRAMS as u16 {
+            let arrival = now + Duration::from_millis(identification as u64);
+            reassembler.insert(arrival, key(identification), &header(0), 0, true, &[0; 8]);
+        }
+        assert_eq!(reassembler.len(), MAX_REASSEMBLY_DATAGRAMS);
+        assert_eq!(reassembler.expire(now), 1);
+        // The one that went was the first, the newest is still there
+        assert!(reassembler.insert(now, key(MAX_REASSEMBLY_DATAGRAMS as u16), &header(0), 8, false, &[0; 8]).is_some());
+
+        let mut reassembler = Reassembler::new();
+        let chunk = vec![0; 60000];
+        for identification in 0..5 {
+            let arrival = now + Duration::from_millis(identification as u64);
+            reassembler.insert(arrival, key(identification), &header(0), 0, true, &chunk);
+        }
+        assert!(reassembler.buffered() <= MAX_REASSEMBLY_BYTES);
+        assert_eq!(reassembler.len(), 4);
+        assert_eq!(reassembler.expire(now), 1);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::net::config::InterfaceAddress;
+
+/// Where a route came from
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RouteOrigin {
+    /// The subnet of an interface address
+    Connected,
+    /// The interface's configured default gateway
+    Gateway,
+    /// Added through `RoutingTable::add`
+    Static,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Route {
+    pub prefix: InterfaceAddress,
+    /// Router to send through, `None` if the destination is on the link
+    pub next_hop: Option<[u8; 4]>,
+    /// Index of the interface to send out of
+    pub interface: usize,
+    /// Preference between routes with equally long prefixes, lower wins
+    pub metric: u32,
+    pub origin: RouteOrigin,
+}
+
+impl Route {
+    /// The neighbor to hand packets for `destination` to
+    pub fn next_hop_for(&self, destination: &[u8; 4]) -> [u8; 4] {
+        self.next_hop.unwrap_or(*destination)
+    }
+}
+
+/// IPv4 routes, looked up by longest prefix match
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable::default()
+    }
+
+    /// Add a static route, replacing one with the same prefix, interface and metric
+    pub fn add(&mut self, prefix: InterfaceAddress, next_hop: Option<[u8; 4]>, interface: usize, metric: u32) {
+        let prefix = prefix.network();
+        self.routes.retain(|r| !(r.prefix == prefix && r.interface == interface && r.metric == metric));
+        self.routes.push(Route { prefix, next_hop, interface, metric, origin: RouteOrigin::Static });
+    }
+
+    /// Remove the static routes for `prefix`. Returns false if there were none.
+    pub fn remove(&mut self, prefix: InterfaceAddress) -> bool {
+        let prefix = prefix.network();
+        let before = self.routes.len();
+        self.routes.retain(|r| !(r.origin == RouteOrigin::Static && r.prefix == prefix));
+        self.routes.len() != before
+    }
+
+    /// Replace the connected and gateway routes of `interface` with ones for its current configuration
+    pub fn sync(&mut self, interface: usize, addresses: &[InterfaceAddress], gateway: Option<[u8; 4]>) {
+        let mut derived: Vec<Route> = Vec::new();
+        for address in addresses {
+            let prefix = address.network();
+            if !derived.iter().any(|r| r.prefix == prefix) {
+                derived.push(Route { prefix, next_hop: None, interface, metric: 0, origin: RouteOrigin::Connected });
+            }
+        }
+        if let Some(gateway) = gateway {
+            derived.push(Route {
+                prefix: InterfaceAddress::new([0; 4], 0),
+                next_hop: Some(gateway),
+                interface,
+                metric: 0,
+                origin: RouteOrigin::Gateway,
+            });
+        }
+
+        self.routes.retain(|r| r.interface != interface || r.origin == RouteOrigin::Static);
+        self.routes.extend(derived);
+    }
+
+    /// Find the route with the longest prefix containing `destination`, preferring lower metrics
+    pub fn lookup(&self, destination: &[u8; 4]) -> Option<&Route> {
+        self.routes.iter()
+            .filter(|r| r.prefix.contains(destination))
+            .min_by_key(|r| (u8::MAX - r.prefix.prefix_len, r.metric))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GATEWAY: [u8; 4] = [192, 168, 1, 1];
+    const OTHER_ROUTER: [u8; 4] = [192, 168, 1, 254];
+
+    fn addresses() -> [InterfaceAddress; 1] {
+        [InterfaceAddress::new([192, 168, 1, 10], 24)]
+    }
+
+    #[test]
+    fn longer_prefixes_beat_the_default_route() {
+        let mut table = RoutingTable::new();
+        table.sync(0, &addresses(), Some(GATEWAY));
+        table.add(InterfaceAddress::new([10, 1, 0, 0], 16), Some(OTHER_ROUTER), 0, 100);
+
+        let route = table.lookup(&[192, 168, 1, 20]).unwrap();
+        assert_eq!(route.origin, RouteOrigin::Connected);
+        assert_eq!(route.next_hop_for(&[192, 168, 1, 20]), [192, 168, 1, 20]);
+        assert_eq!(table.lookup(&[10, 1, 2, 3]).unwrap().next_hop, Some(OTHER_ROUTER));
+        let route = table.lookup(&[10, 2, 0, 1]).unwrap();
+        assert_eq!(route.origin, RouteOrigin::Gateway);
+        assert_eq!(route.next_hop_for(&[10, 2, 0, 1]), GATEWAY);
+    }
+
+    #[test]
+    fn lower_metrics_win_between_equal_prefixes() {
+        let mut table = RoutingTable::new();
+        let prefix = InterfaceAddress::new([10, 1, 2, 3], 16);
+        table.add(prefix, Some(GATEWAY), 0, 20);
+        table.add(prefix, Some(OTHER_ROUTER), 1, 10);
+        assert_eq!(table.lookup(&[10, 1, 0, 1]).unwrap().interface, 1);
+
+        // Same prefix, interface and metric replaces the route
+        table.add(prefix, Some(GATEWAY), 1, 10);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.lookup(&[10, 1, 0, 1]).unwrap().next_hop, Some(GATEWAY));
+        // Stored as the network, not the address it was added with
+        assert_eq!(table.lookup(&[10, 1, 0, 1]).unwrap().prefix, InterfaceAddress::new([10, 1, 0, 0], 16));
+    }
+
+    #[test]
+    fn sync_follows_the_gateway_and_keeps_static_routes() {
+        let mut table = RoutingTable::new();
+        table.add(InterfaceAddress::new([10, 1, 0, 0], 16), Some(OTHER_ROUTER), 0, 0);
+        table.sync(0, &addresses(), Some(GATEWAY));
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.lookup(&[8, 8, 8, 8]).unwrap().next_hop, Some(GATEWAY));
+
+        table.sync(0, &addresses(), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.lookup(&[8, 8, 8, 8]), None);
+        assert_eq!(table.lookup(&[10, 1, 2, 3]).unwrap().origin, RouteOrigin::Static);
+
+        table.sync(0, &[], None);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.lookup(&[10, 1, 2, 3]).unwrap().origin, RouteOrigin::Static);
+    }
+
+    #[test]
+    fn sync_only_touches_its_interface() {
+        let mut table = RoutingTable::new();
+        table.sync(0, &addresses(), Some(GATEWAY));
+        table.sync(1, &[InterfaceAddress::new([10, 0, 0, 1], 8)], None);
+        table.sync(0, &addresses(), None);
+        assert_eq!(table.lookup(&[10, 9, 9, 9]).unwrap().interface, 1);
+        assert_eq!(table.lookup(&[192, 168, 1, 20]).unwrap().interface, 0);
+    }
+
+    #[test]
+    fn remove_only_removes_static_routes() {
+        let mut table = RoutingTable::new();
+        table.sync(0, &addresses(), None);
+        let subnet = addresses()[0];
+        assert!(!table.remove(subnet));
+        assert_eq!(table.lookup(&[192, 168, 1, 20]).unwrap().origin, RouteOrigin::Connected);
+
+        table.add(subnet, Some(OTHER_ROUTER), 0, 10);
+        assert!(table.remove(subnet));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.lookup(&[192, 168, 1, 20]).unwrap().origin, RouteOrigin::Connected);
+    }
+}
@@ -0,0 +1,512 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::net::{timestamp, Handoff, Interface};
+use crate::net::filter::{Chain, Filter, PacketInfo, Verdict};
+use crate::net::nat::Nat;
+use crate::net::reassembly::{FragmentKey, Reassembler};
+use crate::net::route::RoutingTable;
+use crate::net::stats::ForwardingStats;
+use crate::net::tunnel::{Tunnel, TunnelConfig};
+use crate::protocols::icmp::{self, IcmpType};
+use crate::protocols::ipv4::{options, IpPayload, Ipv4Packet};
+
+/// Forwards IPv4 packets between interfaces
+pub struct Router<D: Device> {
+    interfaces: Vec<Interface<D>>,
+    routes: RoutingTable,
+    nat: Nat,
+    filter: Filter,
+    /// Fragments leaving through a masquerading interface, only the first one has the ports to translate
+    reassembler: Reassembler,
+    /// Tunnels with the index of their interface
+    tunnels: Vec<(usize, Tunnel)>,
+    stats: ForwardingStats,
+}
+
+impl<D: Device> Default for Router<D> {
+    fn default() -> Self {
+        Router {
+            interfaces: Vec::new(),
+            routes: RoutingTable::new(),
+            nat: Nat::new(),
+            filter: Filter::new(),
+            reassembler: Reassembler::new(),
+            tunnels: Vec::new(),
+            stats: ForwardingStats::default(),
+        }
+    }
+}
+
+impl<D: Device> Router<D> {
+    pub fn new() -> Router<D> {
+        Router::default()
+    }
+
+    /// Attach an interface. It forwards packets for other hosts if `forwarding` is set in its configuration.
+    /// Returns the index routes use to refer to it.
+    pub fn add_interface(&mut self, mut interface: Interface<D>) -> usize {
+        interface.attached = true;
+        self.interfaces.push(interface);
+        self.sync_routes();
+        self.interfaces.len() - 1
+    }
+
+    /// Attach the interface of a tunnel, whose device is the one `tunnel::pair` created along with it.
+    /// Returns its index like `add_interface`.
+    pub fn add_tunnel(&mut self, interface: Interface<D>, tunnel: Tunnel) -> usize {
+        let index = self.add_interface(interface);
+        self.tunnels.push((index, tunnel));
+        index
+    }
+
+    /// Configurations of the tunnels, with the index of their interface
+    pub fn tunnels(&self) -> impl Iterator<Item = (usize, &TunnelConfig)> {
+        self.tunnels.iter().map(|(index, tunnel)| (*index, tunnel.config()))
+    }
+
+    pub fn interfaces(&self) -> &[Interface<D>] {
+        &self.interfaces
+    }
+
+    pub fn interface(&self, index: usize) -> &Interface<D> {
+        &self.interfaces[index]
+    }
+
+    pub fn interface_mut(&mut self, index: usize) -> &mut Interface<D> {
+        &mut self.interfaces[index]
+    }
+
+    /// Routes used for forwarding. Connected and gateway routes follow the interface configurations.
+    pub fn routes(&self) -> &RoutingTable {
+        &self.routes
+    }
+
+    pub fn routes_mut(&mut self) -> &mut RoutingTable {
+        &mut self.routes
+    }
+
+    /// Connections translated for interfaces with `masquerade` set
+    pub fn nat(&self) -> &Nat {
+        &self.nat
+    }
+
+    /// Rules for forwarded packets. Only the forward chain is evaluated here, the interfaces have their own filters.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn filter_mut(&mut self) -> &mut Filter {
+        &mut self.filter
+    }
+
+    pub fn stats(&self) -> &ForwardingStats {
+        &self.stats
+    }
+
+    /// How long the caller may wait for incoming frames before some interface's timers need to run
+    pub fn poll_delay(&self, now: Instant) -> Option<Duration> {
+        self.interfaces.iter().filter_map(|interface| interface.poll_delay(now)).min()
+    }
+
+    /// Poll every interface once, then forward whatever they handed off.
+    /// Returns false if none of the devices had a frame available.
+    pub fn poll(&mut self, now: Instant, rx_buffer: &mut [u8], tx_buffer: &mut [u8]) -> Result<bool, Error> {
+        let mut received = false;
+        for interface in &mut self.interfaces {
+            received |= interface.poll(now, rx_buffer, tx_buffer)?;
+        }
+
+        self.sync_routes();
+        self.nat.expire(now);
+        self.reassembler.expire(now);
+        self.process_handoffs(now, tx_buffer)?;
+        Ok(received)
+    }
+
+    fn sync_routes(&mut self) {
+        for (index, interface) in self.interfaces.iter().enumerate() {
+            self.routes.sync(index, &interface.config().addresses, interface.config().gateway);
+        }
+    }
+
+    /// Deliver handed off packets and those sent by tunnel interfaces until no interface has any left.
+    /// Sending can hand off more, like ICMP errors for sources only reachable through another interface.
+    fn process_handoffs(&mut self, now: Instant, tx_buffer: &mut [u8]) -> Result<(), Error> {
+        loop {
+            let mut idle = true;
+            for ingress in 0..self.interfaces.len() {
+                while let Some(handoff) = self.interfaces[ingress].take_handoff() {
+                    idle = false;
+                    match handoff {
+                        Handoff::Forward(mut packet) => self.forward(now, ingress, &mut packet, tx_buffer)?,
+                        Handoff::Route(mut packet) => self.route(now, &mut packet, tx_buffer)?,
+                        Handoff::Inbound(mut packet) => self.inbound(now, ingress, &mut packet, tx_buffer)?,
+                        Handoff::Tunneled(mut packet) => self.decapsulate(now, &mut packet, tx_buffer)?,
+                    }
+                }
+            }
+            for tunnel in 0..self.tunnels.len() {
+                while let Some(mut packet) = self.tunnels[tunnel].1.encapsulate()? {
+                    idle = false;
+                    self.send_tunneled(now, tunnel, &mut packet, tx_buffer)?;
+                }
+            }
+            if idle {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Hand the packet to the interface that has its destination address, or is on the subnet it is a broadcast for
+    fn deliver_locally(&mut self, now: Instant, destination: &[u8; 4], packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<bool, Error> {
+        let local = match self.interfaces.iter().position(|interface| interface.accepts(destination)) {
+            Some(local) => local,
+            None => return Ok(false),
+        };
+
+        self.stats.delivered_locally += 1;
+        let mut ipv4_packet = Ipv4Packet::try_from(packet)?;
+        self.interfaces[local].process_ipv4(now, &mut ipv4_packet, false, tx_buffer)?;
+        Ok(true)
+    }
+
+    /// Forward a packet that arrived on `ingress` for another host
+    fn forward(&mut self, now: Instant, ingress: usize, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let (destination, time_to_live) = {
+            let mut ipv4_packet = Ipv4Packet::try_from(&mut *packet)?;
+            let header = ipv4_packet.header();
+            (header.destination_ip().get_address(), header.time_to_live())
+        };
+
+        if self.deliver_locally(now, &destination, packet, tx_buffer)? {
+            return Ok(());
+        }
+
+        if time_to_live <= 1 {
+            println!("TTL of packet for {:?} ran out", destination);
+            self.stats.ttl_exceeded += 1;
+            return self.interfaces[ingress].send_icmp_error(
+                now,
+                IcmpType::TimeExceeded,
+                icmp::CODE_TTL_EXCEEDED,
+                [0; 4],
+                packet,
+                tx_buffer,
+            );
+        }
+
+        let route = match self.routes.lookup(&destination) {
+            Some(route) => *route,
+            None => {
+                println!("No route to {:?}, dropping forwarded packet", destination);
+                self.stats.no_route += 1;
+                return self.interfaces[ingress].send_icmp_error(
+                    now,
+                    IcmpType::DestinationUnreachable,
+                    icmp::CODE_NET_UNREACHABLE,
+                    [0; 4],
+                    packet,
+                    tx_buffer,
+                );
+            }
+        };
+        let next_hop = route.next_hop_for(&destination);
+        let egress = route.interface;
+
+        let (dont_fragment, is_fragment) = {
+            let mut ipv4_packet = Ipv4Packet::try_from(&mut *packet)?;
+            let header = ipv4_packet.header();
+            (header.dont_fragment(), header.is_fragment())
+        };
+
+        // Translating needs the transport header of every fragment, and the forward rules have to see
+        // the ports of the whole datagram, so fragments are collected first
+        if is_fragment && (self.interfaces[egress].config().masquerade || self.filter.is_active(Chain::Forward)) {
+            return match self.reassemble(now, packet)? {
+                Some(mut datagram) => self.forward(now, ingress, &mut datagram, tx_buffer),
+                None => Ok(()),
+            };
+        }
+
+        if self.filter.is_active(Chain::Forward) {
+            let info = PacketInfo {
+                in_interface: Some(ingress),
+                out_interface: Some(egress),
+                ..PacketInfo::from_ipv4(packet)
+            };
+            match self.filter.evaluate(Chain::Forward, &info) {
+                Verdict::Accept => {},
+                Verdict::Drop => {
+                    println!("Filter denied forwarding packet for {:?}", destination);
+                    self.stats.denied += 1;
+                    return Ok(());
+                },
+                Verdict::Reject(rejection) => {
+                    println!("Filter rejected forwarding packet for {:?}", destination);
+                    self.stats.denied += 1;
+                    return self.interfaces[ingress].reject(now, rejection, packet, tx_buffer);
+                },
+            }
+        }
+
+        // Checked before translating, so the error quotes the packet as its sender knows it
+        let mtu = self.interfaces[egress].mtu();
+        if packet.len() > mtu && dont_fragment {
+            println!("Forwarded packet of {} bytes exceeds the MTU of {} and may not be fragmented", packet.len(), mtu);
+            self.interfaces[egress].stats.tx_fragmentation_needed += 1;
+            let next_hop_mtu = (mtu as u16).to_be_bytes();
+            return self.interfaces[ingress].send_icmp_error(
+                now,
+                IcmpType::DestinationUnreachable,
+                icmp::CODE_FRAGMENTATION_NEEDED,
+                [0, 0, next_hop_mtu[0], next_hop_mtu[1]],
+                packet,
+                tx_buffer,
+            );
+        }
+
+        // Record route and timestamp options get the address we send the packet on from
+        let address = self.interfaces[egress].source_address_for(&next_hop).unwrap_or([0; 4]);
+
+        if self.interfaces[egress].config().masquerade {
+            if !self.nat.translate_outbound(now, packet, address) {
+                println!("Unable to translate packet for {:?}, dropping it", destination);
+                self.stats.untranslatable += 1;
+                return Ok(());
+            }
+            self.stats.translated += 1;
+        }
+
+        {
+            let mut ipv4_packet = Ipv4Packet::try_from(&mut *packet)?;
+            let header = ipv4_packet.header();
+            header.decrement_time_to_live();
+
+            if let Some(header_options) = header.options_mut() {
+                options::record(header_options, address, timestamp());
+                header.calculate_checksum();
+            }
+        }
+
+        self.stats.forwarded += 1;
+        self.interfaces[egress].send_ipv4_via(now, packet, Some(next_hop), tx_buffer)
+    }
+
+    /// Collect the fragments of a packet to be translated or filtered. Returns the whole packet once they are all in.
+    fn reassemble(&mut self, now: Instant, packet: &mut [u8]) -> Result<Option<Vec<u8>>, Error> {
+        let mut ipv4_packet = Ipv4Packet::try_from(packet)?;
+        let header = ipv4_packet.header();
+        let key = FragmentKey {
+            source: header.source_ip().get_address(),
+            destination: header.destination_ip().get_address(),
+            identification: header.identification(),
+            protocol: header.protocol_number(),
+        };
+        let offset = header.fragment_offset() as usize * 8;
+        let more_fragments = header.more_fragments();
+        let header_bytes = header.to_bytes();
+
+        Ok(match ipv4_packet.payload() {
+            IpPayload::Fragment(data) => self.reassembler.insert(now, key, &header_bytes, offset, more_fragments, data),
+            _ => None,
+        })
+    }
+
+    /// Translate a packet for a masquerading interface's address back to the host the connection is for.
+    /// Anything that isn't a reply to a translated connection is for the interface itself.
+    fn inbound(&mut self, now: Instant, ingress: usize, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        if self.nat.translate_inbound(now, packet) {
+            self.stats.translated += 1;
+            return self.forward(now, ingress, packet, tx_buffer);
+        }
+
+        let mut ipv4_packet = Ipv4Packet::try_from(packet)?;
+        self.interfaces[ingress].process_ipv4(now, &mut ipv4_packet, false, tx_buffer)
+    }
+
+    /// Send a packet a tunnel interface sent, wrapped for the remote end of the tunnel
+    fn send_tunneled(&mut self, now: Instant, tunnel: usize, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let (interface, remote) = (self.tunnels[tunnel].0, self.tunnels[tunnel].1.config().remote);
+        self.stats.encapsulated += 1;
+
+        if self.deliver_locally(now, &remote, packet, tx_buffer)? {
+            return Ok(());
+        }
+
+        match self.routes.lookup(&remote) {
+            Some(route) if route.interface == interface => {
+                println!("Tunnel to {:?} is routed through itself, dropping packet", remote);
+                self.stats.no_route += 1;
+                Ok(())
+            },
+            Some(route) => {
+                let next_hop = route.next_hop_for(&remote);
+                self.interfaces[route.interface].send_ipv4_via(now, packet, Some(next_hop), tx_buffer)
+            },
+            None => {
+                println!("No route to tunnel end {:?}, dropping packet", remote);
+                self.stats.no_route += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Receive the packet carried by a GRE or IP-in-IP packet on the interface of the tunnel it came through
+    fn decapsulate(&mut self, now: Instant, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let found = self.tunnels.iter()
+            .find_map(|(index, tunnel)| tunnel.decapsulate(packet).map(|inner| (*index, inner)));
+
+        match found {
+            Some((index, mut inner)) => {
+                self.stats.decapsulated += 1;
+                self.interfaces[index].update(now, &mut inner, tx_buffer)
+            },
+            None => {
+                println!("No tunnel for encapsulated packet, dropping it");
+                self.stats.no_tunnel += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a packet an interface originated but had no route for
+    fn route(&mut self, now: Instant, packet: &mut [u8], tx_buffer: &mut [u8]) -> Result<(), Error> {
+        let destination = Ipv4Packet::try_from(&mut *packet)?.header().destination_ip().get_address();
+
+        if self.deliver_locally(now, &destination, packet, tx_buffer)? {
+            return Ok(());
+        }
+
+        match self.routes.lookup(&destination) {
+            Some(route) => {
+                let next_hop = route.next_hop_for(&destination);
+                self.interfaces[route.interface].send_ipv4_via(now, packet, Some(next_hop), tx_buffer)
+            },
+            None => {
+                println!("No route to {:?}, dropping packet", destination);
+                self.stats.no_route += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::device::Device;
+    use crate::net::tests::*;
+
+    /// The ICMP error the router sent back to `HOST_A`, checking where it came from and went to
+    fn icmp_error(link_a: &mut impl Device) -> Vec<u8> {
+        let mut frames = receive_all(link_a);
+        assert_eq!(frames.len(), 1);
+        let frame = frames.remove(0);
+        assert_eq!(&frame[0..6], &HOST_A.0);
+        assert_eq!(frame[14 + 9], PROTOCOL_ICMP);
+        assert_eq!(&frame[14 + 12..14 + 16], &ROUTER_A.1);
+        assert_eq!(&frame[14 + 16..14 + 20], &HOST_A.1);
+        assert_eq!(checksum(&frame[14..14 + 20]), [0, 0]);
+        assert_eq!(checksum(&frame[14 + 20..]), [0, 0]);
+        frame[14 + 20..].to_vec()
+    }
+
+    #[test]
+    fn decrements_the_ttl_of_forwarded_packets() {
+        let now = Instant::now();
+        let (mut link_a, mut link_b, mut router) = router(now);
+        let request = echo_request(1, 1, 32);
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &ipv4(HOST_A.1, HOST_B.1, PROTOCOL_ICMP, 1, 0, &request))).unwrap();
+        poll_router(&mut router, now);
+
+        let forwarded = receive_all(&mut link_b);
+        assert_eq!(forwarded.len(), 1);
+        let frame = &forwarded[0];
+        assert_eq!(&frame[0..6], &HOST_B.0);
+        assert_eq!(&frame[6..12], &ROUTER_B.0);
+        assert_eq!(frame[14 + 8], 63);
+        assert_eq!(checksum(&frame[14..14 + 20]), [0, 0]);
+        assert_eq!(&frame[14 + 12..14 + 16], &HOST_A.1);
+        assert_eq!(&frame[14 + 20..], &request[..]);
+        assert_eq!(router.stats().forwarded, 1);
+        assert!(receive_all(&mut link_a).is_empty());
+    }
+
+    #[test]
+    fn answers_expiring_ttls_with_time_exceeded() {
+        let now = Instant::now();
+        let (mut link_a, mut link_b, mut router) = router(now);
+        let mut packet = ipv4(HOST_A.1, HOST_B.1, PROTOCOL_ICMP, 1, 0, &echo_request(1, 1, 32));
+        packet[8] = 1;
+        set_header_checksum(&mut packet);
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &packet)).unwrap();
+        poll_router(&mut router, now);
+
+        let error = icmp_error(&mut link_a);
+        assert_eq!((error[0], error[1]), (11, 0));
+        assert_eq!(&error[8..], &packet[..20 + 8]);
+        assert_eq!(router.stats().ttl_exceeded, 1);
+        assert!(receive_all(&mut link_b).is_empty());
+    }
+
+    #[test]
+    fn answers_unroutable_destinations_with_net_unreachable() {
+        let now = Instant::now();
+        let (mut link_a, _link_b, mut router) = router(now);
+        let packet = ipv4(HOST_A.1, [172, 16, 0, 1], PROTOCOL_ICMP, 1, 0, &echo_request(1, 1, 32));
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &packet)).unwrap();
+        poll_router(&mut router, now);
+
+        let error = icmp_error(&mut link_a);
+        assert_eq!((error[0], error[1]), (3, 0));
+        assert_eq!(&error[8..], &packet[..20 + 8]);
+        assert_eq!(router.stats().no_route, 1);
+    }
+
+    #[test]
+    fn answers_oversized_dont_fragment_packets_with_the_next_hop_mtu() {
+        let now = Instant::now();
+        let (mut link_a, mut link_b, mut router) = router(now);
+        router.interface_mut(1).config_mut().mtu = 576;
+        let packet = ipv4(HOST_A.1, HOST_B.1, PROTOCOL_ICMP, 1, DONT_FRAGMENT, &echo_request(1, 1, 1000));
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &packet)).unwrap();
+        poll_router(&mut router, now);
+
+        let error = icmp_error(&mut link_a);
+        assert_eq!((error[0], error[1]), (3, 4));
+        assert_eq!(&error[4..8], &[0, 0, 0x02, 0x40]);
+        assert_eq!(router.interface(1).stats().tx_fragmentation_needed, 1);
+        assert!(receive_all(&mut link_b).is_empty());
+
+        // Without DF it is fragmented instead
+        let packet = ipv4(HOST_A.1, HOST_B.1, PROTOCOL_ICMP, 2, 0, &echo_request(1, 1, 1000));
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &packet)).unwrap();
+        poll_router(&mut router, now);
+        let fragments = receive_all(&mut link_b);
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 14 + 576));
+    }
+
+    #[test]
+    fn stamps_the_outgoing_address_into_record_route() {
+        let now = Instant::now();
+        let (mut link_a, mut link_b, mut router) = router(now);
+        // Room for two addresses, followed by the end of the options
+        let record_route = [7, 11, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let packet = ipv4_with_options(HOST_A.1, HOST_B.1, PROTOCOL_ICMP, &record_route, &echo_request(1, 1, 32));
+        link_a.transmit(&ethernet_from(HOST_A.0, ROUTER_A.0, 0x0800, &packet)).unwrap();
+        poll_router(&mut router, now);
+
+        let forwarded = receive_all(&mut link_b);
+        assert_eq!(forwarded.len(), 1);
+        let header = &forwarded[0][14..14 + 32];
+        assert_eq!(checksum(header), [0, 0]);
+        assert_eq!(header[20 + 2], 8);
+        assert_eq!(&header[20 + 3..20 + 7], &ROUTER_B.1);
+        assert_eq!(&header[20 + 7..20 + 11], &[0; 4]);
+    }
+}
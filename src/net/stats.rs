@@ -0,0 +1,63 @@
+/// Per-interface packet counters
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    pub rx_frames: u64,
+    pub tx_frames: u64,
+    /// Received frames dropped because they could not be parsed
+    pub rx_malformed: u64,
+    /// Received packets dropped because of a checksum mismatch
+    pub rx_bad_checksum: u64,
+    /// Received packets addressed to the limited or a directed broadcast address
+    pub rx_broadcast: u64,
+    /// Received packets addressed to a multicast group we are a member of
+    pub rx_multicast: u64,
+    /// Received frames dropped because they were sent to a multicast group we haven't joined
+    pub rx_filtered: u64,
+    /// Received frames dropped or rejected by the filter
+    pub rx_denied: u64,
+    /// Received packets dropped because they use a hardware or protocol type we don't handle
+    pub rx_unsupported: u64,
+    /// Received IPv4 fragments
+    pub rx_fragments: u64,
+    /// Datagrams put back together from fragments
+    pub rx_reassembled: u64,
+    /// Fragmented datagrams given up on: timed out, evicted or inconsistent
+    pub rx_reassembly_failed: u64,
+    /// Fragments sent for packets exceeding the MTU
+    pub tx_fragments: u64,
+    /// Outgoing packets exceeding the MTU that had DF set
+    pub tx_fragmentation_needed: u64,
+    /// Outgoing frames dropped by the filter
+    pub tx_denied: u64,
+    /// Outgoing packets dropped because a queue was full
+    pub tx_dropped: u64,
+    /// Outgoing packets dropped because no route matched their destination
+    pub tx_no_route: u64,
+    /// Outgoing packets dropped because the neighbor's hardware address could not be resolved
+    pub tx_unresolved: u64,
+}
+
+/// Counters of a `Router`
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ForwardingStats {
+    /// Packets sent on towards their destination
+    pub forwarded: u64,
+    /// Packets for the address of another interface than the one they arrived on
+    pub delivered_locally: u64,
+    /// Packets dropped because their TTL ran out
+    pub ttl_exceeded: u64,
+    /// Packets dropped because no route matched their destination
+    pub no_route: u64,
+    /// Packets dropped or rejected by the forward rules of the filter
+    pub denied: u64,
+    /// Packets whose source or destination was rewritten by the NAT
+    pub translated: u64,
+    /// Packets to be sent out of a masquerading interface that the NAT couldn't translate
+    pub untranslatable: u64,
+    /// Packets sent by tunnel interfaces, wrapped for their remote end
+    pub encapsulated: u64,
+    /// Packets taken out of GRE or IP-in-IP packets and received on a tunnel interface
+    pub decapsulated: u64,
+    /// GRE or IP-in-IP packets no tunnel interface was configured for
+    pub no_tunnel: u64,
+}
@@ -0,0 +1,384 @@
+//! Tests driving whole interfaces through in-memory devices, and the frame builders they share
+//! with the tests of the other `net` modules
+
+use std::convert::TryFrom;
+use std::time::Instant;
+
+use crate::device::{loopback::Loopback, pipe, Device, DeviceCapabilities};
+use crate::net::config::InterfaceAddress;
+use crate::net::{Interface, InterfaceConfig, Router};
+use crate::protocols::arp::ArpPacket;
+use crate::protocols::{HardwareAddress, ProtocolAddress};
+
+pub(crate) const PEER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+pub(crate) const PEER_IP: [u8; 4] = [169, 254, 0, 1];
+pub(crate) const STACK_MAC: [u8; 6] = [0x02, 0xDE, 0xAD, 0x00, 0xBE, 0xEF];
+pub(crate) const STACK_IP: [u8; 4] = [169, 254, 0, 2];
+
+pub(crate) const PROTOCOL_ICMP: u8 = 1;
+pub(crate) const PROTOCOL_TCP: u8 = 6;
+/// Don't fragment flag in the flags and fragment offset field
+pub(crate) const DONT_FRAGMENT: u16 = 0x4000;
+/// More fragments flag in the flags and fragment offset field
+pub(crate) const MORE_FRAGMENTS: u16 = 0x2000;
+
+pub(crate) fn checksum(bytes: &[u8]) -> [u8; 2] {
+    internet_checksum::checksum(bytes)
+}
+
+/// Ethernet frame from the peer
+pub(crate) fn ethernet(destination: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    ethernet_from(PEER_MAC, destination, ethertype, payload)
+}
+
+pub(crate) fn ethernet_from(source: [u8; 6], destination: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = destination.to_vec();
+    frame.extend_from_slice(&source);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// IPv4 packet with a TTL of 64 and a valid header checksum
+pub(crate) fn ipv4(source: [u8; 4], destination: [u8; 4], protocol: u8, identification: u16, fragment: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&identification.to_be_bytes());
+    packet.extend_from_slice(&fragment.to_be_bytes());
+    packet.extend_from_slice(&[64, protocol, 0, 0]);
+    packet.extend_from_slice(&source);
+    packet.extend_from_slice(&destination);
+    let header_checksum = checksum(&packet);
+    packet[10..12].copy_from_slice(&header_checksum);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// IPv4 packet like `ipv4` with `options`, already padded to a multiple of 4 bytes, in its header
+pub(crate) fn ipv4_with_options(source: [u8; 4], destination: [u8; 4], protocol: u8, options: &[u8], payload: &[u8]) -> Vec<u8> {
+    assert!(options.len().is_multiple_of(4));
+    let mut packet = ipv4(source, destination, protocol, 1, 0, &[]);
+    packet[0] = 0x45 + options.len() as u8 / 4;
+    packet.extend_from_slice(options);
+    packet.extend_from_slice(payload);
+    let total_length = packet.len() as u16;
+    packet[2..4].copy_from_slice(&total_length.to_be_bytes());
+    set_header_checksum(&mut packet);
+    packet
+}
+
+/// Recalculate the header checksum of an IPv4 packet after changing its header
+pub(crate) fn set_header_checksum(packet: &mut [u8]) {
+    let header_length = (packet[0] & 0x0F) as usize * 4;
+    packet[10..12].copy_from_slice(&[0, 0]);
+    let header_checksum = checksum(&packet[..header_length]);
+    packet[10..12].copy_from_slice(&header_checksum);
+}
+
+/// IPv4 fragments carrying `payload`, each with at most `fragment_size` bytes of it
+pub(crate) fn ipv4_fragments(source: [u8; 4], destination: [u8; 4], protocol: u8, identification: u16, payload: &[u8], fragment_size: usize) -> Vec<Vec<u8>> {
+    assert!(fragment_size.is_multiple_of(8));
+    payload.chunks(fragment_size).enumerate()
+        .map(|(index, data)| {
+            let offset = (index * fragment_size / 8) as u16;
+            let more = if (index + 1) * fragment_size < payload.len() { MORE_FRAGMENTS } else { 0 };
+            ipv4(source, destination, protocol, identification, offset | more, data)
+        })
+        .collect()
+}
+
+/// IPv4 packet from the peer to the stack in a frame
+pub(crate) fn ipv4_frame(protocol: u8, payload: &[u8]) -> Vec<u8> {
+    ethernet(STACK_MAC, 0x0800, &ipv4(PEER_IP, STACK_IP, protocol, 1, 0, payload))
+}
+
+pub(crate) fn echo_request(identifier: u16, sequence: u16, data_len: usize) -> Vec<u8> {
+    let mut packet = vec![8, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend((0..data_len).map(|i| i as u8));
+    let icmp_checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&icmp_checksum);
+    packet
+}
+
+/// TCP header without options, followed by `data`. The checksum is left zero.
+pub(crate) fn tcp(source_port: u16, destination_port: u16, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = source_port.to_be_bytes().to_vec();
+    segment.extend_from_slice(&destination_port.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+    segment.extend_from_slice(data);
+    segment
+}
+
+/// ARP request from the peer for `target`
+pub(crate) fn arp_request(target: [u8; 4]) -> Vec<u8> {
+    let mut packet = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+    packet.extend_from_slice(&PEER_MAC);
+    packet.extend_from_slice(&PEER_IP);
+    packet.extend_from_slice(&[0; 6]);
+    packet.extend_from_slice(&target);
+    ethernet([0xFF; 6], 0x0806, &packet)
+}
+
+/// ARP reply from `sender` to `target`, each a hardware and protocol address
+pub(crate) fn arp_reply(sender: ([u8; 6], [u8; 4]), target: ([u8; 6], [u8; 4])) -> Vec<u8> {
+    let mut packet = vec![0, 1, 0x08, 0x00, 6, 4, 0, 2];
+    packet.extend_from_slice(&sender.0);
+    packet.extend_from_slice(&sender.1);
+    packet.extend_from_slice(&target.0);
+    packet.extend_from_slice(&target.1);
+    ethernet_from(sender.0, target.0, 0x0806, &packet)
+}
+
+pub(crate) fn config() -> InterfaceConfig {
+    InterfaceConfig { duplicate_address_detection: false, ..Default::default() }
+}
+
+/// An interface with the default address on one end of a pipe, which already knows the peer on the other end
+pub(crate) fn interface() -> (pipe::Pipe, Interface<pipe::Pipe>) {
+    let (mut peer, device) = pipe::pair(DeviceCapabilities::default());
+    let mut interface = Interface::new(device, config());
+    peer.transmit(&arp_request(STACK_IP)).unwrap();
+    poll(&mut interface);
+    receive_all(&mut peer);
+    (peer, interface)
+}
+
+/// Hardware and protocol addresses of the router on link A, and of a host there
+pub(crate) const ROUTER_A: ([u8; 6], [u8; 4]) = ([2, 0, 0, 0, 0, 0x0A], [10, 0, 1, 1]);
+pub(crate) const HOST_A: ([u8; 6], [u8; 4]) = ([2, 0, 0, 0, 1, 1], [10, 0, 1, 10]);
+/// Hardware and protocol addresses of the router on link B, and of a host there
+pub(crate) const ROUTER_B: ([u8; 6], [u8; 4]) = ([2, 0, 0, 0, 0, 0x0B], [10, 0, 2, 1]);
+pub(crate) const HOST_B: ([u8; 6], [u8; 4]) = ([2, 0, 0, 0, 2, 2], [10, 0, 2, 20]);
+
+/// A router forwarding between 10.0.1.0/24 on link A and 10.0.2.0/24 on link B, which already knows
+/// `HOST_A` and `HOST_B`. Returns the far ends of the links and the router.
+pub(crate) fn router(now: Instant) -> (pipe::Pipe, pipe::Pipe, Router<pipe::Pipe>) {
+    let (mut link_a, device_a) = pipe::pair(DeviceCapabilities::default());
+    let (mut link_b, device_b) = pipe::pair(DeviceCapabilities::default());
+
+    let mut router = Router::new();
+    for (device, (mac, address)) in [(device_a, ROUTER_A), (device_b, ROUTER_B)] {
+        let address = InterfaceAddress::new(address, 24);
+        let config = InterfaceConfig { mac, addresses: vec![address], forwarding: true, ..config() };
+        router.add_interface(Interface::new(device, config));
+    }
+    link_a.transmit(&arp_reply(HOST_A, ROUTER_A)).unwrap();
+    link_b.transmit(&arp_reply(HOST_B, ROUTER_B)).unwrap();
+    poll_router(&mut router, now);
+    (link_a, link_b, router)
+}
+
+pub(crate) fn poll_router<D: Device>(router: &mut Router<D>, now: Instant) -> bool {
+    let mut rx_buffer = vec![0; 1514];
+    let mut tx_buffer = vec![0; 1514];
+    router.poll(now, &mut rx_buffer, &mut tx_buffer).unwrap()
+}
+
+pub(crate) fn poll<D: Device>(interface: &mut Interface<D>) -> bool {
+    poll_at(interface, Instant::now())
+}
+
+pub(crate) fn poll_at<D: Device>(interface: &mut Interface<D>, now: Instant) -> bool {
+    let mut rx_buffer = vec![0; 1514];
+    let mut tx_buffer = vec![0; 1514];
+    interface.poll(now, &mut rx_buffer, &mut tx_buffer).unwrap()
+}
+
+/// Every frame waiting on `device`
+pub(crate) fn receive_all<D: Device>(device: &mut D) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut buffer = vec![0; 1514];
+    loop {
+        let size = device.receive(&mut buffer).unwrap();
+        if size == 0 {
+            return frames;
+        }
+        frames.push(buffer[..size].to_vec());
+    }
+}
+
+#[test]
+fn answers_arp_requests_for_its_address() {
+    let (mut peer, device) = pipe::pair(DeviceCapabilities::default());
+    let mut interface = Interface::new(device, config());
+
+    peer.transmit(&arp_request(STACK_IP)).unwrap();
+    assert!(poll(&mut interface));
+    let replies = receive_all(&mut peer);
+    assert_eq!(replies.len(), 1);
+    let reply = &replies[0];
+    assert_eq!(&reply[0..6], &PEER_MAC);
+    assert_eq!(&reply[6..12], &STACK_MAC);
+    assert_eq!(&reply[12..14], &[0x08, 0x06]);
+    assert_eq!(&reply[20..22], &[0, 2]);
+    assert_eq!(&reply[22..28], &STACK_MAC);
+    assert_eq!(&reply[28..32], &STACK_IP);
+    assert_eq!(&reply[32..38], &PEER_MAC);
+    assert_eq!(&reply[38..42], &PEER_IP);
+
+    assert!(!poll(&mut interface));
+}
+
+#[test]
+fn ignores_arp_requests_for_other_addresses() {
+    let (mut peer, mut interface) = interface();
+    peer.transmit(&arp_request([169, 254, 0, 3])).unwrap();
+    assert!(poll(&mut interface));
+    assert!(receive_all(&mut peer).is_empty());
+}
+
+#[test]
+fn answers_echo_requests_of_any_size() {
+    let (mut peer, mut interface) = interface();
+    for &size in &[0, 1, 32, 56, 1000, 1472] {
+        let request = echo_request(0x1234, 7, size);
+        peer.transmit(&ipv4_frame(PROTOCOL_ICMP, &request)).unwrap();
+        assert!(poll(&mut interface));
+
+        let replies = receive_all(&mut peer);
+        assert_eq!(replies.len(), 1, "{} bytes", size);
+        let reply = &replies[0];
+        assert_eq!(reply.len(), 14 + 20 + 8 + size);
+        assert_eq!(&reply[0..6], &PEER_MAC);
+        assert_eq!(&reply[26..30], &STACK_IP);
+        assert_eq!(&reply[30..34], &PEER_IP);
+        assert_eq!(checksum(&reply[14..34]), [0, 0]);
+        assert_eq!(reply[34], 0);
+        assert_eq!(checksum(&reply[34..]), [0, 0]);
+        assert_eq!(&reply[38..], &request[4..]);
+    }
+}
+
+#[test]
+fn drops_malformed_frames() {
+    let (mut peer, mut interface) = interface();
+    let mut bad_version = ipv4_frame(PROTOCOL_ICMP, &echo_request(1, 1, 8));
+    bad_version[14] = 0x65;
+    let mut bad_ihl = ipv4_frame(PROTOCOL_ICMP, &echo_request(1, 1, 8));
+    bad_ihl[14] = 0x43;
+    let frames = vec![
+        vec![1, 2, 3],
+        bad_version,
+        bad_ihl,
+        ipv4_frame(PROTOCOL_ICMP, &[8, 0, 0]),
+        arp_request(STACK_IP)[..30].to_vec(),
+    ];
+
+    for frame in &frames {
+        peer.transmit(frame).unwrap();
+        assert!(poll(&mut interface));
+    }
+    assert_eq!(interface.stats().rx_malformed, frames.len() as u64);
+    assert!(receive_all(&mut peer).is_empty());
+}
+
+#[test]
+fn drops_packets_with_bad_checksums() {
+    let (mut peer, mut interface) = interface();
+    let mut bad_header = ipv4_frame(PROTOCOL_ICMP, &echo_request(1, 1, 56));
+    bad_header[24] ^= 0xFF;
+    let mut bad_icmp = ipv4_frame(PROTOCOL_ICMP, &echo_request(1, 1, 56));
+    bad_icmp[50] ^= 0xFF;
+
+    peer.transmit(&bad_header).unwrap();
+    peer.transmit(&bad_icmp).unwrap();
+    poll(&mut interface);
+    poll(&mut interface);
+    assert_eq!(interface.stats().rx_bad_checksum, 2);
+    assert!(receive_all(&mut peer).is_empty());
+}
+
+#[test]
+fn update_transmits_replies_on_the_same_device() {
+    let mut interface = Interface::new(Loopback::new(DeviceCapabilities::default()), config());
+    let mut tx_buffer = vec![0; 1514];
+
+    interface.update(Instant::now(), &mut arp_request(STACK_IP), &mut tx_buffer).unwrap();
+    let replies = receive_all(interface.device_mut());
+    assert_eq!(replies.len(), 1);
+    assert_eq!(&replies[0][28..32], &STACK_IP);
+    assert_eq!(interface.stats().rx_frames, 1);
+    assert_eq!(interface.stats().tx_frames, 1);
+}
+
+#[test]
+fn mtus_below_the_minimum_are_raised_to_it() {
+    let (mut peer, mut interface) = interface();
+    interface.config_mut().mtu = 0;
+    assert_eq!(interface.mtu(), crate::net::config::MIN_MTU);
+
+    peer.transmit(&ipv4_frame(PROTOCOL_ICMP, &echo_request(1, 1, 100))).unwrap();
+    poll(&mut interface);
+    let fragments = receive_all(&mut peer);
+    assert_eq!(fragments.len(), 3);
+    assert!(fragments.iter().all(|fragment| fragment.len() <= 14 + crate::net::config::MIN_MTU));
+}
+
+#[test]
+fn echo_replies_follow_reversed_source_routes_and_keep_the_dscp() {
+    let (mut peer, mut interface) = interface();
+    let source = [10, 9, 9, 9];
+    // Came from 10.9.9.9 through 10.0.0.1 and then the peer, marked expedited forwarding
+    let options = [1, 131, 11, 12, 10, 0, 0, 1, 169, 254, 0, 1];
+    let request = echo_request(9, 3, 32);
+    let mut packet = ipv4_with_options(source, STACK_IP, PROTOCOL_ICMP, &options, &request);
+    packet[1] = 46 << 2;
+    set_header_checksum(&mut packet);
+    peer.transmit(&ethernet(STACK_MAC, 0x0800, &packet)).unwrap();
+    poll(&mut interface);
+
+    let replies = receive_all(&mut peer);
+    assert_eq!(replies.len(), 1);
+    let reply = &replies[0];
+    assert_eq!(&reply[0..6], &PEER_MAC);
+    let header = &reply[14..14 + 32];
+    assert_eq!(checksum(header), [0, 0]);
+    assert_eq!(header[0], 0x48);
+    assert_eq!(header[1] >> 2, 46);
+    // Addressed to the last hop, with the rest of the way back to the sender in the option
+    assert_eq!(&header[12..16], &STACK_IP);
+    assert_eq!(&header[16..20], &PEER_IP);
+    assert_eq!(&header[20..32], &[131, 11, 4, 10, 0, 0, 1, 10, 9, 9, 9, 0]);
+
+    let icmp = &reply[14 + 32..];
+    assert_eq!(icmp[0], 0);
+    assert_eq!(checksum(icmp), [0, 0]);
+    assert_eq!(&icmp[4..], &request[4..]);
+}
+
+#[test]
+fn counts_arp_for_other_hardware_and_protocols_as_unsupported() {
+    let (mut peer, mut interface) = interface();
+
+    // IEEE 802 hardware type, asking for our IPv4 address
+    let mut ieee_802 = vec![0, 6, 0x08, 0x00, 6, 4, 0, 1];
+    ieee_802.extend_from_slice(&PEER_MAC);
+    ieee_802.extend_from_slice(&PEER_IP);
+    ieee_802.extend_from_slice(&[0; 6]);
+    ieee_802.extend_from_slice(&STACK_IP);
+    // Ethernet, but for 16 byte IPv6 addresses
+    let mut ipv6 = vec![0, 1, 0x86, 0xDD, 6, 16, 0, 1];
+    ipv6.extend_from_slice(&PEER_MAC);
+    ipv6.extend_from_slice(&[0xFE; 16]);
+    ipv6.extend_from_slice(&[0; 6]);
+    ipv6.extend_from_slice(&[0xFE; 16]);
+
+    let mut packet = ieee_802.clone();
+    let mut arp_packet = ArpPacket::try_from(&mut packet[..]).unwrap();
+    assert!(matches!(arp_packet.sha(), HardwareAddress::Unknown(bytes) if bytes[..] == PEER_MAC));
+    assert!(matches!(arp_packet.tpa(), ProtocolAddress::IPv4(_)));
+    let mut packet = ipv6.clone();
+    let mut arp_packet = ArpPacket::try_from(&mut packet[..]).unwrap();
+    assert!(matches!(arp_packet.sha(), HardwareAddress::MAC(_)));
+    assert!(matches!(arp_packet.spa(), ProtocolAddress::Unknown(bytes) if bytes.len() == 16));
+
+    for packet in &[ieee_802, ipv6] {
+        peer.transmit(&ethernet([0xFF; 6], 0x0806, packet)).unwrap();
+        assert!(poll(&mut interface));
+    }
+    assert_eq!(interface.stats().rx_unsupported, 2);
+    assert_eq!(interface.stats().rx_malformed, 0);
+    assert!(receive_all(&mut peer).is_empty());
+}
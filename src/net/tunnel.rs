@@ -0,0 +1,301 @@
+use std::convert::TryFrom;
+
+use crate::device::{pipe, Device, DeviceCapabilities, Medium, Pipe};
+use crate::error::Error;
+use crate::net::config::MIN_MTU;
+use crate::protocols::ethernet;
+use crate::protocols::gre::{self, GrePacket};
+use crate::protocols::ipv4::{IpPayload, Ipv4Packet};
+
+/// What a tunnel carries and how
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TunnelKind {
+    /// IPv4 in GRE (RFC 2784)
+    Gre,
+    /// Ethernet in GRE, joining the links at both ends (Transparent Ethernet Bridging)
+    GreTap,
+    /// IPv4 in IPv4 (RFC 2003)
+    IpIp,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TunnelConfig {
+    pub kind: TunnelKind,
+    /// Our address the encapsulating packets are sent from
+    pub local: [u8; 4],
+    /// Address of the other end of the tunnel
+    pub remote: [u8; 4],
+    /// GRE key both ends have to agree on (RFC 2890), unused by IP-in-IP
+    pub key: Option<u32>,
+    /// TTL of the encapsulating packets
+    pub time_to_live: u8,
+}
+
+impl TunnelConfig {
+    pub fn new(kind: TunnelKind, local: [u8; 4], remote: [u8; 4]) -> TunnelConfig {
+        TunnelConfig { kind, local, remote, key: None, time_to_live: 64 }
+    }
+
+    /// Bytes the encapsulation adds to every packet
+    pub fn overhead(&self) -> usize {
+        let gre_header = 4 + if self.key.is_some() { 4 } else { 0 };
+        match self.kind {
+            TunnelKind::Gre => 20 + gre_header,
+            TunnelKind::GreTap => 20 + gre_header + ethernet::HEADER_LEN,
+            TunnelKind::IpIp => 20,
+        }
+    }
+
+    fn medium(&self) -> Medium {
+        match self.kind {
+            TunnelKind::GreTap => Medium::Ethernet,
+            TunnelKind::Gre | TunnelKind::IpIp => Medium::Ip,
+        }
+    }
+
+    fn gre_protocol_type(&self) -> u16 {
+        match self.kind {
+            TunnelKind::GreTap => gre::PROTOCOL_TEB,
+            TunnelKind::Gre | TunnelKind::IpIp => gre::PROTOCOL_IPV4,
+        }
+    }
+}
+
+/// Carries what a tunnel interface sends to the remote end and back, see `pair`
+#[derive(Debug)]
+pub struct Tunnel {
+    config: TunnelConfig,
+    /// Our end of the link with the tunnel interface's device
+    port: Pipe,
+    /// Identification field of the next encapsulating packet
+    identification: u16,
+}
+
+/// Create a tunnel and the device for its interface, whose MTU leaves room for the encapsulation
+/// on an underlying link of `underlay_mtu`. It is never below `MIN_MTU` though, on links too small
+/// for that the encapsulating packets get fragmented.
+pub fn pair(config: TunnelConfig, underlay_mtu: usize) -> (Pipe, Tunnel) {
+    let capabilities = DeviceCapabilities {
+        mtu: underlay_mtu.saturating_sub(config.overhead()).max(MIN_MTU),
+        medium: config.medium(),
+        rx_checksum_offload: false,
+    };
+    let (device, port) = pipe::pair(capabilities);
+    (device, Tunnel { config, port, identification: 0 })
+}
+
+impl Tunnel {
+    pub fn config(&self) -> &TunnelConfig {
+        &self.config
+    }
+
+    /// Take the next packet or frame the tunnel interface sent, wrapped in an IPv4 packet for the remote end
+    pub fn encapsulate(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut inner = vec![0_u8; self.port.capabilities().max_frame_size()];
+        let size = self.port.receive(&mut inner)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        let inner = &inner[..size];
+
+        let gre_header = match self.config.kind {
+            TunnelKind::IpIp => 0,
+            _ => 4 + if self.config.key.is_some() { 4 } else { 0 },
+        };
+        let mut packet = vec![0_u8; 20 + gre_header + inner.len()];
+        let mut ipv4_packet = Ipv4Packet::new(
+            &mut packet,
+            &(&mut self.config.local).into(),
+            &(&mut self.config.remote).into(),
+        );
+        ipv4_packet.header().set_identification(self.identification);
+        ipv4_packet.header().set_time_to_live(self.config.time_to_live);
+        self.identification = self.identification.wrapping_add(1);
+
+        let payload_buffer = ipv4_packet.take_payload_buffer();
+        let payload = match self.config.kind {
+            TunnelKind::IpIp => {
+                payload_buffer.copy_from_slice(inner);
+                IpPayload::IPIP(payload_buffer)
+            },
+            TunnelKind::Gre | TunnelKind::GreTap => {
+                let mut gre_packet = GrePacket::new(payload_buffer, self.config.key);
+                gre_packet.set_protocol_type(self.config.gre_protocol_type());
+                gre_packet.set_data(inner);
+                IpPayload::GRE(gre_packet)
+            },
+        };
+        ipv4_packet.set_payload(payload);
+        ipv4_packet.header().calculate_checksum();
+        Ok(Some(packet))
+    }
+
+    /// The packet or frame carried by a GRE or IP-in-IP packet, if it came from the remote end of this tunnel
+    pub fn decapsulate(&self, packet: &mut [u8]) -> Option<Vec<u8>> {
+        let mut ipv4_packet = Ipv4Packet::try_from(packet).ok()?;
+        let header = ipv4_packet.header();
+        if header.source_ip().get_address() != self.config.remote || header.destination_ip().get_address() != self.config.local {
+            return None;
+        }
+
+        match (self.config.kind, ipv4_packet.payload()) {
+            (TunnelKind::IpIp, IpPayload::IPIP(inner)) => Some(inner.to_vec()),
+            (TunnelKind::Gre, IpPayload::GRE(gre_packet)) | (TunnelKind::GreTap, IpPayload::GRE(gre_packet)) => {
+                let matches = gre_packet.key() == self.config.key
+                    && gre_packet.protocol_type() == self.config.gre_protocol_type()
+                    && gre_packet.verify_checksum();
+                Some(gre_packet.data.to_vec()).filter(|_| matches)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::net::config::InterfaceAddress;
+    use crate::net::tests::*;
+    use crate::net::{Interface, InterfaceConfig};
+
+    const LOCAL: [u8; 4] = [10, 0, 0, 1];
+    const REMOTE: [u8; 4] = [10, 0, 0, 2];
+
+    /// Both ends of a tunnel, each with the device of its interface
+    fn ends(kind: TunnelKind, key: Option<u32>) -> ((Pipe, Tunnel), (Pipe, Tunnel)) {
+        let config = TunnelConfig { key, ..TunnelConfig::new(kind, LOCAL, REMOTE) };
+        let remote_config = TunnelConfig { local: REMOTE, remote: LOCAL, ..config };
+        (pair(config, 1500), pair(remote_config, 1500))
+    }
+
+    /// What the tunnel interface sends, to be encapsulated
+    fn inner(kind: TunnelKind) -> Vec<u8> {
+        let packet = ipv4([192, 168, 100, 1], [192, 168, 100, 2], PROTOCOL_ICMP, 1, 0, &echo_request(1, 1, 32));
+        match kind {
+            TunnelKind::GreTap => ethernet_from(STACK_MAC, PEER_MAC, 0x0800, &packet),
+            TunnelKind::Gre | TunnelKind::IpIp => packet,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_both_ends() {
+        for &(kind, key, protocol, tunnel_header) in &[
+            (TunnelKind::Gre, None, 47, &[0, 0, 0x08, 0x00][..]),
+            (TunnelKind::Gre, Some(42), 47, &[gre::FLAG_KEY, 0, 0x08, 0x00, 0, 0, 0, 42][..]),
+            (TunnelKind::GreTap, None, 47, &[0, 0, 0x65, 0x58][..]),
+            (TunnelKind::IpIp, None, 4, &[][..]),
+        ] {
+            let ((mut device, mut tunnel), (_, remote_tunnel)) = ends(kind, key);
+            assert_eq!(tunnel.encapsulate().unwrap(), None);
+            let inner = inner(kind);
+            device.transmit(&inner).unwrap();
+
+            let mut packet = tunnel.encapsulate().unwrap().unwrap();
+            assert_eq!(packet.len(), 20 + tunnel_header.len() + inner.len());
+            assert_eq!(checksum(&packet[..20]), [0, 0]);
+            assert_eq!((packet[8], packet[9]), (64, protocol));
+            assert_eq!(&packet[12..16], &LOCAL);
+            assert_eq!(&packet[16..20], &REMOTE);
+            assert_eq!(&packet[20..20 + tunnel_header.len()], tunnel_header, "{:?}", kind);
+
+            assert_eq!(remote_tunnel.decapsulate(&mut packet), Some(inner), "{:?}", kind);
+            // Not from the remote end of our own tunnel
+            assert_eq!(tunnel.decapsulate(&mut packet), None);
+        }
+    }
+
+    #[test]
+    fn numbers_the_encapsulating_packets() {
+        let ((mut device, mut tunnel), _) = ends(TunnelKind::IpIp, None);
+        device.transmit(&inner(TunnelKind::IpIp)).unwrap();
+        device.transmit(&inner(TunnelKind::IpIp)).unwrap();
+        let first = tunnel.encapsulate().unwrap().unwrap();
+        let second = tunnel.encapsulate().unwrap().unwrap();
+        assert_eq!(u16::from_be_bytes([second[4], second[5]]), u16::from_be_bytes([first[4], first[5]]) + 1);
+    }
+
+    #[test]
+    fn rejects_other_keys_and_protocols() {
+        let ((mut device, mut tunnel), _) = ends(TunnelKind::Gre, Some(1));
+        device.transmit(&inner(TunnelKind::Gre)).unwrap();
+        let mut packet = tunnel.encapsulate().unwrap().unwrap();
+
+        for key in &[None, Some(2)] {
+            let (_, (_, remote_tunnel)) = ends(TunnelKind::Gre, *key);
+            assert_eq!(remote_tunnel.decapsulate(&mut packet), None, "{:?}", key);
+        }
+        let (_, (_, remote_tunnel)) = ends(TunnelKind::GreTap, Some(1));
+        assert_eq!(remote_tunnel.decapsulate(&mut packet), None);
+        let (_, (_, remote_tunnel)) = ends(TunnelKind::IpIp, None);
+        assert_eq!(remote_tunnel.decapsulate(&mut packet), None);
+    }
+
+    #[test]
+    fn rejects_packets_from_or_to_other_addresses() {
+        let (_, (_, remote_tunnel)) = ends(TunnelKind::IpIp, None);
+        let inner = inner(TunnelKind::IpIp);
+        assert!(remote_tunnel.decapsulate(&mut ipv4(LOCAL, REMOTE, 4, 1, 0, &inner)).is_some());
+        assert_eq!(remote_tunnel.decapsulate(&mut ipv4([10, 0, 0, 3], REMOTE, 4, 1, 0, &inner)), None);
+        assert_eq!(remote_tunnel.decapsulate(&mut ipv4(LOCAL, [10, 0, 0, 3], 4, 1, 0, &inner)), None);
+    }
+
+    #[test]
+    fn rejects_bad_gre_checksums() {
+        let (_, (_, remote_tunnel)) = ends(TunnelKind::Gre, None);
+        let mut gre = vec![gre::FLAG_CHECKSUM, 0, 0x08, 0x00, 0, 0, 0, 0];
+        gre.extend_from_slice(&inner(TunnelKind::Gre));
+        let gre_checksum = checksum(&gre);
+        gre[4..6].copy_from_slice(&gre_checksum);
+        assert!(remote_tunnel.decapsulate(&mut ipv4(LOCAL, REMOTE, 47, 1, 0, &gre)).is_some());
+
+        gre[5] ^= 0xFF;
+        assert_eq!(remote_tunnel.decapsulate(&mut ipv4(LOCAL, REMOTE, 47, 1, 0, &gre)), None);
+    }
+
+    #[test]
+    fn router_receives_decapsulated_packets_on_the_tunnel_interface() {
+        let now = Instant::now();
+        let (_link_a, mut link_b, mut router) = router(now);
+        let (device, tunnel) = pair(TunnelConfig::new(TunnelKind::IpIp, ROUTER_B.1, HOST_B.1), 1500);
+        let config = InterfaceConfig { addresses: vec![InterfaceAddress::new([192, 168, 100, 1], 30)], ..config() };
+        let index = router.add_tunnel(Interface::new(device, config), tunnel);
+
+        let request = ipv4([192, 168, 100, 2], [192, 168, 100, 1], PROTOCOL_ICMP, 1, 0, &echo_request(1, 1, 32));
+        let outer = ipv4(HOST_B.1, ROUTER_B.1, 4, 1, 0, &request);
+        link_b.transmit(&ethernet_from(HOST_B.0, ROUTER_B.0, 0x0800, &outer)).unwrap();
+        poll_router(&mut router, now);
+
+        assert_eq!(router.stats().decapsulated, 1);
+        assert_eq!(router.interface(index).stats().rx_frames, 1);
+
+        // The reply goes back through the tunnel
+        let replies = receive_all(&mut link_b);
+        assert_eq!(replies.len(), 1);
+        let reply = &replies[0][14..];
+        assert_eq!((reply[9], &reply[12..16], &reply[16..20]), (4, &ROUTER_B.1[..], &HOST_B.1[..]));
+        let inner = &reply[20..];
+        assert_eq!(&inner[12..16], &[192, 168, 100, 1]);
+        assert_eq!(&inner[16..20], &[192, 168, 100, 2]);
+        assert_eq!(inner[20], 0);
+        assert_eq!(router.stats().encapsulated, 1);
+    }
+
+    #[test]
+    fn interface_mtu_leaves_room_for_the_encapsulation() {
+        let mut config = TunnelConfig::new(TunnelKind::Gre, [10, 0, 0, 1], [10, 0, 0, 2]);
+        assert_eq!(pair(config, 1500).0.capabilities().mtu, 1500 - 24);
+        config.key = Some(1);
+        assert_eq!(pair(config, 1500).0.capabilities().mtu, 1500 - 28);
+        config.kind = TunnelKind::IpIp;
+        assert_eq!(pair(config, 1500).0.capabilities().mtu, 1500 - 20);
+    }
+
+    #[test]
+    fn interface_mtu_is_never_below_the_minimum() {
+        let config = TunnelConfig::new(TunnelKind::GreTap, [10, 0, 0, 1], [10, 0, 0, 2]);
+        assert_eq!(pair(config, 80).0.capabilities().mtu, MIN_MTU);
+        assert_eq!(pair(config, 0).0.capabilities().mtu, MIN_MTU);
+    }
+}
@@ -1,17 +1,106 @@
 pub mod arp;
 pub mod ethernet;
+pub mod gre;
 pub mod ipv4;
 pub mod icmp;
+pub mod igmp;
+pub mod tcp;
+pub mod udp;
 
 use ipv4::Ipv4Address;
 use ethernet::MacAddress;
 
+/// ARP hardware type of Ethernet
+pub const HARDWARE_TYPE_ETHERNET: u16 = 0x0001;
+/// ARP protocol type of IPv4, same as its EtherType
+pub const PROTOCOL_TYPE_IPV4: u16 = 0x0800;
+
+/// Update an internet checksum for the bytes `old` being replaced with `new` (RFC 1624).
+/// Both must be the same, even length and start at an even offset of the checksummed data.
+pub fn adjust_checksum(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    assert!(old.len() == new.len() && old.len().is_multiple_of(2), "Checksum adjusted for uneven data");
+
+    // HC' = ~(~HC + ~m + m')
+    let mut sum = !checksum as u32;
+    for (old_word, new_word) in old.chunks(2).zip(new.chunks(2)) {
+        sum += !u16::from_be_bytes([old_word[0], old_word[1]]) as u32;
+        sum += u16::from_be_bytes([new_word[0], new_word[1]]) as u32;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// IPv4 pseudo header covered by the TCP and UDP checksums
+pub fn pseudo_header(source: &[u8; 4], destination: &[u8; 4], protocol: u8, length: u16) -> [u8; 12] {
+    let mut header = [0; 12];
+    header[0..4].copy_from_slice(source);
+    header[4..8].copy_from_slice(destination);
+    header[9] = protocol;
+    header[10..12].copy_from_slice(&length.to_be_bytes());
+    header
+}
+
 #[derive(Debug)]
 pub enum HardwareAddress<'a> {
     MAC(MacAddress<'a>),
+    /// Address of a hardware type or length we don't understand
+    Unknown(&'a mut [u8]),
+}
+
+impl<'a> HardwareAddress<'a> {
+    /// Hardware type used in ARP packets, `None` for unknown addresses
+    pub fn hardware_type(&self) -> Option<u16> {
+        match self {
+            HardwareAddress::MAC(_) => Some(HARDWARE_TYPE_ETHERNET),
+            HardwareAddress::Unknown(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            HardwareAddress::MAC(mac) => mac.as_bytes(),
+            HardwareAddress::Unknown(bytes) => bytes,
+        }
+    }
+
+    pub fn as_mac(&self) -> Option<&MacAddress<'a>> {
+        match self {
+            HardwareAddress::MAC(mac) => Some(mac),
+            HardwareAddress::Unknown(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ProtocolAddress<'a> {
     IPv4(Ipv4Address<'a>),
-}
\ No newline at end of file
+    /// Address of a protocol type or length we don't understand
+    Unknown(&'a mut [u8]),
+}
+
+impl<'a> ProtocolAddress<'a> {
+    /// Protocol type used in ARP packets, `None` for unknown addresses
+    pub fn protocol_type(&self) -> Option<u16> {
+        match self {
+            ProtocolAddress::IPv4(_) => Some(PROTOCOL_TYPE_IPV4),
+            ProtocolAddress::Unknown(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ProtocolAddress::IPv4(ip) => ip.as_bytes(),
+            ProtocolAddress::Unknown(bytes) => bytes,
+        }
+    }
+
+    pub fn as_ipv4(&self) -> Option<&Ipv4Address<'a>> {
+        match self {
+            ProtocolAddress::IPv4(ip) => Some(ip),
+            ProtocolAddress::Unknown(_) => None,
+        }
+    }
+}
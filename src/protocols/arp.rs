@@ -1,5 +1,7 @@
 use byteorder::{BigEndian, ReadBytesExt};
-use crate::protocols::{HardwareAddress,ProtocolAddress};
+use crate::error::ParseError;
+use crate::protocols::{HardwareAddress, ProtocolAddress, HARDWARE_TYPE_ETHERNET, PROTOCOL_TYPE_IPV4};
+use std::convert::{TryFrom, TryInto};
 use std::fmt::Formatter;
 
 pub struct ArpPacket<'a> {
@@ -17,39 +19,54 @@ pub enum ArpOperation {
     INVALID,
 }
 
-impl<'a> From <&'a mut [u8]> for ArpPacket<'a> {
-    fn from(frame: &'a mut [u8]) -> ArpPacket {
+impl<'a> TryFrom<&'a mut [u8]> for ArpPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 8 {
+            return Err(ParseError::Truncated);
+        }
 
         let (header, rest) = frame.split_at_mut(8);
 
-        let htype = header[0..2].as_ref().clone().read_u16::<BigEndian>().unwrap();
-        let ptype = header[2..4].as_ref().clone().read_u16::<BigEndian>().unwrap();
-        let hlen = header[4];
-        let plen = header[5];
-
-        // Support only ethernet
-        assert_eq!(htype, 0x0001);
-        assert_eq!(hlen, 6); // MAC -> 6 octets
-        // Support only IPv4
-        assert_eq!(ptype, 0x0800);
-        assert_eq!(plen, 4); // IPv4 -> 4 octets
-
-        let (sha_bytes, rest) = rest.split_at_mut(hlen as usize);
-        let (spa_bytes, rest) = rest.split_at_mut(plen as usize);
-        let (tha_bytes, rest) = rest.split_at_mut(hlen as usize);
-        let (tpa_bytes, rest) = rest.split_at_mut(plen as usize);
-        assert_eq!(rest.len(), 0);
-
-        ArpPacket {
-            header,
-            sha: HardwareAddress::MAC(sha_bytes.into()),
-            spa: ProtocolAddress::IPv4(spa_bytes.into()),
-            tha: HardwareAddress::MAC(tha_bytes.into()),
-            tpa: ProtocolAddress::IPv4(tpa_bytes.into()),
+        let htype = header[0..2].as_ref().read_u16::<BigEndian>().unwrap();
+        let ptype = header[2..4].as_ref().read_u16::<BigEndian>().unwrap();
+        let hlen = header[4] as usize;
+        let plen = header[5] as usize;
+
+        // Anything past the addresses is Ethernet padding
+        if rest.len() < 2 * (hlen + plen) {
+            return Err(ParseError::Truncated);
         }
+
+        let (sha_bytes, rest) = rest.split_at_mut(hlen);
+        let (spa_bytes, rest) = rest.split_at_mut(plen);
+        let (tha_bytes, rest) = rest.split_at_mut(hlen);
+        let (tpa_bytes, _padding) = rest.split_at_mut(plen);
+
+        Ok(ArpPacket {
+            header,
+            sha: hardware_address(htype, sha_bytes)?,
+            spa: protocol_address(ptype, spa_bytes)?,
+            tha: hardware_address(htype, tha_bytes)?,
+            tpa: protocol_address(ptype, tpa_bytes)?,
+        })
     }
 }
 
+fn hardware_address(htype: u16, bytes: &mut [u8]) -> Result<HardwareAddress<'_>, ParseError> {
+    Ok(match (htype, bytes.len()) {
+        (HARDWARE_TYPE_ETHERNET, 6) => HardwareAddress::MAC(bytes.try_into()?),
+        _ => HardwareAddress::Unknown(bytes),
+    })
+}
+
+fn protocol_address(ptype: u16, bytes: &mut [u8]) -> Result<ProtocolAddress<'_>, ParseError> {
+    Ok(match (ptype, bytes.len()) {
+        (PROTOCOL_TYPE_IPV4, 4) => ProtocolAddress::IPv4(bytes.try_into()?),
+        _ => ProtocolAddress::Unknown(bytes),
+    })
+}
 
 impl<'a> ArpPacket<'a> {
     pub fn new(
@@ -61,10 +78,20 @@ impl<'a> ArpPacket<'a> {
         param_tpa: &ProtocolAddress,
 
     ) -> ArpPacket<'a> {
-        let (buffer, _excess) = buffer.split_at_mut(28);
-        // Header: ethernet/ipv4
-        let header = [0, 1, 8, 0, 6, 4];
-        buffer[0..6].copy_from_slice(&header);
+        let htype = param_sha.hardware_type()
+            .expect("Unable to build ARP packets for unknown hardware types");
+        let ptype = param_spa.protocol_type()
+            .expect("Unable to build ARP packets for unknown protocol types");
+        let hlen = param_sha.as_bytes().len();
+        let plen = param_spa.as_bytes().len();
+        assert_eq!(param_tha.as_bytes().len(), hlen);
+        assert_eq!(param_tpa.as_bytes().len(), plen);
+
+        let (buffer, _excess) = buffer.split_at_mut(8 + 2 * (hlen + plen));
+        buffer[0..2].copy_from_slice(&htype.to_be_bytes());
+        buffer[2..4].copy_from_slice(&ptype.to_be_bytes());
+        buffer[4] = hlen as u8;
+        buffer[5] = plen as u8;
 
         let oper_bytes = match oper {
             ArpOperation::REQUEST => [0, 1],
@@ -73,26 +100,32 @@ impl<'a> ArpPacket<'a> {
         };
         buffer[6..8].copy_from_slice(&oper_bytes);
 
-        let mut arp_packet: ArpPacket = buffer.into();
-
-        let HardwareAddress::MAC(ref param_sha) = param_sha;
-        let HardwareAddress::MAC(ref param_tha) = param_tha;
-        let ProtocolAddress::IPv4(ref param_spa) = param_spa;
-        let ProtocolAddress::IPv4(ref param_tpa) = param_tpa;
+        let mut offset = 8;
+        for address in [
+            param_sha.as_bytes(), param_spa.as_bytes(),
+            param_tha.as_bytes(), param_tpa.as_bytes(),
+        ].iter() {
+            buffer[offset..offset + address.len()].copy_from_slice(address);
+            offset += address.len();
+        }
 
-        let HardwareAddress::MAC(ref mut response_sha) = arp_packet.sha();
-        response_sha.set_address(&param_sha.get_address());
+        ArpPacket::try_from(buffer).expect("ARP packet was just written")
+    }
 
-        let ProtocolAddress::IPv4(ref mut response_spa) = arp_packet.spa();
-        response_spa.set_address(&param_spa.get_address());
+    pub fn hardware_type(&self) -> u16 {
+        self.header[0..2].as_ref().read_u16::<BigEndian>().unwrap()
+    }
 
-        let HardwareAddress::MAC(ref mut response_tha) = arp_packet.tha();
-        response_tha.set_address(&param_tha.get_address());
+    pub fn protocol_type(&self) -> u16 {
+        self.header[2..4].as_ref().read_u16::<BigEndian>().unwrap()
+    }
 
-        let ProtocolAddress::IPv4(ref mut response_tpa) = arp_packet.tpa();
-        response_tpa.set_address(&param_tpa.get_address());
+    pub fn hardware_length(&self) -> u8 {
+        self.header[4]
+    }
 
-        arp_packet
+    pub fn protocol_length(&self) -> u8 {
+        self.header[5]
     }
 
     pub fn oper(&self) -> ArpOperation {
@@ -124,6 +157,11 @@ impl<'a> ArpPacket<'a> {
         &mut self.tpa
     }
 
+    /// Number of bytes the packet occupies
+    pub fn size(&self) -> usize {
+        8 + 2 * (self.header[4] as usize + self.header[5] as usize)
+    }
+
     pub fn fmt_header(&self) -> (String, String, String) {
 
         let hardware = match self.header[0..2] {
@@ -132,7 +170,7 @@ impl<'a> ArpPacket<'a> {
         };
         let protocol = match self.header[2..4] {
             [0x08, 0x00] => String::from("IPv4"),
-            _ => format!("Unknown hardware {:?}", &self.header[2..4]),
+            _ => format!("Unknown protocol {:?}", &self.header[2..4]),
         };
 
         (
@@ -1,10 +1,17 @@
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::mem::take;
+use crate::address::EthernetAddress;
+use crate::error::ParseError;
 use crate::protocols::arp::*;
 use crate::protocols::ipv4::Ipv4Packet;
 
+pub const HEADER_LEN: usize = 14;
+
+// Destination MAC, source MAC, ethertype and payload
+type FrameParts<'a> = (MacAddress<'a>, MacAddress<'a>, &'a mut [u8], &'a mut [u8]);
+
 // Ethernet frame
 
 #[derive(Debug)]
@@ -17,52 +24,38 @@ pub struct EthernetFrame<'a> {
 
 impl<'a> EthernetFrame<'a> {
 
-    fn generate_payload(ethertype: u16, bytes: &mut [u8]) -> Payload{
-        match EtherType::from_u16(ethertype) {
-            EtherType::IPv4 => Payload::IPv4(bytes.into()),
-            EtherType::ARP => Payload::ARP(bytes.into()),
+    fn generate_payload(ethertype: u16, bytes: &mut [u8]) -> Result<Payload<'_>, ParseError> {
+        Ok(match EtherType::from_u16(ethertype) {
+            EtherType::IPv4 => Payload::IPv4(bytes.try_into()?),
+            EtherType::ARP => Payload::ARP(bytes.try_into()?),
             EtherType::IPv6 => Payload::IPv6,
             _ => Payload::Unknown(UnknownPayload { ethertype, bytes }),
-        }
+        })
     }
 
-    fn split_buffer(frame: &mut [u8]) -> [&mut[u8]; 4] {
+    fn split_buffer(frame: &mut [u8]) -> Result<FrameParts<'_>, ParseError> {
+        if frame.len() < HEADER_LEN {
+            return Err(ParseError::Truncated);
+        }
+
         let (destination_mac_bytes, rest) = frame.split_at_mut(6);
         let (source_mac_bytes, rest) = rest.split_at_mut(6);
         let (ethertype_bytes, payload_bytes) = rest.split_at_mut(2);
 
-        [destination_mac_bytes, source_mac_bytes, ethertype_bytes, payload_bytes]
+        Ok((destination_mac_bytes.try_into()?, source_mac_bytes.try_into()?, ethertype_bytes, payload_bytes))
     }
 
-    pub fn from_slice(frame: &mut [u8]) -> EthernetFrame {
-        let [
-            destination_mac_bytes,
-            source_mac_bytes,
+    pub fn uninitialized(frame: &mut [u8]) -> EthernetFrame<'_> {
+        let (
+            destination_mac,
+            source_mac,
             ethertype_bytes,
             payload_bytes
-        ] = EthernetFrame::split_buffer(frame);
-
-        let ethertype = ethertype_slice_to_u16(ethertype_bytes);
+        ) = EthernetFrame::split_buffer(frame).expect("Buffer too small for an Ethernet header");
 
         EthernetFrame {
-            source_mac: source_mac_bytes.into(),
-            destination_mac: destination_mac_bytes.into(),
-            ethertype_bytes,
-            payload: EthernetFrame::generate_payload(ethertype, payload_bytes),
-        }
-    }
-
-    pub fn uninitialized(frame: &mut [u8]) -> EthernetFrame {
-        let [
-        destination_mac_bytes,
-        source_mac_bytes,
-        ethertype_bytes,
-        payload_bytes
-        ] = EthernetFrame::split_buffer(frame);
-
-        EthernetFrame {
-            source_mac: source_mac_bytes.into(),
-            destination_mac: destination_mac_bytes.into(),
+            source_mac,
+            destination_mac,
             ethertype_bytes,
             payload: Payload::Uninitialized(payload_bytes),
         }
@@ -99,6 +92,33 @@ impl<'a> EthernetFrame<'a> {
 
         self.payload = payload;
     }
+
+    /// Number of bytes the frame occupies, including the header
+    pub fn size(&self) -> usize {
+        HEADER_LEN + self.payload.size()
+    }
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for EthernetFrame<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        let (
+            destination_mac,
+            source_mac,
+            ethertype_bytes,
+            payload_bytes
+        ) = EthernetFrame::split_buffer(frame)?;
+
+        let ethertype = ethertype_slice_to_u16(ethertype_bytes);
+
+        Ok(EthernetFrame {
+            source_mac,
+            destination_mac,
+            ethertype_bytes,
+            payload: EthernetFrame::generate_payload(ethertype, payload_bytes)?,
+        })
+    }
 }
 
 pub enum EtherType {
@@ -119,29 +139,36 @@ impl EtherType {
     }
 }
 
-impl Into<u16> for EtherType {
-    fn into(self) -> u16 {
-        self as u16
+impl From<EtherType> for u16 {
+    fn from(ethertype: EtherType) -> u16 {
+        ethertype as u16
     }
 }
 
-fn ethertype_slice_to_u16 (ethertype: &[u8]) -> u16 {
-    ethertype.clone().read_u16::<NetworkEndian>().unwrap()
+fn ethertype_slice_to_u16 (mut ethertype: &[u8]) -> u16 {
+    ethertype.read_u16::<NetworkEndian>().unwrap()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum Payload<'a> {
     ARP(ArpPacket<'a>),
     IPv4(Ipv4Packet<'a>),
     IPv6,
     Unknown(UnknownPayload<'a>),
     Uninitialized(&'a mut [u8]),
+    #[default]
     None,
 }
 
-impl Default for Payload<'_> {
-    fn default() -> Self {
-        Payload::None
+impl Payload<'_> {
+    /// Number of bytes written to the payload
+    pub fn size(&self) -> usize {
+        match self {
+            Payload::ARP(arp_packet) => arp_packet.size(),
+            Payload::IPv4(ipv4_packet) => ipv4_packet.size(),
+            Payload::Unknown(unknown) => unknown.bytes.len(),
+            Payload::IPv6 | Payload::Uninitialized(_) | Payload::None => 0,
+        }
     }
 }
 
@@ -151,6 +178,16 @@ pub struct UnknownPayload<'a> {
     bytes: &'a mut [u8],
 }
 
+impl<'a> UnknownPayload<'a> {
+    pub fn ethertype(&self) -> u16 {
+        self.ethertype
+    }
+
+    pub fn bytes(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+}
+
 // MAC address
 
 #[derive(PartialEq)]
@@ -158,9 +195,17 @@ pub struct MacAddress<'a> {
     mac: &'a mut [u8; 6],
 }
 
-impl<'a> From<&'a mut [u8]> for MacAddress<'a> {
-    fn from(slice: &'a mut [u8]) -> MacAddress {
-        MacAddress { mac: slice.try_into().unwrap() }
+impl<'a> From<&'a mut [u8; 6]> for MacAddress<'a> {
+    fn from(mac: &'a mut [u8; 6]) -> MacAddress<'a> {
+        MacAddress { mac }
+    }
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for MacAddress<'a> {
+    type Error = ParseError;
+
+    fn try_from(slice: &'a mut [u8]) -> Result<Self, Self::Error> {
+        Ok(MacAddress { mac: slice.try_into().map_err(|_| ParseError::BadAddressLength)? })
     }
 }
 
@@ -172,14 +217,14 @@ impl<'a> MacAddress<'a> {
     pub fn set_address(&mut self, new_address: &[u8; 6]) {
         self.mac.copy_from_slice(new_address);
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.mac
+    }
 }
 
 impl fmt::Debug for MacAddress<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_fmt(format_args!(
-            "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}",
-            self.mac[0], self.mac[1], self.mac[2],
-            self.mac[3], self.mac[4], self.mac[5]
-        ))
+        fmt::Display::fmt(&EthernetAddress(*self.mac), f)
     }
 }
\ No newline at end of file
@@ -0,0 +1,222 @@
+use std::convert::TryFrom;
+use std::fmt::Formatter;
+use std::mem::take;
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use internet_checksum::Checksum;
+use crate::error::ParseError;
+
+/// Checksum present bit in the first byte of the header
+pub const FLAG_CHECKSUM: u8 = 0x80;
+/// Key present bit (RFC 2890)
+pub const FLAG_KEY: u8 = 0x20;
+/// Sequence number present bit (RFC 2890)
+pub const FLAG_SEQUENCE: u8 = 0x10;
+/// Bits of the first byte that RFC 1701 gave meanings RFC 2784 dropped: routing present, strict source route
+/// and recursion control. Receivers have to discard packets with any of them set.
+const FLAGS_RFC_1701: u8 = 0x40 | 0x08 | 0x07;
+
+/// Protocol type of encapsulated IPv4 packets, same as its EtherType
+pub const PROTOCOL_IPV4: u16 = 0x0800;
+/// Protocol type of encapsulated Ethernet frames (Transparent Ethernet Bridging)
+pub const PROTOCOL_TEB: u16 = 0x6558;
+
+/// Generic Routing Encapsulation header (RFC 2784) with its optional fields, followed by the encapsulated packet
+pub struct GrePacket<'a> {
+    header: &'a mut [u8],
+    pub data: &'a mut [u8],
+}
+
+/// Length of the header with the optional fields the first byte says are present
+fn header_length(flags: u8) -> usize {
+    let optional = [FLAG_CHECKSUM, FLAG_KEY, FLAG_SEQUENCE].iter().filter(|&&flag| flags & flag != 0).count();
+    4 + 4 * optional
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for GrePacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 4 {
+            return Err(ParseError::Truncated);
+        }
+
+        let version = frame[1] & 0x07;
+        if version != 0 {
+            return Err(ParseError::BadVersion(version));
+        }
+        if frame[0] & FLAGS_RFC_1701 != 0 {
+            return Err(ParseError::BadFlags(frame[0]));
+        }
+
+        let header_length = header_length(frame[0]);
+        if frame.len() < header_length {
+            return Err(ParseError::Truncated);
+        }
+
+        let (header, data) = frame.split_at_mut(header_length);
+        Ok(GrePacket { header, data })
+    }
+}
+
+impl<'a> GrePacket<'a> {
+    /// Header with a key field if `key` is given, with the rest of the buffer as data
+    pub fn new(buffer: &'a mut [u8], key: Option<u32>) -> GrePacket<'a> {
+        let flags = if key.is_some() { FLAG_KEY } else { 0 };
+        // Zero out the header
+        for i in &mut buffer[0..header_length(flags)] { *i = 0; }
+        buffer[0] = flags;
+
+        let gre_packet = GrePacket::try_from(buffer).expect("Buffer too small for a GRE header");
+        if let Some(key) = key {
+            gre_packet.header[4..8].as_mut().write_u32::<NetworkEndian>(key).unwrap();
+        }
+        gre_packet
+    }
+
+    pub fn checksum_present(&self) -> bool {
+        self.header[0] & FLAG_CHECKSUM != 0
+    }
+
+    /// EtherType of the encapsulated packet
+    pub fn protocol_type(&self) -> u16 {
+        self.header[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_protocol_type(&mut self, protocol_type: u16) {
+        self.header[2..4].as_mut().write_u16::<NetworkEndian>(protocol_type).unwrap()
+    }
+
+    /// Offset of an optional field, the fields present before it each taking four bytes
+    fn field_offset(&self, flag: u8) -> Option<usize> {
+        if self.header[0] & flag == 0 {
+            return None;
+        }
+        let before = [FLAG_CHECKSUM, FLAG_KEY, FLAG_SEQUENCE].iter()
+            .take_while(|&&other| other != flag)
+            .filter(|&&other| self.header[0] & other != 0)
+            .count();
+        Some(4 + 4 * before)
+    }
+
+    pub fn key(&self) -> Option<u32> {
+        self.field_offset(FLAG_KEY)
+            .map(|offset| self.header[offset..offset + 4].as_ref().read_u32::<NetworkEndian>().unwrap())
+    }
+
+    pub fn sequence_number(&self) -> Option<u32> {
+        self.field_offset(FLAG_SEQUENCE)
+            .map(|offset| self.header[offset..offset + 4].as_ref().read_u32::<NetworkEndian>().unwrap())
+    }
+
+    /// Check the checksum over the header and data if there is one. Packets without one always pass.
+    pub fn verify_checksum(&self) -> bool {
+        if !self.checksum_present() {
+            return true;
+        }
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(self.header);
+        checksum.add_bytes(self.data);
+        checksum.checksum() == [0, 0]
+    }
+
+    /// Copy in the encapsulated packet and shrink the data to its size
+    pub fn set_data(&mut self, data: &[u8]) {
+        let buffer = take(&mut self.data);
+        let (used, _) = buffer.split_at_mut(data.len());
+        used.copy_from_slice(data);
+        self.data = used;
+    }
+
+    /// Number of bytes the packet occupies, including the header
+    pub fn size(&self) -> usize {
+        self.header.len() + self.data.len()
+    }
+
+    /// Copy of the packet as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(self.header);
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> std::fmt::Debug for GrePacket<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f
+            .debug_struct("GrePacket")
+            .field("checksum_present", &self.checksum_present())
+            .field("protocol_type", &format_args!("{:#06x}", self.protocol_type()))
+            .field("key", &self.key())
+            .field("sequence_number", &self.sequence_number())
+            .field("data", &format!("{} bytes of data", self.data.len()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_optional_fields_present() {
+        let mut bytes = [FLAG_CHECKSUM | FLAG_KEY | FLAG_SEQUENCE, 0, 0x65, 0x58, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 9, 1, 2, 3];
+        let gre_packet = GrePacket::try_from(&mut bytes[..]).unwrap();
+        assert!(gre_packet.checksum_present());
+        assert_eq!(gre_packet.protocol_type(), PROTOCOL_TEB);
+        assert_eq!(gre_packet.key(), Some(7));
+        assert_eq!(gre_packet.sequence_number(), Some(9));
+        assert_eq!(gre_packet.data, &[1, 2, 3]);
+
+        let mut bytes = [FLAG_SEQUENCE, 0, 0x08, 0x00, 0, 0, 0, 9];
+        let gre_packet = GrePacket::try_from(&mut bytes[..]).unwrap();
+        assert_eq!((gre_packet.key(), gre_packet.sequence_number()), (None, Some(9)));
+        assert!(gre_packet.data.is_empty());
+        assert!(gre_packet.verify_checksum());
+    }
+
+    #[test]
+    fn emits_headers_with_and_without_keys() {
+        let mut buffer = [0xFF; 16];
+        let mut gre_packet = GrePacket::new(&mut buffer, Some(0x01020304));
+        gre_packet.set_protocol_type(PROTOCOL_IPV4);
+        gre_packet.set_data(&[0xAA, 0xBB]);
+        assert_eq!(gre_packet.size(), 10);
+        assert_eq!(gre_packet.to_bytes(), vec![FLAG_KEY, 0, 0x08, 0x00, 1, 2, 3, 4, 0xAA, 0xBB]);
+
+        let mut buffer = [0xFF; 6];
+        let mut gre_packet = GrePacket::new(&mut buffer, None);
+        gre_packet.set_protocol_type(PROTOCOL_TEB);
+        gre_packet.set_data(&[0xAA]);
+        let mut bytes = gre_packet.to_bytes();
+        assert_eq!(bytes, vec![0, 0, 0x65, 0x58, 0xAA]);
+        assert_eq!(GrePacket::try_from(&mut bytes[..]).unwrap().key(), None);
+    }
+
+    #[test]
+    fn verifies_checksums() {
+        let mut bytes = [FLAG_CHECKSUM, 0, 0x08, 0x00, 0, 0, 0, 0, 1, 2, 3];
+        let sum = internet_checksum::checksum(&bytes);
+        bytes[4..6].copy_from_slice(&sum);
+        assert!(GrePacket::try_from(&mut bytes[..]).unwrap().verify_checksum());
+        bytes[10] ^= 0xFF;
+        assert!(!GrePacket::try_from(&mut bytes[..]).unwrap().verify_checksum());
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        assert_eq!(GrePacket::try_from(&mut [0, 0, 0x08][..]).unwrap_err(), ParseError::Truncated);
+        assert_eq!(GrePacket::try_from(&mut [FLAG_KEY, 0, 0x08, 0x00, 0, 0, 0][..]).unwrap_err(), ParseError::Truncated);
+    }
+
+    #[test]
+    fn rejects_other_versions_and_rfc_1701_flags() {
+        assert_eq!(GrePacket::try_from(&mut [0, 1, 0x08, 0x00][..]).unwrap_err(), ParseError::BadVersion(1));
+        for &flags in &[0x40, 0x08, 0x04, 0x01, FLAG_KEY | 0x40] {
+            let mut bytes = [flags, 0, 0x08, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+            assert_eq!(GrePacket::try_from(&mut bytes[..]).unwrap_err(), ParseError::BadFlags(flags));
+        }
+        // Bits 6 to 12 are reserved for future use and ignored
+        assert!(GrePacket::try_from(&mut [0, 0xF8, 0x08, 0x00][..]).is_ok());
+    }
+}
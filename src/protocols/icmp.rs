@@ -1,29 +1,81 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt::Formatter;
+use std::mem::take;
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use internet_checksum::Checksum;
+use crate::error::ParseError;
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum IcmpType {
     EchoRequest,
     EchoReply,
-    Unknown,
+    DestinationUnreachable,
+    TimeExceeded,
+    Unknown(u8),
 }
 
+impl From<u8> for IcmpType {
+    fn from(value: u8) -> IcmpType {
+        match value {
+            0x00 => IcmpType::EchoReply,
+            0x03 => IcmpType::DestinationUnreachable,
+            0x08 => IcmpType::EchoRequest,
+            0x0B => IcmpType::TimeExceeded,
+            _ => IcmpType::Unknown(value),
+        }
+    }
+}
+
+impl From<IcmpType> for u8 {
+    fn from(icmp_type: IcmpType) -> u8 {
+        match icmp_type {
+            IcmpType::EchoReply => 0x00,
+            IcmpType::DestinationUnreachable => 0x03,
+            IcmpType::EchoRequest => 0x08,
+            IcmpType::TimeExceeded => 0x0B,
+            IcmpType::Unknown(value) => value,
+        }
+    }
+}
+
+impl IcmpType {
+    /// Whether this type reports an error, which must never be answered with another error (RFC 1122 3.2.2)
+    pub fn is_error(&self) -> bool {
+        // Destination unreachable, source quench, redirect, time exceeded and parameter problem
+        matches!(u8::from(*self), 3 | 4 | 5 | 11 | 12)
+    }
+}
+
+/// Destination unreachable code when there is no route to the destination network
+pub const CODE_NET_UNREACHABLE: u8 = 0;
+/// Destination unreachable code when the destination host doesn't answer ARP
+pub const CODE_HOST_UNREACHABLE: u8 = 1;
+/// Destination unreachable code for datagrams that need fragmenting but have DF set
+pub const CODE_FRAGMENTATION_NEEDED: u8 = 4;
+/// Time exceeded code for datagrams whose TTL ran out in transit
+pub const CODE_TTL_EXCEEDED: u8 = 0;
+
 pub struct IcmpPacket<'a> {
     header0to3: &'a mut [u8; 4],
     header4to7: &'a mut [u8; 4],
     pub data: &'a  mut [u8],
 }
 
-impl<'a> From <&'a mut [u8]> for IcmpPacket<'a> {
-    fn from(frame: &'a mut [u8]) -> IcmpPacket {
+impl<'a> TryFrom<&'a mut [u8]> for IcmpPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 8 {
+            return Err(ParseError::Truncated);
+        }
+
         let  (header0to3, rest) = frame.split_at_mut(4);
         let  (header4to7, data) = rest.split_at_mut(4);
-        IcmpPacket {
-            header0to3: header0to3.try_into().unwrap(),
-            header4to7: header4to7.try_into().unwrap(),
+        Ok(IcmpPacket {
+            header0to3: header0to3.try_into().map_err(|_| ParseError::Truncated)?,
+            header4to7: header4to7.try_into().map_err(|_| ParseError::Truncated)?,
             data
-        }
+        })
     }
 }
 
@@ -33,21 +85,25 @@ impl<'a> IcmpPacket<'a> {
     ) -> IcmpPacket<'a> {
         // Zero out the header
         for i in &mut buffer[0..8] { *i = 0; }
-        buffer.into()
+        IcmpPacket::try_from(buffer).expect("Buffer too small for an ICMP header")
     }
 
     pub fn icmp_type(&self) -> IcmpType {
-        match self.header0to3[0] {
-            0x00 => IcmpType::EchoReply,
-            0x08 => IcmpType::EchoRequest,
-            _ => IcmpType::Unknown,
-        }
+        self.header0to3[0].into()
+    }
+
+    pub fn set_icmp_type(&mut self, icmp_type: IcmpType) {
+        self.header0to3[0] = icmp_type.into();
     }
 
     pub fn icmp_code(&self) -> u8 {
         self.header0to3[1]
     }
 
+    pub fn set_icmp_code(&mut self, code: u8) {
+        self.header0to3[1] = code;
+    }
+
     pub fn checksum(&self) -> u16 {
         self.header0to3[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
     }
@@ -66,6 +122,15 @@ impl<'a> IcmpPacket<'a> {
         println!("Checksum set to: {:x}", self.checksum());
     }
 
+    /// Check the checksum of a received packet
+    pub fn verify_checksum(&self) -> bool {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(self.header0to3);
+        checksum.add_bytes(self.header4to7);
+        checksum.add_bytes(self.data);
+        checksum.checksum() == [0, 0]
+    }
+
     pub fn rest_of_header(&self) -> [u8; 4] {
         self.header4to7.as_ref().try_into().unwrap()
     }
@@ -74,6 +139,29 @@ impl<'a> IcmpPacket<'a> {
         self.header4to7.copy_from_slice(data);
     }
 
+    /// Copy `data` into the packet and shrink the packet to end right after it.
+    /// Panics if the packet buffer is too small.
+    pub fn set_data(&mut self, data: &[u8]) {
+        let buffer = take(&mut self.data);
+        let (buffer, _excess) = buffer.split_at_mut(data.len());
+        buffer.copy_from_slice(data);
+        self.data = buffer;
+    }
+
+    /// Number of bytes the packet occupies, including the header
+    pub fn size(&self) -> usize {
+        8 + self.data.len()
+    }
+
+    /// Copy of the packet as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(self.header0to3);
+        bytes.extend_from_slice(self.header4to7);
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+
     /*pub fn data(&'a mut self) -> &'a mut [u8] {
         self.data
     }*/
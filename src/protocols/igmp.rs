@@ -0,0 +1,223 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Formatter;
+use std::mem::take;
+use std::time::Duration;
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use internet_checksum::Checksum;
+use crate::error::ParseError;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum IgmpType {
+    MembershipQuery,
+    V1MembershipReport,
+    V2MembershipReport,
+    V2LeaveGroup,
+    V3MembershipReport,
+    Unknown(u8),
+}
+
+impl From<u8> for IgmpType {
+    fn from(value: u8) -> IgmpType {
+        match value {
+            0x11 => IgmpType::MembershipQuery,
+            0x12 => IgmpType::V1MembershipReport,
+            0x16 => IgmpType::V2MembershipReport,
+            0x17 => IgmpType::V2LeaveGroup,
+            0x22 => IgmpType::V3MembershipReport,
+            _ => IgmpType::Unknown(value),
+        }
+    }
+}
+
+impl From<IgmpType> for u8 {
+    fn from(igmp_type: IgmpType) -> u8 {
+        match igmp_type {
+            IgmpType::MembershipQuery => 0x11,
+            IgmpType::V1MembershipReport => 0x12,
+            IgmpType::V2MembershipReport => 0x16,
+            IgmpType::V2LeaveGroup => 0x17,
+            IgmpType::V3MembershipReport => 0x22,
+            IgmpType::Unknown(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum IgmpVersion {
+    V1,
+    V2,
+    V3,
+}
+
+/// IGMPv3 group record type reporting that we receive from all sources (RFC 3376 4.2.12)
+pub const RECORD_MODE_IS_EXCLUDE: u8 = 2;
+/// IGMPv3 group record type for leaving a group
+pub const RECORD_CHANGE_TO_INCLUDE_MODE: u8 = 3;
+/// IGMPv3 group record type for joining a group
+pub const RECORD_CHANGE_TO_EXCLUDE_MODE: u8 = 4;
+
+/// IGMPv3 group record for `group` without any sources
+pub fn group_record(record_type: u8, group: &[u8; 4]) -> [u8; 8] {
+    let mut record = [0; 8];
+    record[0] = record_type;
+    record[4..8].copy_from_slice(group);
+    record
+}
+
+pub struct IgmpPacket<'a> {
+    header0to3: &'a mut [u8; 4],
+    /// Group address, or the number of group records of an IGMPv3 report
+    header4to7: &'a mut [u8; 4],
+    /// IGMPv3 query fields and sources, or the group records of an IGMPv3 report
+    pub data: &'a mut [u8],
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for IgmpPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 8 {
+            return Err(ParseError::Truncated);
+        }
+
+        let (header0to3, rest) = frame.split_at_mut(4);
+        let (header4to7, data) = rest.split_at_mut(4);
+        Ok(IgmpPacket {
+            header0to3: header0to3.try_into().map_err(|_| ParseError::Truncated)?,
+            header4to7: header4to7.try_into().map_err(|_| ParseError::Truncated)?,
+            data,
+        })
+    }
+}
+
+impl<'a> IgmpPacket<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> IgmpPacket<'a> {
+        // Zero out the header
+        for i in &mut buffer[0..8] { *i = 0; }
+        IgmpPacket::try_from(buffer).expect("Buffer too small for an IGMP header")
+    }
+
+    pub fn igmp_type(&self) -> IgmpType {
+        self.header0to3[0].into()
+    }
+
+    pub fn set_igmp_type(&mut self, igmp_type: IgmpType) {
+        self.header0to3[0] = igmp_type.into();
+    }
+
+    pub fn max_response_code(&self) -> u8 {
+        self.header0to3[1]
+    }
+
+    pub fn set_max_response_code(&mut self, code: u8) {
+        self.header0to3[1] = code;
+    }
+
+    /// Longest a query may be waited on before answering it.
+    /// Codes from 128 up hold a floating point value (RFC 3376 4.1.1).
+    pub fn max_response_time(&self) -> Duration {
+        let code = self.max_response_code();
+        let tenths = if code < 128 {
+            code as u64
+        } else {
+            let mantissa = (code & 0x0F) as u64;
+            let exponent = ((code >> 4) & 0x07) as u64;
+            (mantissa | 0x10) << (exponent + 3)
+        };
+        Duration::from_millis(tenths * 100)
+    }
+
+    /// Version of a membership query, told apart by its length and maximum response code (RFC 3376 7.1).
+    /// `None` for other messages and queries of an invalid length.
+    pub fn query_version(&self) -> Option<IgmpVersion> {
+        if self.igmp_type() != IgmpType::MembershipQuery {
+            return None;
+        }
+        match self.data.len() {
+            0 if self.max_response_code() == 0 => Some(IgmpVersion::V1),
+            0 => Some(IgmpVersion::V2),
+            length if length >= 4 => Some(IgmpVersion::V3),
+            _ => None,
+        }
+    }
+
+    pub fn group(&self) -> [u8; 4] {
+        *self.header4to7
+    }
+
+    pub fn set_group(&mut self, group: &[u8; 4]) {
+        self.header4to7.copy_from_slice(group);
+    }
+
+    /// Number of group records in an IGMPv3 report
+    pub fn number_of_records(&self) -> u16 {
+        self.header4to7[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_number_of_records(&mut self, records: u16) {
+        self.header4to7[0..2].copy_from_slice(&[0, 0]);
+        self.header4to7[2..4].as_mut().write_u16::<NetworkEndian>(records).unwrap()
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.header0to3[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header0to3[2..4].as_mut().write_u16::<NetworkEndian>(checksum).unwrap()
+    }
+
+    pub fn calculate_checksum(&mut self) {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(&self.header0to3[0..2]);
+        checksum.add_bytes(&[0, 0]);
+        checksum.add_bytes(self.header4to7);
+        checksum.add_bytes(self.data);
+        self.header0to3[2..4].copy_from_slice(&checksum.checksum());
+    }
+
+    /// Check the checksum of a received packet
+    pub fn verify_checksum(&self) -> bool {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(self.header0to3);
+        checksum.add_bytes(self.header4to7);
+        checksum.add_bytes(self.data);
+        checksum.checksum() == [0, 0]
+    }
+
+    /// Copy `data` into the packet and shrink the packet to end right after it.
+    /// Panics if the packet buffer is too small.
+    pub fn set_data(&mut self, data: &[u8]) {
+        let buffer = take(&mut self.data);
+        let (buffer, _excess) = buffer.split_at_mut(data.len());
+        buffer.copy_from_slice(data);
+        self.data = buffer;
+    }
+
+    /// Number of bytes the packet occupies, including the header
+    pub fn size(&self) -> usize {
+        8 + self.data.len()
+    }
+
+    /// Copy of the packet as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(self.header0to3);
+        bytes.extend_from_slice(self.header4to7);
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> std::fmt::Debug for IgmpPacket<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f
+            .debug_struct("IgmpPacket")
+            .field("igmp_type", &self.igmp_type())
+            .field("max_response_code", &self.max_response_code())
+            .field("checksum", &format_args!("{:#06x}", self.checksum()))
+            .field("header4to7", &self.header4to7)
+            .field("data", &format!("{} bytes of data", self.data.len()))
+            .finish()
+    }
+}
@@ -1,10 +1,20 @@
-use std::convert::TryInto;
+pub mod options;
+
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use std::fmt::{Formatter, Debug};
+use crate::address;
+use crate::error::ParseError;
+use crate::protocols::adjust_checksum;
+use crate::protocols::gre::GrePacket;
 use crate::protocols::icmp::IcmpPacket;
+use crate::protocols::igmp::IgmpPacket;
+use crate::protocols::tcp::TcpPacket;
+use crate::protocols::udp::UdpPacket;
 use std::mem::take;
 use internet_checksum::Checksum;
+use options::{Ipv4Option, Ipv4Options};
 
 // Custom fmt::Debug
 #[derive(PartialEq)]
@@ -12,9 +22,17 @@ pub struct Ipv4Address<'a> {
     ip: &'a mut [u8; 4],
 }
 
-impl<'a> From<&'a mut [u8]> for Ipv4Address<'a> {
-    fn from(slice: &'a mut [u8]) -> Ipv4Address {
-        Ipv4Address { ip: slice.try_into().unwrap() }
+impl<'a> From<&'a mut [u8; 4]> for Ipv4Address<'a> {
+    fn from(ip: &'a mut [u8; 4]) -> Ipv4Address<'a> {
+        Ipv4Address { ip }
+    }
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for Ipv4Address<'a> {
+    type Error = ParseError;
+
+    fn try_from(slice: &'a mut [u8]) -> Result<Self, Self::Error> {
+        Ok(Ipv4Address { ip: slice.try_into().map_err(|_| ParseError::BadAddressLength)? })
     }
 }
 
@@ -26,36 +44,64 @@ impl<'a> Ipv4Address<'a> {
     pub fn set_address(&mut self, new_address: &[u8; 4]) {
         self.ip.copy_from_slice(new_address);
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.ip
+    }
 }
 
 impl fmt::Debug for Ipv4Address<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_fmt(format_args!(
-            "{}.{}.{}.{}",
-            self.ip[0], self.ip[1], self.ip[2], self.ip[3]
-        ))
+        fmt::Display::fmt(&address::Ipv4Address(*self.ip), f)
     }
 }
 
+/// Don't fragment bit in `Ipv4Header::flags`
+pub const FLAG_DONT_FRAGMENT: u8 = 0b010;
+/// More fragments bit in `Ipv4Header::flags`
+pub const FLAG_MORE_FRAGMENTS: u8 = 0b001;
+
 #[derive(Debug)]
 pub enum IpProtocol {
     ICMP = 0x01,
+    IGMP = 0x02,
+    /// IPv4 encapsulated in IPv4 (RFC 2003)
+    IPIP = 0x04,
     TCP = 0x06,
     UDP = 0x11,
+    GRE = 0x2F,
     UNKNOWN,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum IpPayload<'a> {
     ICMP(IcmpPacket<'a>),
+    IGMP(IgmpPacket<'a>),
+    TCP(TcpPacket<'a>),
+    UDP(UdpPacket<'a>),
+    GRE(GrePacket<'a>),
+    /// Encapsulated IPv4 packet, parsed once it is taken out
+    IPIP(&'a mut [u8]),
     Unknown(&'a mut [u8]),
+    /// Part of a fragmented datagram, only parseable once reassembled
+    Fragment(&'a mut [u8]),
     Uninitialized(&'a mut [u8]),
+    #[default]
     None,
 }
 
-impl Default for IpPayload<'_> {
-    fn default() -> Self {
-        IpPayload::None
+impl IpPayload<'_> {
+    /// Number of bytes written to the payload
+    pub fn size(&self) -> usize {
+        match self {
+            IpPayload::ICMP(icmp_packet) => icmp_packet.size(),
+            IpPayload::IGMP(igmp_packet) => igmp_packet.size(),
+            IpPayload::TCP(tcp_packet) => tcp_packet.size(),
+            IpPayload::UDP(udp_packet) => udp_packet.size(),
+            IpPayload::GRE(gre_packet) => gre_packet.size(),
+            IpPayload::IPIP(bytes) | IpPayload::Unknown(bytes) | IpPayload::Fragment(bytes) => bytes.len(),
+            IpPayload::Uninitialized(_) | IpPayload::None => 0,
+        }
     }
 }
 
@@ -64,22 +110,55 @@ pub struct Ipv4Packet<'a> {
     payload: IpPayload<'a>,
 }
 
-impl<'a> From <&'a mut [u8]> for Ipv4Packet<'a> {
-    fn from(frame: &'a mut [u8]) -> Ipv4Packet {
+impl<'a> TryFrom<&'a mut [u8]> for Ipv4Packet<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 20 {
+            return Err(ParseError::Truncated);
+        }
+
+        let version = frame[0] >> 4;
+        if version != 4 {
+            return Err(ParseError::BadVersion(version));
+        }
+
         let ihl = frame[0] & 0x0F;
+        let header_length = ihl as usize * 4;
+        if ihl < 5 {
+            return Err(ParseError::BadIhl(ihl));
+        }
+        if frame.len() < header_length {
+            return Err(ParseError::Truncated);
+        }
 
-        let (header_bytes, payload_bytes) =
-            frame.split_at_mut((ihl * 4) as usize);
+        let length = frame[2..4].as_ref().read_u16::<NetworkEndian>().unwrap();
+        if (length as usize) < header_length {
+            return Err(ParseError::BadLength(length));
+        }
+        if (length as usize) > frame.len() {
+            return Err(ParseError::Truncated);
+        }
+
+        // Anything past the total length is link layer padding
+        let (frame, _padding) = frame.split_at_mut(length as usize);
+        let (header_bytes, payload_bytes) = frame.split_at_mut(header_length);
 
-        let header: Ipv4Header = header_bytes.into();
+        let header = Ipv4Header::try_from(header_bytes)?;
 
-        Ipv4Packet {
+        Ok(Ipv4Packet {
             payload: match &header.protocol() {
-                IpProtocol::ICMP => IpPayload::ICMP(payload_bytes.into()),
+                _ if header.is_fragment() => IpPayload::Fragment(payload_bytes),
+                IpProtocol::ICMP => IpPayload::ICMP(payload_bytes.try_into()?),
+                IpProtocol::IGMP => IpPayload::IGMP(payload_bytes.try_into()?),
+                IpProtocol::TCP => IpPayload::TCP(payload_bytes.try_into()?),
+                IpProtocol::UDP => IpPayload::UDP(payload_bytes.try_into()?),
+                IpProtocol::GRE => IpPayload::GRE(payload_bytes.try_into()?),
+                IpProtocol::IPIP => IpPayload::IPIP(payload_bytes),
                 _ => IpPayload::Unknown(payload_bytes),
             },
             header,
-        }
+        })
     }
 }
 
@@ -89,19 +168,35 @@ impl<'a> Ipv4Packet<'a> {
         source_ip: &Ipv4Address,
         destination_ip: &Ipv4Address,
     ) -> Ipv4Packet<'a> {
+        Ipv4Packet::new_with_options(buffer, source_ip, destination_ip, &[])
+    }
+
+    /// Like `new`, but writes `options` after the addresses and sets the IHL to match.
+    /// Panics if the options don't fit in the 40 bytes available.
+    pub fn new_with_options(
+        buffer: &'a mut [u8],
+        source_ip: &Ipv4Address,
+        destination_ip: &Ipv4Address,
+        options: &[Ipv4Option],
+    ) -> Ipv4Packet<'a> {
+        let options_length = options::padded_len(options);
+        assert!(options_length <= options::MAX_OPTIONS_LEN, "IPv4 options too long");
+
         // Zero out the header
         for i in &mut buffer[0..20] { *i = 0; }
+        options::emit(options, &mut buffer[20..]);
 
-        let ihl: usize = 5;
+        let ihl: usize = 5 + options_length / 4;
         let (buf_header, buf_payload) = buffer.split_at_mut(4*ihl);
 
+        // Version 4
+        buf_header[0] = 0x40 | ihl as u8;
+
         let mut ipv4_packet = Ipv4Packet {
-            header: buf_header.into(),
+            header: Ipv4Header::try_from(buf_header).expect("IPv4 header was just written"),
             payload: IpPayload::Uninitialized(buf_payload),
         };
 
-        ipv4_packet.header().set_version(4);
-        ipv4_packet.header().set_ihl(5);
         ipv4_packet.header().set_time_to_live(64);
         ipv4_packet.header().source_ip().set_address(&source_ip.get_address());
         ipv4_packet.header().destination_ip().set_address(&destination_ip.get_address());
@@ -126,19 +221,49 @@ impl<'a> Ipv4Packet<'a> {
         }
     }
 
+    /// Set the payload, updating the protocol and total length fields to match
     pub fn set_payload(&mut self, payload: IpPayload<'a>) {
         self.header.set_protocol(match payload {
             IpPayload::ICMP(_) => IpProtocol::ICMP as u8,
+            IpPayload::IGMP(_) => IpProtocol::IGMP as u8,
+            IpPayload::TCP(_) => IpProtocol::TCP as u8,
+            IpPayload::UDP(_) => IpProtocol::UDP as u8,
+            IpPayload::GRE(_) => IpProtocol::GRE as u8,
+            IpPayload::IPIP(_) => IpProtocol::IPIP as u8,
             _ => 0xFF,
         });
 
         self.payload = payload;
+        let size = self.size();
+        self.header.set_length(size as u16);
+    }
+
+    /// Number of bytes the packet occupies, including the header
+    pub fn size(&self) -> usize {
+        self.header.ihl() as usize * 4 + self.payload.size()
+    }
+
+    /// Copy of the packet as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_bytes();
+        match &self.payload {
+            IpPayload::ICMP(icmp_packet) => bytes.extend_from_slice(&icmp_packet.to_bytes()),
+            IpPayload::IGMP(igmp_packet) => bytes.extend_from_slice(&igmp_packet.to_bytes()),
+            IpPayload::TCP(tcp_packet) => bytes.extend_from_slice(&tcp_packet.to_bytes()),
+            IpPayload::UDP(udp_packet) => bytes.extend_from_slice(&udp_packet.to_bytes()),
+            IpPayload::GRE(gre_packet) => bytes.extend_from_slice(&gre_packet.to_bytes()),
+            IpPayload::IPIP(payload) | IpPayload::Unknown(payload) | IpPayload::Fragment(payload) => {
+                bytes.extend_from_slice(payload)
+            },
+            IpPayload::Uninitialized(_) | IpPayload::None => {},
+        }
+        bytes
     }
 }
 
 impl<'a> std::fmt::Debug for Ipv4Packet<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if let IpPayload::Unknown(bytes) =  &self.payload {
+        if let IpPayload::Unknown(bytes) | IpPayload::Fragment(bytes) = &self.payload {
             f
                 .debug_struct("Ipv4Packet")
                 .field("header", &self.header)
@@ -161,30 +286,34 @@ pub struct Ipv4Header<'a> {
     options: Option<&'a mut [u8]>
 }
 
-impl<'a> From<&'a mut [u8]> for Ipv4Header<'a> {
-    fn from(buffer: &'a mut [u8]) -> Self {
+impl<'a> TryFrom<&'a mut [u8]> for Ipv4Header<'a> {
+    type Error = ParseError;
+
+    fn try_from(buffer: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if buffer.len() < 20 {
+            return Err(ParseError::Truncated);
+        }
+
         let ihl = buffer[0] & 0x0F;
+        if ihl < 5 || ihl as usize * 4 != buffer.len() {
+            return Err(ParseError::BadIhl(ihl));
+        }
 
         let (header, rest) = buffer.split_at_mut(12);
         let (source_ip_bytes, rest) = rest.split_at_mut(4);
         let (destination_ip_bytes, rest) = rest.split_at_mut(4);
-        let options;
-        if ihl > 5 {
-            let (options_bytes, rest) =
-                rest.split_at_mut((ihl * 4 - 20) as usize);
-            options = Some(options_bytes);
-            assert_eq!(rest.len(), 0);
+        let options = if rest.is_empty() {
+            None
         } else {
-            options = None;
-            assert_eq!(rest.len(), 0);
-        }
+            Some(rest)
+        };
 
-        Ipv4Header {
-            header: header,
-            source_ip: source_ip_bytes.into(),
-            destination_ip: destination_ip_bytes.into(),
+        Ok(Ipv4Header {
+            header,
+            source_ip: source_ip_bytes.try_into()?,
+            destination_ip: destination_ip_bytes.try_into()?,
             options
-        }
+        })
     }
 }
 
@@ -197,6 +326,28 @@ impl<'a> Ipv4Header<'a> {
         &mut self.destination_ip
     }
 
+    pub fn options(&self) -> Option<&[u8]> {
+        self.options.as_deref()
+    }
+
+    /// Copy of the header as it appears on the wire, including the options
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_vec();
+        bytes.extend_from_slice(&self.source_ip.get_address());
+        bytes.extend_from_slice(&self.destination_ip.get_address());
+        bytes.extend_from_slice(self.options().unwrap_or(&[]));
+        bytes
+    }
+
+    pub fn options_mut(&mut self) -> Option<&mut [u8]> {
+        self.options.as_deref_mut()
+    }
+
+    /// Iterate over the parsed options
+    pub fn options_iter(&self) -> Ipv4Options<'_> {
+        Ipv4Options::new(self.options().unwrap_or(&[]))
+    }
+
     pub fn version(&self) -> u8 {
         (self.header[0] & 0xF0) >> 4
     }
@@ -218,14 +369,26 @@ impl<'a> Ipv4Header<'a> {
         self.header[0] = (current & 0xF0) | ihl;
     }
 
+    /// Differentiated services code point, the upper six bits of the second byte
     pub fn dscp(&self) -> u8 {
-        (self.header[1] & 0b11111100) >> 6
+        (self.header[1] & 0b11111100) >> 2
+    }
+
+    pub fn set_dscp(&mut self, dscp: u8) {
+        assert!(dscp <= 0b00111111);
+        self.header[1] = (self.header[1] & 0b00000011) | (dscp << 2);
     }
 
+    /// Explicit congestion notification, the lower two bits of the second byte
     pub fn ecn(&self) -> u8 {
         self.header[1] & 0b00000011
     }
 
+    pub fn set_ecn(&mut self, ecn: u8) {
+        assert!(ecn <= 0b00000011);
+        self.header[1] = (self.header[1] & 0b11111100) | ecn;
+    }
+
     pub fn length(&self) -> u16 {
         self.header[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
     }
@@ -238,14 +401,57 @@ impl<'a> Ipv4Header<'a> {
         self.header[4..6].as_ref().read_u16::<NetworkEndian>().unwrap()
     }
 
+    pub fn set_identification(&mut self, identification: u16) {
+        self.header[4..6].as_mut().write_u16::<NetworkEndian>(identification).unwrap();
+    }
+
+    /// The three flag bits: reserved, don't fragment and more fragments
     pub fn flags(&self) -> u8 {
         (self.header[6] & 0b11100000) >> 5
     }
 
+    pub fn set_flags(&mut self, flags: u8) {
+        assert!(flags <= 0b111);
+        self.header[6] = (self.header[6] & 0b00011111) | (flags << 5);
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.flags() & FLAG_DONT_FRAGMENT != 0
+    }
+
+    pub fn set_dont_fragment(&mut self, dont_fragment: bool) {
+        self.set_flag(FLAG_DONT_FRAGMENT, dont_fragment);
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.flags() & FLAG_MORE_FRAGMENTS != 0
+    }
+
+    pub fn set_more_fragments(&mut self, more_fragments: bool) {
+        self.set_flag(FLAG_MORE_FRAGMENTS, more_fragments);
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        let flags = if value { self.flags() | flag } else { self.flags() & !flag };
+        self.set_flags(flags);
+    }
+
+    /// Fragment offset in units of 8 bytes
     pub fn fragment_offset(&self) -> u16 {
         self.header[6..8].as_ref().read_u16::<NetworkEndian>().unwrap() & 0x1FFF
     }
 
+    pub fn set_fragment_offset(&mut self, offset: u16) {
+        assert!(offset <= 0x1FFF);
+        let flags = (self.header[6] as u16 & 0b11100000) << 8;
+        self.header[6..8].as_mut().write_u16::<NetworkEndian>(flags | offset).unwrap();
+    }
+
+    /// Whether this is a fragment of a larger datagram rather than a whole one
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+
     pub fn time_to_live(&self) -> u8 {
         self.header[8]
     }
@@ -254,15 +460,31 @@ impl<'a> Ipv4Header<'a> {
         self.header[8] = ttl;
     }
 
+    /// Decrement the TTL of a forwarded packet and patch the checksum to match (RFC 1624)
+    pub fn decrement_time_to_live(&mut self) {
+        let old_word = [self.header[8], self.header[9]];
+        self.header[8] = self.header[8].wrapping_sub(1);
+        let checksum = adjust_checksum(self.checksum(), &old_word, &self.header[8..10]);
+        self.set_checksum(checksum);
+    }
+
     pub fn protocol(&self) -> IpProtocol {
         match self.header[9] {
             0x01 => IpProtocol::ICMP,
+            0x02 => IpProtocol::IGMP,
             0x06 => IpProtocol::TCP,
             0x11 => IpProtocol::UDP,
+            0x04 => IpProtocol::IPIP,
+            0x2F => IpProtocol::GRE,
             _ => IpProtocol::UNKNOWN,
         }
     }
 
+    /// The raw protocol number, also for protocols `IpProtocol` doesn't know
+    pub fn protocol_number(&self) -> u8 {
+        self.header[9]
+    }
+
     pub fn set_protocol(&mut self, protocol: u8) {
         self.header[9] = protocol;
     }
@@ -271,21 +493,169 @@ impl<'a> Ipv4Header<'a> {
         self.header[10..12].as_ref().read_u16::<NetworkEndian>().unwrap()
     }
 
-    pub fn calculate_checksum(&mut self) {
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header[10..12].as_mut().write_u16::<NetworkEndian>(checksum).unwrap();
+    }
+
+    fn sum_header(&self) -> [u8; 2] {
         let mut checksum = Checksum::new();
         checksum.add_bytes(self.header);
         checksum.add_bytes(&self.source_ip.get_address());
         checksum.add_bytes(&self.destination_ip.get_address());
-        self.header[10..12].copy_from_slice(&checksum.checksum());
+        if let Some(options) = &self.options {
+            checksum.add_bytes(options);
+        }
+        checksum.checksum()
+    }
+
+    pub fn calculate_checksum(&mut self) {
+        self.header[10..12].copy_from_slice(&[0, 0]);
+        let checksum = self.sum_header();
+        self.header[10..12].copy_from_slice(&checksum);
         println!("IPv4 checksum set to: {:x}", self.checksum());
     }
+
+    /// Check the header checksum of a received packet
+    pub fn verify_checksum(&self) -> bool {
+        self.sum_header() == [0, 0]
+    }
 }
 
 impl<'a> std::fmt::Debug for Ipv4Header<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f
             .debug_struct("Ipv4Header")
-            .field("protocol", &self.protocol())
+            .field("version", &self.version())
+            .field("ihl", &self.ihl())
+            .field("dscp", &self.dscp())
+            .field("ecn", &self.ecn())
+            .field("length", &self.length())
+            .field("identification", &self.identification())
+            .field("dont_fragment", &self.dont_fragment())
+            .field("more_fragments", &self.more_fragments())
+            .field("fragment_offset", &self.fragment_offset())
+            .field("time_to_live", &self.time_to_live())
+            .field("protocol", &format_args!("{:?} ({})", self.protocol(), self.protocol_number()))
+            .field("checksum", &format_args!("{:#06x}", self.checksum()))
+            .field("source_ip", &self.source_ip)
+            .field("destination_ip", &self.destination_ip)
+            .field("options", &self.options_iter().collect::<Vec<_>>())
             .finish()
     }
-}
\ No newline at end of file
+}
+
+impl<'a> std::fmt::Display for Ipv4Header<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} > {:?} ver {} ihl {} proto {} ttl {} len {} id {:#06x} flags [{}{}] off {} dscp {} ecn {} cksum {:#06x}",
+            self.source_ip, self.destination_ip, self.version(), self.ihl(), self.protocol_number(), self.time_to_live(),
+            self.length(), self.identification(),
+            if self.dont_fragment() { "DF" } else { "" },
+            if self.more_fragments() { "MF" } else { "" },
+            self.fragment_offset() * 8, self.dscp(), self.ecn(), self.checksum(),
+        )?;
+        if let Some(options) = &self.options {
+            write!(f, " options {:02x?}", options)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of a UDP packet from 10.0.0.1 to 10.0.0.2 with a record route option, checksum left zero
+    fn header_bytes() -> [u8; 28] {
+        [
+            0x47, 0xB9, 0x00, 0x40, 0x12, 0x34, 0x40, 0x00, 64, 17, 0, 0,
+            10, 0, 0, 1, 10, 0, 0, 2,
+            7, 7, 4, 0, 0, 0, 0, 0,
+        ]
+    }
+
+    #[test]
+    fn reads_every_field() {
+        let mut bytes = header_bytes();
+        let header = Ipv4Header::try_from(&mut bytes[..]).unwrap();
+        assert_eq!((header.version(), header.ihl()), (4, 7));
+        // 0xB9 is DSCP 46 (expedited forwarding) with ECN 1
+        assert_eq!((header.dscp(), header.ecn()), (46, 1));
+        assert_eq!((header.length(), header.identification()), (64, 0x1234));
+        assert_eq!(header.flags(), FLAG_DONT_FRAGMENT);
+        assert!(header.dont_fragment() && !header.more_fragments() && !header.is_fragment());
+        assert_eq!(header.fragment_offset(), 0);
+        assert_eq!((header.time_to_live(), header.protocol_number()), (64, 17));
+        assert!(matches!(header.protocol(), IpProtocol::UDP));
+        assert_eq!(header.options(), Some(&[7, 7, 4, 0, 0, 0, 0, 0][..]));
+    }
+
+    #[test]
+    fn setters_only_change_their_own_bits() {
+        let mut bytes = header_bytes();
+        let mut header = Ipv4Header::try_from(&mut bytes[..]).unwrap();
+
+        header.set_dscp(10);
+        assert_eq!((header.dscp(), header.ecn()), (10, 1));
+        header.set_ecn(3);
+        assert_eq!((header.dscp(), header.ecn()), (10, 3));
+
+        header.set_more_fragments(true);
+        header.set_fragment_offset(0x1FFF);
+        assert!(header.dont_fragment() && header.more_fragments());
+        assert_eq!(header.fragment_offset(), 0x1FFF);
+        header.set_flags(0);
+        assert_eq!(header.fragment_offset(), 0x1FFF);
+        assert!(!header.dont_fragment() && !header.more_fragments());
+        header.set_dont_fragment(true);
+        header.set_fragment_offset(1);
+        assert_eq!((header.flags(), header.fragment_offset()), (FLAG_DONT_FRAGMENT, 1));
+        assert!(header.is_fragment());
+
+        header.set_version(6);
+        header.set_ihl(5);
+        assert_eq!((header.version(), header.ihl()), (6, 5));
+        header.set_identification(0xBEEF);
+        header.set_length(1500);
+        header.set_time_to_live(1);
+        header.set_protocol(6);
+        assert_eq!((header.identification(), header.length(), header.time_to_live()), (0xBEEF, 1500, 1));
+        assert!(matches!(header.protocol(), IpProtocol::TCP));
+        assert_eq!(bytes[1], (10 << 2) | 3);
+        assert_eq!(&bytes[6..8], &[0x40, 0x01]);
+    }
+
+    #[test]
+    fn checksums_survive_ttl_decrements() {
+        let mut bytes = header_bytes();
+        let mut header = Ipv4Header::try_from(&mut bytes[..]).unwrap();
+        assert!(!header.verify_checksum());
+        header.calculate_checksum();
+        assert!(header.verify_checksum());
+        header.decrement_time_to_live();
+        assert_eq!(header.time_to_live(), 63);
+        assert!(header.verify_checksum());
+    }
+
+    #[test]
+    fn formats_every_field_and_the_options() {
+        let mut bytes = header_bytes();
+        let mut header = Ipv4Header::try_from(&mut bytes[..]).unwrap();
+        header.set_checksum(0xABCD);
+        assert_eq!(
+            header.to_string(),
+            "10.0.0.1 > 10.0.0.2 ver 4 ihl 7 proto 17 ttl 64 len 64 id 0x1234 flags [DF] off 0 dscp 46 ecn 1 \
+             cksum 0xabcd options [07, 07, 04, 00, 00, 00, 00, 00]"
+        );
+
+        let debug = format!("{:?}", header);
+        for field in &[
+            "version: 4", "ihl: 7", "dscp: 46", "ecn: 1", "length: 64", "identification: 4660", "dont_fragment: true",
+            "more_fragments: false", "fragment_offset: 0", "time_to_live: 64", "protocol: UDP (17)", "checksum: 0xabcd",
+            "source_ip: 10.0.0.1", "destination_ip: 10.0.0.2", "options: [Ok(RecordRoute",
+        ] {
+            assert!(debug.contains(field), "{} missing from {}", field, debug);
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::ParseError;
+
+pub const OPTION_END: u8 = 0;
+pub const OPTION_NOP: u8 = 1;
+pub const OPTION_RECORD_ROUTE: u8 = 7;
+pub const OPTION_TIMESTAMP: u8 = 68;
+pub const OPTION_LOOSE_SOURCE_ROUTE: u8 = 131;
+pub const OPTION_STRICT_SOURCE_ROUTE: u8 = 137;
+pub const OPTION_ROUTER_ALERT: u8 = 148;
+
+/// Timestamp option flag: timestamps only
+pub const TIMESTAMP_ONLY: u8 = 0;
+/// Timestamp option flag: each timestamp is preceded by the address of the recording host
+pub const TIMESTAMP_WITH_ADDRESS: u8 = 1;
+/// Timestamp option flag: only the hosts whose addresses are prespecified record a timestamp
+pub const TIMESTAMP_PRESPECIFIED: u8 = 3;
+
+/// The options can use at most 40 bytes, as IHL tops out at 15 words
+pub const MAX_OPTIONS_LEN: usize = 40;
+
+/// A single IPv4 option (RFC 791).
+/// Pointers are kept as on the wire: the 1-based offset of the next free slot within the option.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Ipv4Option<'a> {
+    End,
+    NoOperation,
+    RecordRoute { pointer: u8, route: &'a [u8] },
+    Timestamp { pointer: u8, overflow: u8, flag: u8, data: &'a [u8] },
+    RouterAlert(u16),
+    LooseSourceRoute { pointer: u8, route: &'a [u8] },
+    StrictSourceRoute { pointer: u8, route: &'a [u8] },
+    Unknown { kind: u8, data: &'a [u8] },
+}
+
+impl<'a> Ipv4Option<'a> {
+    /// Parse the option at the start of `bytes`, returning it with the number of bytes it occupies
+    pub fn parse(bytes: &'a [u8]) -> Result<(Ipv4Option<'a>, usize), ParseError> {
+        let kind = match bytes.first() {
+            Some(kind) => *kind,
+            None => return Err(ParseError::Truncated),
+        };
+        match kind {
+            OPTION_END => return Ok((Ipv4Option::End, 1)),
+            OPTION_NOP => return Ok((Ipv4Option::NoOperation, 1)),
+            _ => {}
+        }
+
+        let length = *bytes.get(1).ok_or(ParseError::Truncated)? as usize;
+        if length < 2 {
+            return Err(ParseError::BadOption(kind));
+        }
+        if length > bytes.len() {
+            return Err(ParseError::Truncated);
+        }
+        let data = &bytes[2..length];
+
+        let option = match kind {
+            OPTION_RECORD_ROUTE | OPTION_LOOSE_SOURCE_ROUTE | OPTION_STRICT_SOURCE_ROUTE => {
+                if data.is_empty() || !(data.len() - 1).is_multiple_of(4) || data[0] < 4 {
+                    return Err(ParseError::BadOption(kind));
+                }
+                let (pointer, route) = (data[0], &data[1..]);
+                match kind {
+                    OPTION_RECORD_ROUTE => Ipv4Option::RecordRoute { pointer, route },
+                    OPTION_LOOSE_SOURCE_ROUTE => Ipv4Option::LooseSourceRoute { pointer, route },
+                    _ => Ipv4Option::StrictSourceRoute { pointer, route },
+                }
+            },
+            OPTION_TIMESTAMP => {
+                if data.len() < 2 || data[0] < 5 {
+                    return Err(ParseError::BadOption(kind));
+                }
+                Ipv4Option::Timestamp {
+                    pointer: data[0],
+                    overflow: data[1] >> 4,
+                    flag: data[1] & 0x0F,
+                    data: &data[2..],
+                }
+            },
+            OPTION_ROUTER_ALERT => {
+                if data.len() != 2 {
+                    return Err(ParseError::BadOption(kind));
+                }
+                Ipv4Option::RouterAlert(data.as_ref().read_u16::<NetworkEndian>().unwrap())
+            },
+            _ => Ipv4Option::Unknown { kind, data },
+        };
+
+        Ok((option, length))
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            Ipv4Option::End => OPTION_END,
+            Ipv4Option::NoOperation => OPTION_NOP,
+            Ipv4Option::RecordRoute { .. } => OPTION_RECORD_ROUTE,
+            Ipv4Option::Timestamp { .. } => OPTION_TIMESTAMP,
+            Ipv4Option::RouterAlert(_) => OPTION_ROUTER_ALERT,
+            Ipv4Option::LooseSourceRoute { .. } => OPTION_LOOSE_SOURCE_ROUTE,
+            Ipv4Option::StrictSourceRoute { .. } => OPTION_STRICT_SOURCE_ROUTE,
+            Ipv4Option::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Number of bytes the option occupies
+    pub fn size(&self) -> usize {
+        match self {
+            Ipv4Option::End | Ipv4Option::NoOperation => 1,
+            Ipv4Option::RecordRoute { route, .. }
+            | Ipv4Option::LooseSourceRoute { route, .. }
+            | Ipv4Option::StrictSourceRoute { route, .. } => 3 + route.len(),
+            Ipv4Option::Timestamp { data, .. } => 4 + data.len(),
+            Ipv4Option::RouterAlert(_) => 4,
+            Ipv4Option::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    /// Write the option to the start of `buffer`, returning the number of bytes written.
+    /// Panics if the buffer is too small.
+    pub fn emit(&self, buffer: &mut [u8]) -> usize {
+        let length = self.size();
+        let buffer = &mut buffer[..length];
+        buffer[0] = self.kind();
+        if length > 1 {
+            buffer[1] = length as u8;
+        }
+
+        match self {
+            Ipv4Option::End | Ipv4Option::NoOperation => {},
+            Ipv4Option::RecordRoute { pointer, route }
+            | Ipv4Option::LooseSourceRoute { pointer, route }
+            | Ipv4Option::StrictSourceRoute { pointer, route } => {
+                buffer[2] = *pointer;
+                buffer[3..].copy_from_slice(route);
+            },
+            Ipv4Option::Timestamp { pointer, overflow, flag, data } => {
+                buffer[2] = *pointer;
+                buffer[3] = (overflow << 4) | (flag & 0x0F);
+                buffer[4..].copy_from_slice(data);
+            },
+            Ipv4Option::RouterAlert(value) => {
+                buffer[2..4].as_mut().write_u16::<NetworkEndian>(*value).unwrap();
+            },
+            Ipv4Option::Unknown { data, .. } => buffer[2..].copy_from_slice(data),
+        }
+
+        length
+    }
+
+    /// Whether a host replying to a packet with this option should copy it into the reply (RFC 1122 3.2.2.6)
+    pub fn copied_to_reply(&self) -> bool {
+        matches!(self, Ipv4Option::RecordRoute { .. } | Ipv4Option::Timestamp { .. })
+    }
+}
+
+/// Iterator over the options of an IPv4 header. Stops after the End option or the first malformed option.
+pub struct Ipv4Options<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Ipv4Options<'a> {
+    pub fn new(bytes: &'a [u8]) -> Ipv4Options<'a> {
+        Ipv4Options { bytes }
+    }
+}
+
+impl<'a> Iterator for Ipv4Options<'a> {
+    type Item = Result<Ipv4Option<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        match Ipv4Option::parse(self.bytes) {
+            Ok((Ipv4Option::End, _)) => {
+                self.bytes = &[];
+                Some(Ok(Ipv4Option::End))
+            },
+            Ok((option, length)) => {
+                self.bytes = &self.bytes[length..];
+                Some(Ok(option))
+            },
+            Err(err) => {
+                self.bytes = &[];
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+/// Number of bytes the options take in a header, padded to a multiple of four
+pub fn padded_len(options: &[Ipv4Option]) -> usize {
+    let length: usize = options.iter().map(Ipv4Option::size).sum();
+    length.div_ceil(4) * 4
+}
+
+/// Write the options followed by End padding up to a multiple of four bytes.
+/// Returns the number of bytes written. Panics if the buffer is too small.
+pub fn emit(options: &[Ipv4Option], buffer: &mut [u8]) -> usize {
+    let length = padded_len(options);
+    let buffer = &mut buffer[..length];
+    let mut offset = 0;
+    for option in options {
+        offset += option.emit(&mut buffer[offset..]);
+    }
+    for byte in &mut buffer[offset..] {
+        *byte = OPTION_END;
+    }
+    length
+}
+
+/// Copy the options a reply should carry from `options` into `buffer`, returning the number of bytes written.
+/// Malformed options end the copy.
+pub fn copy_for_reply(options: &[u8], buffer: &mut [u8]) -> usize {
+    let mut offset = 0;
+    for option in Ipv4Options::new(options) {
+        match option {
+            Ok(option) if option.copied_to_reply() => offset += option.emit(&mut buffer[offset..]),
+            Ok(_) => {},
+            Err(_) => break,
+        }
+    }
+    offset
+}
+
+/// Reverse a source route the packet followed to its end, so the reply takes the same hops back (RFC 1122 3.2.1.8).
+/// The reversed option ends with `source`, the original sender. Writes it into `buffer` and returns its length
+/// with the first hop, which the reply has to be addressed to. `None` if there is no completed source route
+/// or the reversed one doesn't fit.
+pub fn reverse_source_route(options: &[u8], source: [u8; 4], buffer: &mut [u8]) -> Option<(usize, [u8; 4])> {
+    let (kind, route) = Ipv4Options::new(options)
+        .map_while(Result::ok)
+        .find_map(|option| match option {
+            Ipv4Option::LooseSourceRoute { pointer, route } | Ipv4Option::StrictSourceRoute { pointer, route }
+                if pointer as usize > route.len() + 3 => Some((option.kind(), route)),
+            _ => None,
+        })?;
+
+    // Each hop replaced its entry with the address it sent the packet on from, the last one being closest to us
+    let hops = route.chunks_exact(4).collect::<Vec<_>>();
+    let (first_hop, rest) = hops.split_last()?;
+    let length = 3 + 4 * hops.len();
+    if length > buffer.len() {
+        return None;
+    }
+
+    let option = &mut buffer[..length];
+    option[0] = kind;
+    option[1] = length as u8;
+    option[2] = 4;
+    for (slot, hop) in option[3..].chunks_exact_mut(4).zip(rest.iter().rev()) {
+        slot.copy_from_slice(hop);
+    }
+    option[length - 4..].copy_from_slice(&source);
+
+    let mut first = [0_u8; 4];
+    first.copy_from_slice(first_hop);
+    Some((length, first))
+}
+
+/// Record `address` in Record Route options and `timestamp` in Timestamp options, in place.
+/// Full timestamp options have their overflow counter incremented instead.
+pub fn record(options: &mut [u8], address: [u8; 4], timestamp: u32) {
+    let mut offset = 0;
+    while offset < options.len() {
+        let (kind, length) = match Ipv4Option::parse(&options[offset..]) {
+            Ok((Ipv4Option::End, _)) | Err(_) => return,
+            Ok((option, length)) => (option.kind(), length),
+        };
+        let option = &mut options[offset..offset + length];
+
+        match kind {
+            OPTION_RECORD_ROUTE => {
+                let pointer = option[2] as usize;
+                if pointer + 3 <= length {
+                    option[pointer - 1..pointer + 3].copy_from_slice(&address);
+                    option[2] += 4;
+                }
+            },
+            OPTION_TIMESTAMP => record_timestamp(option, address, timestamp),
+            _ => {},
+        }
+
+        offset += length;
+    }
+}
+
+fn record_timestamp(option: &mut [u8], address: [u8; 4], timestamp: u32) {
+    let pointer = option[2] as usize;
+    let flag = option[3] & 0x0F;
+    let entry_length = if flag == TIMESTAMP_ONLY { 4 } else { 8 };
+
+    if pointer + entry_length - 1 > option.len() {
+        let overflow = (option[3] >> 4).saturating_add(1).min(0x0F);
+        option[3] = (overflow << 4) | flag;
+        return;
+    }
+
+    let slot = &mut option[pointer - 1..pointer - 1 + entry_length];
+    match flag {
+        TIMESTAMP_ONLY => slot.as_mut().write_u32::<NetworkEndian>(timestamp).unwrap(),
+        TIMESTAMP_WITH_ADDRESS => {
+            slot[..4].copy_from_slice(&address);
+            slot[4..].as_mut().write_u32::<NetworkEndian>(timestamp).unwrap();
+        },
+        TIMESTAMP_PRESPECIFIED if slot[..4] == address => {
+            slot[4..].as_mut().write_u32::<NetworkEndian>(timestamp).unwrap();
+        },
+        _ => return,
+    }
+    option[2] += entry_length as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(bytes: &[u8]) -> Vec<Result<Ipv4Option<'_>, ParseError>> {
+        Ipv4Options::new(bytes).collect()
+    }
+
+    #[test]
+    fn emitted_options_parse_back() {
+        let route = [10, 0, 0, 1, 0, 0, 0, 0];
+        let timestamps = [0, 0, 0, 7, 0, 0, 0, 0];
+        let options = [
+            Ipv4Option::NoOperation,
+            Ipv4Option::RecordRoute { pointer: 8, route: &route },
+            Ipv4Option::Timestamp { pointer: 9, overflow: 2, flag: TIMESTAMP_ONLY, data: &timestamps },
+            Ipv4Option::RouterAlert(0),
+            Ipv4Option::LooseSourceRoute { pointer: 4, route: &route[..4] },
+            Ipv4Option::Unknown { kind: 0x99, data: &[1, 2] },
+        ];
+        let mut buffer = [0xAA; MAX_OPTIONS_LEN];
+        let length = emit(&options, &mut buffer);
+        assert_eq!(length, 40);
+        assert_eq!(length, padded_len(&options));
+
+        let parsed = parse_all(&buffer[..length]);
+        let expected = options.iter().cloned().map(Ok).chain(std::iter::once(Ok(Ipv4Option::End)));
+        assert!(parsed.into_iter().eq(expected));
+    }
+
+    #[test]
+    fn emit_pads_with_end_options() {
+        let mut buffer = [0xAA; 8];
+        assert_eq!(emit(&[Ipv4Option::NoOperation], &mut buffer), 4);
+        assert_eq!(&buffer[..4], &[OPTION_NOP, OPTION_END, OPTION_END, OPTION_END]);
+        assert_eq!(buffer[4], 0xAA);
+    }
+
+    #[test]
+    fn rejects_malformed_options() {
+        assert_eq!(Ipv4Option::parse(&[]), Err(ParseError::Truncated));
+        assert_eq!(Ipv4Option::parse(&[OPTION_RECORD_ROUTE]), Err(ParseError::Truncated));
+        assert_eq!(Ipv4Option::parse(&[OPTION_RECORD_ROUTE, 1]), Err(ParseError::BadOption(OPTION_RECORD_ROUTE)));
+        assert_eq!(Ipv4Option::parse(&[OPTION_RECORD_ROUTE, 7, 4, 0]), Err(ParseError::Truncated));
+        // Routes are whole addresses and the pointer can't point into the option's own header
+        assert_eq!(Ipv4Option::parse(&[OPTION_RECORD_ROUTE, 5, 4, 0, 0]), Err(ParseError::BadOption(OPTION_RECORD_ROUTE)));
+        assert_eq!(Ipv4Option::parse(&[OPTION_RECORD_ROUTE, 7, 3, 0, 0, 0, 0]), Err(ParseError::BadOption(OPTION_RECORD_ROUTE)));
+        assert_eq!(Ipv4Option::parse(&[OPTION_TIMESTAMP, 4, 4, 0]), Err(ParseError::BadOption(OPTION_TIMESTAMP)));
+        assert_eq!(Ipv4Option::parse(&[OPTION_ROUTER_ALERT, 3, 0]), Err(ParseError::BadOption(OPTION_ROUTER_ALERT)));
+
+        // The iterator stops at the first malformed option, and after End
+        let parsed = parse_all(&[OPTION_NOP, OPTION_ROUTER_ALERT, 3, 0, OPTION_NOP]);
+        assert_eq!(parsed, vec![Ok(Ipv4Option::NoOperation), Err(ParseError::BadOption(OPTION_ROUTER_ALERT))]);
+        let parsed = parse_all(&[OPTION_END, OPTION_NOP]);
+        assert_eq!(parsed, vec![Ok(Ipv4Option::End)]);
+    }
+
+    #[test]
+    fn copies_record_route_and_timestamp_to_replies() {
+        let options = [
+            OPTION_ROUTER_ALERT, 4, 0, 0,
+            OPTION_RECORD_ROUTE, 7, 4, 0, 0, 0, 0,
+            OPTION_NOP,
+            OPTION_TIMESTAMP, 8, 5, 0, 0, 0, 0, 0,
+        ];
+        let mut buffer = [0; MAX_OPTIONS_LEN];
+        let length = copy_for_reply(&options, &mut buffer);
+        assert_eq!(&buffer[..length], &options[4..11].iter().chain(&options[12..]).copied().collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn records_routes_until_full() {
+        let mut options = [OPTION_RECORD_ROUTE, 11, 4, 0, 0, 0, 0, 0, 0, 0, 0, OPTION_END];
+        record(&mut options, [10, 0, 0, 1], 0);
+        record(&mut options, [10, 0, 0, 2], 0);
+        record(&mut options, [10, 0, 0, 3], 0);
+        assert_eq!(options, [OPTION_RECORD_ROUTE, 11, 12, 10, 0, 0, 1, 10, 0, 0, 2, OPTION_END]);
+    }
+
+    #[test]
+    fn records_timestamps_by_flag() {
+        let mut only = [OPTION_TIMESTAMP, 8, 5, TIMESTAMP_ONLY, 0, 0, 0, 0];
+        record(&mut only, [10, 0, 0, 1], 0x01020304);
+        assert_eq!(only, [OPTION_TIMESTAMP, 8, 9, TIMESTAMP_ONLY, 1, 2, 3, 4]);
+        // Full, the overflow counter goes up instead
+        record(&mut only, [10, 0, 0, 1], 0x01020304);
+        assert_eq!(&only[2..4], &[9, 0x10 | TIMESTAMP_ONLY]);
+
+        let mut with_address = [OPTION_TIMESTAMP, 12, 5, TIMESTAMP_WITH_ADDRESS, 0, 0, 0, 0, 0, 0, 0, 0];
+        record(&mut with_address, [10, 0, 0, 1], 5);
+        assert_eq!(with_address, [OPTION_TIMESTAMP, 12, 13, TIMESTAMP_WITH_ADDRESS, 10, 0, 0, 1, 0, 0, 0, 5]);
+
+        // Only the prespecified hosts record, in order
+        let mut prespecified = [OPTION_TIMESTAMP, 12, 5, TIMESTAMP_PRESPECIFIED, 10, 0, 0, 2, 0, 0, 0, 0];
+        record(&mut prespecified, [10, 0, 0, 1], 5);
+        assert_eq!(prespecified[2], 5);
+        record(&mut prespecified, [10, 0, 0, 2], 5);
+        assert_eq!(prespecified, [OPTION_TIMESTAMP, 12, 13, TIMESTAMP_PRESPECIFIED, 10, 0, 0, 2, 0, 0, 0, 5]);
+    }
+
+    const SOURCE: [u8; 4] = [10, 9, 9, 9];
+
+    #[test]
+    fn reverses_completed_source_routes() {
+        // Went through 10.0.0.1, 10.0.1.1 and 10.0.2.1 in that order
+        let options = [OPTION_NOP, OPTION_LOOSE_SOURCE_ROUTE, 15, 16, 10, 0, 0, 1, 10, 0, 1, 1, 10, 0, 2, 1];
+        let mut buffer = [0; MAX_OPTIONS_LEN];
+        let (length, first_hop) = reverse_source_route(&options, SOURCE, &mut buffer).unwrap();
+        assert_eq!(first_hop, [10, 0, 2, 1]);
+        assert_eq!(&buffer[..length], &[OPTION_LOOSE_SOURCE_ROUTE, 15, 4, 10, 0, 1, 1, 10, 0, 0, 1, 10, 9, 9, 9]);
+
+        let options = [OPTION_STRICT_SOURCE_ROUTE, 7, 8, 10, 0, 0, 1, OPTION_END];
+        let (length, first_hop) = reverse_source_route(&options, SOURCE, &mut buffer).unwrap();
+        assert_eq!(first_hop, [10, 0, 0, 1]);
+        assert_eq!(&buffer[..length], &[OPTION_STRICT_SOURCE_ROUTE, 7, 4, 10, 9, 9, 9]);
+    }
+
+    #[test]
+    fn leaves_incomplete_source_routes_alone() {
+        let mut buffer = [0; MAX_OPTIONS_LEN];
+        let options = [OPTION_LOOSE_SOURCE_ROUTE, 11, 8, 10, 0, 0, 1, 10, 0, 1, 1, OPTION_END];
+        assert_eq!(reverse_source_route(&options, SOURCE, &mut buffer), None);
+        let options = [OPTION_RECORD_ROUTE, 7, 8, 10, 0, 0, 1, OPTION_END];
+        assert_eq!(reverse_source_route(&options, SOURCE, &mut buffer), None);
+        assert_eq!(buffer, [0; MAX_OPTIONS_LEN]);
+    }
+
+    #[test]
+    fn reversed_routes_have_to_fit_the_buffer() {
+        let options = [OPTION_LOOSE_SOURCE_ROUTE, 11, 12, 10, 0, 0, 1, 10, 0, 1, 1, OPTION_END];
+        assert_eq!(reverse_source_route(&options, SOURCE, &mut [0; 10]), None);
+        assert_eq!(reverse_source_route(&options, SOURCE, &mut [0; 11]).map(|(length, _)| length), Some(11));
+    }
+}
@@ -0,0 +1,182 @@
+use std::convert::TryFrom;
+use std::fmt::Formatter;
+use std::mem::take;
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use internet_checksum::Checksum;
+use crate::error::ParseError;
+use crate::protocols::pseudo_header;
+use crate::protocols::ipv4::IpProtocol;
+
+pub const FLAG_FIN: u8 = 0x01;
+pub const FLAG_SYN: u8 = 0x02;
+pub const FLAG_RST: u8 = 0x04;
+pub const FLAG_PSH: u8 = 0x08;
+pub const FLAG_ACK: u8 = 0x10;
+pub const FLAG_URG: u8 = 0x20;
+
+pub struct TcpPacket<'a> {
+    /// Fixed header followed by the options
+    header: &'a mut [u8],
+    pub data: &'a mut [u8],
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for TcpPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 20 {
+            return Err(ParseError::Truncated);
+        }
+
+        let data_offset = frame[12] >> 4;
+        if data_offset < 5 {
+            return Err(ParseError::BadDataOffset(data_offset));
+        }
+        if frame.len() < data_offset as usize * 4 {
+            return Err(ParseError::Truncated);
+        }
+
+        let (header, data) = frame.split_at_mut(data_offset as usize * 4);
+        Ok(TcpPacket { header, data })
+    }
+}
+
+impl<'a> TcpPacket<'a> {
+    /// Header without options, with the rest of the buffer as data
+    pub fn new(buffer: &'a mut [u8]) -> TcpPacket<'a> {
+        // Zero out the header
+        for i in &mut buffer[0..20] { *i = 0; }
+        buffer[12] = 5 << 4;
+        TcpPacket::try_from(buffer).expect("Buffer too small for a TCP header")
+    }
+
+    pub fn source_port(&self) -> u16 {
+        self.header[0..2].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_source_port(&mut self, port: u16) {
+        self.header[0..2].as_mut().write_u16::<NetworkEndian>(port).unwrap()
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        self.header[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_destination_port(&mut self, port: u16) {
+        self.header[2..4].as_mut().write_u16::<NetworkEndian>(port).unwrap()
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        self.header[4..8].as_ref().read_u32::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_sequence_number(&mut self, sequence_number: u32) {
+        self.header[4..8].as_mut().write_u32::<NetworkEndian>(sequence_number).unwrap()
+    }
+
+    pub fn acknowledgment_number(&self) -> u32 {
+        self.header[8..12].as_ref().read_u32::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_acknowledgment_number(&mut self, acknowledgment_number: u32) {
+        self.header[8..12].as_mut().write_u32::<NetworkEndian>(acknowledgment_number).unwrap()
+    }
+
+    /// Header length in 32 bit words
+    pub fn data_offset(&self) -> u8 {
+        self.header[12] >> 4
+    }
+
+    /// The `FLAG_*` bits set in the segment
+    pub fn flags(&self) -> u8 {
+        self.header[13]
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header[13] = flags;
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags() & flag != 0
+    }
+
+    /// Sequence space the segment occupies: its data plus one each for SYN and FIN
+    pub fn segment_length(&self) -> u32 {
+        self.data.len() as u32 + self.has_flag(FLAG_SYN) as u32 + self.has_flag(FLAG_FIN) as u32
+    }
+
+    pub fn window(&self) -> u16 {
+        self.header[14..16].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.header[14..16].as_mut().write_u16::<NetworkEndian>(window).unwrap()
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.header[16..18].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header[16..18].as_mut().write_u16::<NetworkEndian>(checksum).unwrap()
+    }
+
+    fn sum(&self, source: &[u8; 4], destination: &[u8; 4]) -> [u8; 2] {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(&pseudo_header(source, destination, IpProtocol::TCP as u8, self.size() as u16));
+        checksum.add_bytes(self.header);
+        checksum.add_bytes(self.data);
+        checksum.checksum()
+    }
+
+    /// Compute the checksum over the pseudo header of the addresses, the header and the data
+    pub fn calculate_checksum(&mut self, source: &[u8; 4], destination: &[u8; 4]) {
+        self.set_checksum(0);
+        let checksum = self.sum(source, destination);
+        self.header[16..18].copy_from_slice(&checksum);
+    }
+
+    /// Check the checksum of a received segment
+    pub fn verify_checksum(&self, source: &[u8; 4], destination: &[u8; 4]) -> bool {
+        self.sum(source, destination) == [0, 0]
+    }
+
+    /// Copy `data` into the segment and shrink the segment to end right after it.
+    /// Panics if the segment buffer is too small.
+    pub fn set_data(&mut self, data: &[u8]) {
+        let buffer = take(&mut self.data);
+        let (buffer, _excess) = buffer.split_at_mut(data.len());
+        buffer.copy_from_slice(data);
+        self.data = buffer;
+    }
+
+    /// Number of bytes the segment occupies, including the header
+    pub fn size(&self) -> usize {
+        self.header.len() + self.data.len()
+    }
+
+    /// Copy of the segment as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(self.header);
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> std::fmt::Debug for TcpPacket<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f
+            .debug_struct("TcpPacket")
+            .field("source_port", &self.source_port())
+            .field("destination_port", &self.destination_port())
+            .field("sequence_number", &self.sequence_number())
+            .field("acknowledgment_number", &self.acknowledgment_number())
+            .field("data_offset", &self.data_offset())
+            .field("flags", &format_args!("{:#04x}", self.flags()))
+            .field("window", &self.window())
+            .field("checksum", &format_args!("{:#06x}", self.checksum()))
+            .field("data", &format!("{} bytes of data", self.data.len()))
+            .finish()
+    }
+}
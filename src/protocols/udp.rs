@@ -0,0 +1,110 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Formatter;
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use internet_checksum::Checksum;
+use crate::error::ParseError;
+use crate::protocols::pseudo_header;
+use crate::protocols::ipv4::IpProtocol;
+
+pub struct UdpPacket<'a> {
+    header: &'a mut [u8; 8],
+    pub data: &'a mut [u8],
+}
+
+impl<'a> TryFrom<&'a mut [u8]> for UdpPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from(frame: &'a mut [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < 8 {
+            return Err(ParseError::Truncated);
+        }
+
+        let (header, data) = frame.split_at_mut(8);
+        Ok(UdpPacket {
+            header: header.try_into().map_err(|_| ParseError::Truncated)?,
+            data,
+        })
+    }
+}
+
+impl<'a> UdpPacket<'a> {
+    pub fn source_port(&self) -> u16 {
+        self.header[0..2].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_source_port(&mut self, port: u16) {
+        self.header[0..2].as_mut().write_u16::<NetworkEndian>(port).unwrap()
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        self.header[2..4].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_destination_port(&mut self, port: u16) {
+        self.header[2..4].as_mut().write_u16::<NetworkEndian>(port).unwrap()
+    }
+
+    pub fn length(&self) -> u16 {
+        self.header[4..6].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_length(&mut self, length: u16) {
+        self.header[4..6].as_mut().write_u16::<NetworkEndian>(length).unwrap()
+    }
+
+    /// Zero if the sender didn't compute a checksum
+    pub fn checksum(&self) -> u16 {
+        self.header[6..8].as_ref().read_u16::<NetworkEndian>().unwrap()
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header[6..8].as_mut().write_u16::<NetworkEndian>(checksum).unwrap()
+    }
+
+    fn sum(&self, source: &[u8; 4], destination: &[u8; 4]) -> [u8; 2] {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(&pseudo_header(source, destination, IpProtocol::UDP as u8, self.size() as u16));
+        checksum.add_bytes(self.header);
+        checksum.add_bytes(self.data);
+        checksum.checksum()
+    }
+
+    /// Compute the checksum over the pseudo header of the addresses, the header and the data
+    pub fn calculate_checksum(&mut self, source: &[u8; 4], destination: &[u8; 4]) {
+        self.set_checksum(0);
+        let checksum = u16::from_be_bytes(self.sum(source, destination));
+        // An all zero checksum means none was computed, so it is sent as all ones instead
+        self.set_checksum(if checksum == 0 { 0xFFFF } else { checksum });
+    }
+
+    /// Check the checksum of a received packet. Packets without one always pass.
+    pub fn verify_checksum(&self, source: &[u8; 4], destination: &[u8; 4]) -> bool {
+        self.checksum() == 0 || self.sum(source, destination) == [0, 0]
+    }
+
+    /// Number of bytes the packet occupies, including the header
+    pub fn size(&self) -> usize {
+        8 + self.data.len()
+    }
+
+    /// Copy of the packet as it appears on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(self.header);
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> std::fmt::Debug for UdpPacket<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f
+            .debug_struct("UdpPacket")
+            .field("source_port", &self.source_port())
+            .field("destination_port", &self.destination_port())
+            .field("length", &self.length())
+            .field("checksum", &format_args!("{:#06x}", self.checksum()))
+            .field("data", &format!("{} bytes of data", self.data.len()))
+            .finish()
+    }
+}